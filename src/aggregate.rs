@@ -0,0 +1,304 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Mutex},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::{
+    logging::STATUS_TO_STDERR,
+    output::{create_output, csv_escape, open_lines, read_record},
+    record::RecordView,
+    sink::Sink,
+    status,
+    writer::Records,
+};
+
+/// Added to a query's filename for the file its per-channel totals are written to by
+/// `search --aggregate`.
+pub const CHANNELS_SUFFIX: &str = ".channels.csv";
+
+const HEADER: &str = "channel_id,channel,videos,views,first_upload,last_upload";
+
+#[derive(Debug, clap::Args)]
+pub struct AggregateArgs {
+    /// The result files to total up, as written by the `raw` or `jsonl` output formats.
+    /// Files ending in `.zst` or `.gz` are decompressed.
+    #[clap(required = true)]
+    files: Vec<PathBuf>,
+    /// Where to write the totals, as CSV. Defaults to stdout.
+    #[clap(long = "output", short = 'o')]
+    output: Option<PathBuf>,
+}
+
+/// What's been seen of one channel.
+#[derive(Debug, Default)]
+struct ChannelTotals {
+    /// The channel's name, from the first record which gave one.
+    name: String,
+    videos: u64,
+    views: u64,
+    /// The earliest and latest `upload_date`, which as `YYYYMMDD` sort as text.
+    first_upload: Option<String>,
+    last_upload: Option<String>,
+}
+
+/// The number of records, total views and first and last upload dates of each channel in a
+/// set of records, keyed by their `channel_id`.
+#[derive(Debug, Default)]
+pub struct ChannelAggregates {
+    channels: HashMap<String, ChannelTotals>,
+    /// Records without a `channel_id`, or which aren't JSON objects.
+    skipped: u64,
+}
+
+impl ChannelAggregates {
+    pub fn add(&mut self, record: &RecordView) {
+        let inner = record.inner_record();
+        let record = inner.as_ref().unwrap_or(record);
+
+        let channel_id = record.field_text("channel_id");
+        if channel_id.is_empty() {
+            self.skipped += 1;
+            return;
+        }
+        let totals = self.channels.entry(channel_id.into_owned()).or_default();
+        totals.videos += 1;
+        totals.views += record
+            .field("view_count")
+            .and_then(|views| views.as_u64())
+            .unwrap_or(0);
+        if totals.name.is_empty() {
+            let name = record.field_text("channel");
+            totals.name = if name.is_empty() {
+                record.field_text("uploader").into_owned()
+            } else {
+                name.into_owned()
+            };
+        }
+        let date = record.field_text("upload_date");
+        if !date.is_empty() {
+            totals.add_dates(Some(&date), Some(&date));
+        }
+    }
+
+    /// The number of records which couldn't be put down to a channel.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Writes the totals as CSV, the channels with the most records first.
+    pub fn write_csv(&self, out: &mut impl Write) -> Result<()> {
+        let mut channels: Vec<_> = self.channels.iter().collect();
+        channels.sort_by(|(a_id, a), (b_id, b)| b.videos.cmp(&a.videos).then(a_id.cmp(b_id)));
+
+        writeln!(out, "{HEADER}")?;
+        for (id, totals) in channels {
+            // Each channel is kept to one line, so the file can be read back a line at a time.
+            let name = totals.name.replace(['\n', '\r'], " ");
+            writeln!(
+                out,
+                "{},{},{},{},{},{}",
+                csv_escape(id),
+                csv_escape(&name),
+                totals.videos,
+                totals.views,
+                totals.first_upload.as_deref().unwrap_or(""),
+                totals.last_upload.as_deref().unwrap_or(""),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reads back totals written by [`ChannelAggregates::write_csv`], to carry on adding to
+    /// them.
+    fn read_csv(reader: impl BufRead) -> Result<Self> {
+        let mut aggregates = Self::default();
+        let mut lines = reader.lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            bail!("not a file of per-channel totals");
+        }
+        for line in lines {
+            let line = line?;
+            let fields = split_csv(&line);
+            let [id, name, videos, views, first, last] = fields.as_slice() else {
+                bail!("expected 6 fields in `{line}`");
+            };
+            let totals = aggregates.channels.entry(id.clone()).or_default();
+            totals.name.clone_from(name);
+            totals.videos += videos.parse::<u64>()?;
+            totals.views += views.parse::<u64>()?;
+            totals.add_dates(
+                Some(first.as_str()).filter(|d| !d.is_empty()),
+                Some(last.as_str()).filter(|d| !d.is_empty()),
+            );
+        }
+        Ok(aggregates)
+    }
+}
+
+impl ChannelTotals {
+    fn add_dates(&mut self, first: Option<&str>, last: Option<&str>) {
+        if let Some(first) = first {
+            if self.first_upload.as_deref().is_none_or(|d| first < d) {
+                self.first_upload = Some(first.to_owned());
+            }
+        }
+        if let Some(last) = last {
+            if self.last_upload.as_deref().is_none_or(|d| last > d) {
+                self.last_upload = Some(last.to_owned());
+            }
+        }
+    }
+}
+
+/// Splits a line of CSV into its fields, undoing [`csv_escape`].
+pub(crate) fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().unwrap();
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+    fields
+}
+
+/// Totals up the channels of the records in the result files.
+pub fn aggregate(args: &AggregateArgs) -> Result<()> {
+    // The totals can be written to stdout, so keep it clear of status messages.
+    STATUS_TO_STDERR.store(args.output.is_none(), Ordering::Relaxed);
+    let mut aggregates = ChannelAggregates::default();
+    let mut line = String::new();
+    for path in &args.files {
+        let mut reader =
+            open_lines(path).with_context(|| anyhow!("Error opening {}", path.display()))?;
+        while read_record(&mut reader, &mut line)
+            .with_context(|| anyhow!("Error reading {}", path.display()))?
+        {
+            aggregates.add(&RecordView::new(line.trim_end()));
+        }
+    }
+    if aggregates.skipped() > 0 {
+        status!(
+            "Skipped {} records without a channel_id, or which aren't JSON",
+            aggregates.skipped()
+        );
+    }
+
+    match &args.output {
+        Some(path) => {
+            let mut out = create_output(path)?;
+            aggregates.write_csv(&mut out)?;
+            out.flush()
+                .with_context(|| anyhow!("Error writing {}", path.display()))?;
+        }
+        None => aggregates.write_csv(&mut std::io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+/// Totals up the channels of each query's matches as the search goes, for
+/// `search --aggregate`. The totals are written to `<query filename>.channels.csv` whenever
+/// the outputs are flushed, so that they stay in step with the progress recorded in the
+/// management file, and added to when resuming.
+pub struct ChannelTotalsSink {
+    paths: Vec<PathBuf>,
+    aggregates: Vec<Mutex<ChannelAggregates>>,
+}
+
+impl ChannelTotalsSink {
+    /// Starts the totals for the query files, carrying on from those already written if
+    /// `append` is set.
+    pub fn new(query_files: impl IntoIterator<Item = PathBuf>, append: bool) -> Result<Self> {
+        let mut paths = Vec::new();
+        let mut aggregates = Vec::new();
+        for path in query_files {
+            let mut path = path.into_os_string();
+            path.push(CHANNELS_SUFFIX);
+            let path = PathBuf::from(path);
+            let totals = if append && path.exists() {
+                load(&path)?
+            } else {
+                ChannelAggregates::default()
+            };
+            paths.push(path);
+            aggregates.push(Mutex::new(totals));
+        }
+        Ok(Self { paths, aggregates })
+    }
+
+    fn write(&self) -> Result<(), String> {
+        for (path, aggregates) in self.paths.iter().zip(&self.aggregates) {
+            let write = || -> Result<()> {
+                let mut temp_path = path.clone().into_os_string();
+                temp_path.push(".tmp");
+                let mut out = create_output(Path::new(&temp_path))?;
+                aggregates.lock().unwrap().write_csv(&mut out)?;
+                out.flush()?;
+                drop(out);
+                std::fs::rename(&temp_path, path)?;
+                Ok(())
+            };
+            write().map_err(|e| format!("Error writing {}: {e:#}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+fn load(path: &Path) -> Result<ChannelAggregates> {
+    let file =
+        std::fs::File::open(path).with_context(|| anyhow!("Error opening {}", path.display()))?;
+    ChannelAggregates::read_csv(BufReader::new(file))
+        .with_context(|| anyhow!("Error reading {}", path.display()))
+}
+
+impl Sink for ChannelTotalsSink {
+    fn wants_lines(&self) -> bool {
+        true
+    }
+
+    fn write_match(&self, query: usize, _record: &[u8], line: Option<&[u8]>) -> Result<(), String> {
+        if let Some(line) = line.and_then(|line| std::str::from_utf8(line).ok()) {
+            self.aggregates[query]
+                .lock()
+                .unwrap()
+                .add(&RecordView::new(line));
+        }
+        Ok(())
+    }
+
+    fn write_matches(
+        &self,
+        query: usize,
+        _records: &Records,
+        lines: &[&[u8]],
+    ) -> Result<(), String> {
+        // Taking the lock once for the batch, rather than for each match.
+        let mut aggregates = self.aggregates[query].lock().unwrap();
+        for line in lines {
+            if let Ok(line) = std::str::from_utf8(line) {
+                aggregates.add(&RecordView::new(line));
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        self.write()
+    }
+
+    fn finalize(self: Box<Self>) -> Result<(), String> {
+        self.write()
+    }
+}
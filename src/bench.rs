@@ -0,0 +1,182 @@
+use std::{
+    io,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use aho_corasick::AhoCorasick;
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use rayon::iter::{ParallelBridge, ParallelIterator};
+
+use crate::{
+    build_searchers, decode::DecodeOptions, input::Input, load_queries, parse_size,
+    pipeline::spawn_reader, pool::BufferPool, prefilter::Prefilter, search_line, source::Source,
+    thread_pool, Automaton, Query,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct BenchArgs {
+    #[clap(long = "query-json", short = 'q')]
+    query_json: PathBuf,
+    /// The sample file to search. It's decompressed if it's a zstd file.
+    #[clap(long = "input", short = 'i')]
+    input: PathBuf,
+    /// How many times to search the sample with each combination of settings.
+    #[clap(long = "passes", default_value_t = 3)]
+    passes: u32,
+    /// The automatons to compare, e.g. `nfa,dfa`.
+    #[clap(
+        long = "automaton",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "nfa"
+    )]
+    automatons: Vec<Automaton>,
+    /// The thread counts to compare, e.g. `1,4,8`. Defaults to one for each CPU core.
+    #[clap(long = "threads", short = 'j', value_delimiter = ',')]
+    threads: Vec<usize>,
+    /// The sizes of the chunks the sample is split into for the threads to search, as set
+    /// by `--parallel-chunk-size`, e.g. `1M,16M`.
+    #[clap(
+        long = "chunk-size",
+        value_delimiter = ',',
+        default_value = "16M",
+        value_parser = parse_size
+    )]
+    chunk_sizes: Vec<u64>,
+    /// The largest window the zstd decoder will accept, as for searching.
+    #[clap(long = "zstd-window-log", value_parser = clap::value_parser!(u32).range(10..=31))]
+    zstd_window_log: Option<u32>,
+}
+
+/// What was found in a pass over the sample.
+#[derive(Debug, Clone, Copy, Default)]
+struct Pass {
+    lines: u64,
+    bytes: u64,
+    matches: u64,
+}
+
+/// Decompresses and searches the sample once, without writing out the matches.
+fn search_pass(
+    input: &Input,
+    queries: &[Query],
+    searchers: &[AhoCorasick],
+    prefilter: &Prefilter,
+    pool: &rayon::ThreadPool,
+    chunk_size: u64,
+    decode_options: &DecodeOptions,
+) -> Result<Pass> {
+    let reader = input.lines(decode_options)?;
+    let buffers = BufferPool::new(pool.current_num_threads() * 3);
+    let pass = std::thread::scope(|scope| {
+        let threads = pool.current_num_threads();
+        let receiver = spawn_reader(scope, reader, chunk_size, 0, &buffers, threads);
+        pool.install(|| {
+            receiver
+                .into_iter()
+                .par_bridge()
+                .map(|chunk| {
+                    let chunk = chunk?;
+                    let mut does_match = vec![false; searchers.len()];
+                    let mut matches = 0;
+                    let text = chunk.text.as_ref();
+                    for (_, line) in prefilter.candidates(text) {
+                        search_line(line, searchers, &mut does_match);
+                        matches += does_match
+                            .iter()
+                            .zip(queries)
+                            .filter(|(m, q)| **m != q.invert)
+                            .count() as u64;
+                    }
+                    let pass = Pass {
+                        lines: chunk.lines,
+                        bytes: text.len() as u64,
+                        matches,
+                    };
+                    let mut text = chunk.text.into_buffer();
+                    text.clear();
+                    buffers.give(text);
+                    Ok::<_, io::Error>(pass)
+                })
+                .try_reduce(Pass::default, |a, b| {
+                    Ok(Pass {
+                        lines: a.lines + b.lines,
+                        bytes: a.bytes + b.bytes,
+                        matches: a.matches + b.matches,
+                    })
+                })
+        })
+    });
+    pass.with_context(|| anyhow!("Error reading {input}"))
+}
+
+fn display_size(size: u64) -> String {
+    ["T", "G", "M", "K"]
+        .iter()
+        .zip([40, 30, 20, 10])
+        .find(|(_, shift)| size >= 1 << shift && size.is_multiple_of(1 << shift))
+        .map_or_else(
+            || size.to_string(),
+            |(suffix, shift)| format!("{}{suffix}", size >> shift),
+        )
+}
+
+/// Searches the sample with each combination of the settings, printing how quickly it went.
+pub fn bench(args: &BenchArgs) -> Result<()> {
+    let (_, queries) = load_queries(&args.query_json)?;
+    let input = Input::File(args.input.clone());
+    let decode_options = DecodeOptions {
+        window_log_max: args.zstd_window_log,
+        ..DecodeOptions::default()
+    };
+    // Zero threads gives one for each core.
+    let threads = match args.threads.as_slice() {
+        [] => &[0][..],
+        threads => threads,
+    };
+
+    println!(
+        "{:<10} {:>7} {:>10} {:>12} {:>9} {:>10}",
+        "automaton", "threads", "chunk size", "lines/sec", "MB/sec", "matches"
+    );
+    for &automaton in &args.automatons {
+        let searchers = build_searchers(&queries, automaton);
+        let prefilter = Prefilter::new(&queries, automaton);
+        let name = automaton
+            .to_possible_value()
+            .map_or("", |value| value.get_name());
+        for &threads in threads {
+            let pool = thread_pool((threads > 0).then_some(threads))?;
+            for &chunk_size in &args.chunk_sizes {
+                let mut pass = Pass::default();
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..args.passes.max(1) {
+                    let started = Instant::now();
+                    pass = search_pass(
+                        &input,
+                        &queries,
+                        &searchers,
+                        &prefilter,
+                        &pool,
+                        chunk_size.max(1),
+                        &decode_options,
+                    )?;
+                    elapsed += started.elapsed();
+                }
+
+                let secs = elapsed.as_secs_f64() / f64::from(args.passes.max(1));
+                println!(
+                    "{name:<10} {:>7} {:>10} {:>12.0} {:>9.1} {:>10}",
+                    pool.current_num_threads(),
+                    display_size(chunk_size),
+                    pass.lines as f64 / secs,
+                    pass.bytes as f64 / secs / 1_000_000.0,
+                    pass.matches,
+                );
+            }
+        }
+    }
+    Ok(())
+}
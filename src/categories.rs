@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::record::RecordView;
+
+/// YouTube's video category IDs, and the names they're given in records' `categories`.
+const CATEGORIES: &[(u32, &str)] = &[
+    (1, "Film & Animation"),
+    (2, "Autos & Vehicles"),
+    (10, "Music"),
+    (15, "Pets & Animals"),
+    (17, "Sports"),
+    (18, "Short Movies"),
+    (19, "Travel & Events"),
+    (20, "Gaming"),
+    (21, "Videoblogging"),
+    (22, "People & Blogs"),
+    (23, "Comedy"),
+    (24, "Entertainment"),
+    (25, "News & Politics"),
+    (26, "Howto & Style"),
+    (27, "Education"),
+    (28, "Science & Technology"),
+    (29, "Nonprofits & Activism"),
+    (30, "Movies"),
+    (31, "Anime/Animation"),
+    (32, "Action/Adventure"),
+    (33, "Classics"),
+    (34, "Comedy"),
+    (35, "Documentary"),
+    (36, "Drama"),
+    (37, "Family"),
+    (38, "Foreign"),
+    (39, "Horror"),
+    (40, "Sci-Fi/Fantasy"),
+    (41, "Thriller"),
+    (42, "Shorts"),
+    (43, "Shows"),
+    (44, "Trailers"),
+];
+
+fn category_name(id: u32) -> Option<&'static str> {
+    CATEGORIES
+        .iter()
+        .find(|(category, _)| *category == id)
+        .map(|(_, name)| *name)
+}
+
+/// The categories a query is limited to, given by their IDs (e.g. `25`) or names (e.g.
+/// `News & Politics`). Records are checked by the names in their `categories`, or their
+/// `category_id`, and names are compared ignoring case.
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "Vec<String>")]
+pub struct CategoryFilter {
+    names: HashSet<String>,
+}
+
+impl TryFrom<Vec<String>> for CategoryFilter {
+    type Error = anyhow::Error;
+
+    fn try_from(categories: Vec<String>) -> Result<Self> {
+        let mut names = HashSet::new();
+        for category in categories {
+            let name = match category.trim().parse() {
+                Ok(id) => match category_name(id) {
+                    Some(name) => name.to_owned(),
+                    None => bail!("unknown category ID {id}"),
+                },
+                Err(_) => category.trim().to_owned(),
+            };
+            names.insert(name.to_lowercase());
+        }
+        Ok(Self { names })
+    }
+}
+
+impl CategoryFilter {
+    /// The categories, by name.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(String::as_str)
+    }
+
+    /// Whether the record is in any of the categories. Without any categories, every record
+    /// is.
+    pub fn contains(&self, record: &RecordView) -> bool {
+        if self.names.is_empty() {
+            return true;
+        }
+        let matches = |name: &str| self.names.contains(&name.to_lowercase());
+        match record.field("categories") {
+            Some(Value::Array(categories))
+                if categories.iter().filter_map(Value::as_str).any(matches) =>
+            {
+                return true;
+            }
+            Some(Value::String(category)) if matches(&category) => return true,
+            _ => {}
+        }
+
+        // As YouTube's API gives it, a number in a string.
+        let id = match record.field("category_id") {
+            Some(Value::Number(id)) => id.as_u64(),
+            Some(Value::String(id)) => id.parse().ok(),
+            _ => None,
+        };
+        id.and_then(|id| category_name(u32::try_from(id).ok()?))
+            .is_some_and(matches)
+    }
+}
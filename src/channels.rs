@@ -0,0 +1,52 @@
+use std::{collections::HashSet, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{aggregate::split_csv, record::RecordView};
+
+/// A set of channels to limit a query to, read from a `channel_list` file.
+///
+/// Text files have a channel ID on each line, skipping blank lines and those starting with
+/// `#`. CSV files (ending in `.csv`) start with a header, and the IDs are taken from their
+/// `channel_id` column, or the first column if there isn't one, so the totals written by
+/// `aggregate` can be used as a channel list.
+#[derive(Debug)]
+pub struct ChannelList {
+    ids: HashSet<String>,
+}
+
+impl ChannelList {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| anyhow!("Error reading channel list {}", path.display()))?;
+        let mut lines = text.lines();
+
+        let ids = if path.extension().is_some_and(|ext| ext == "csv") {
+            let column = lines
+                .next()
+                .and_then(|header| split_csv(header).iter().position(|c| c == "channel_id"))
+                .unwrap_or(0);
+            lines
+                .filter_map(|line| split_csv(line).into_iter().nth(column))
+                .map(|id| id.trim().to_owned())
+                .filter(|id| !id.is_empty())
+                .collect()
+        } else {
+            lines
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_owned)
+                .collect()
+        };
+        Ok(Self { ids })
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Whether the record's `channel_id` is one of the channels.
+    pub fn contains(&self, record: &RecordView) -> bool {
+        self.ids.contains(record.field_text("channel_id").as_ref())
+    }
+}
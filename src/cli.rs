@@ -0,0 +1,132 @@
+use std::{ffi::OsString, path::PathBuf};
+
+use anyhow::Result;
+use clap::{CommandFactory, Parser, Subcommand};
+
+use crate::{
+    aggregate, bench, config, histogram, index, logging::LogArgs, manage, merge, query, schema,
+    search, search::SearchArgs, serve, sort, stats, top, validate,
+};
+
+#[derive(Debug, Parser)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+    #[clap(flatten)]
+    log: LogArgs,
+    /// A JSON file mapping the fields of records from other generations of scrapers onto
+    /// those the queries, filters and output formats look up: other names for each field
+    /// (`aliases`), and fields to read as dates (`dates`) or numbers (`numbers`).
+    #[clap(long = "schema", env = "YTMS_SCHEMA", global = true)]
+    schema: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Search the input files for the queries' expressions, writing the matches of each query
+    /// to its own output file. This is also what runs if the arguments are given without a
+    /// subcommand, as they were before there were any.
+    Search(Box<SearchArgs>),
+    /// Check that the query file is valid and the input files can be found, and optionally
+    /// that they can be read all the way through, before starting a long run.
+    Validate(validate::ValidateArgs),
+    /// Summarize what was found in each file, from the statistics recorded in a management
+    /// file.
+    Stats(stats::StatsArgs),
+    /// Sort a query's output file by a field of its records.
+    #[clap(alias = "sort")]
+    SortOutput(sort::SortArgs),
+    /// Merge the query results from several output folders, dropping duplicate records.
+    #[clap(alias = "merge")]
+    MergeOutput(merge::MergeArgs),
+    /// Total up the matched records in result files by channel: how many each channel has,
+    /// their total views, and the first and last upload dates among them.
+    Aggregate(aggregate::AggregateArgs),
+    /// List the most frequent values of a field among the records in result files, such as
+    /// the uploaders, channels or tags with the most matches.
+    Top(top::TopArgs),
+    /// Count the records in result files by when they were uploaded, as a table with a row
+    /// for each day, month or year and a column for each file.
+    Histogram(histogram::HistogramArgs),
+    /// List or edit the progress recorded in a management file.
+    Manage(manage::ManageArgs),
+    /// Time repeated searches of a sample file with different settings, to find which are
+    /// fastest without doing a full run.
+    Bench(bench::BenchArgs),
+    /// Index the trigrams in each input file, so that searches given the same
+    /// `--index-folder` can skip the files which can't contain any of their expressions.
+    /// With `--inverted`, also index the tokens in each record for `query`.
+    Index(index::IndexArgs),
+    /// Find the records with a field containing any of the expressions, using the inverted
+    /// indexes built by `index --inverted` to only read the records which might match.
+    Query(query::QueryArgs),
+    /// Serve an HTTP API for submitting query sets to search the input folder for, following
+    /// their progress and fetching their results.
+    Serve(serve::ServeArgs),
+}
+
+/// Parses the command line and runs the command it gives, as the `ytmetasearch` binary does.
+pub fn run() -> Result<()> {
+    let cli = Cli::parse_from(command_line()?);
+    cli.log.apply();
+    if let Some(path) = &cli.schema {
+        schema::load(path)?;
+    }
+    match cli.command {
+        Command::Search(args) => search::search(*args),
+        Command::Validate(args) => validate::validate(&args),
+        Command::Stats(args) => stats::stats(&args),
+        Command::SortOutput(args) => sort::sort_output(&args),
+        Command::MergeOutput(args) => merge::merge_output(&args),
+        Command::Aggregate(args) => aggregate::aggregate(&args),
+        Command::Top(args) => top::top(&args),
+        Command::Histogram(args) => histogram::histogram(&args),
+        Command::Manage(args) => manage::manage(&args),
+        Command::Bench(args) => bench::bench(&args),
+        Command::Index(args) => index::index(&args),
+        Command::Query(args) => query::query(&args),
+        Command::Serve(args) => serve::serve(args),
+    }
+}
+
+/// The command line, with `search` put where the subcommand goes if there isn't one, so that
+/// the search arguments still work on their own, and the options from a `--config` file
+/// added to them.
+fn command_line() -> Result<Vec<OsString>> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let command = Cli::command();
+
+    // The global options can come before the subcommand.
+    let global = |arg: &str| {
+        let name = arg.split_once('=').map_or(arg, |(name, _)| name);
+        command
+            .get_arguments()
+            .filter(|a| a.is_global_set())
+            .find(|a| {
+                name.strip_prefix("--")
+                    .is_some_and(|long| a.get_long() == Some(long))
+                    || a.get_short()
+                        .is_some_and(|short| name == format!("-{short}"))
+            })
+            .map(|a| a.is_takes_value_set() && !arg.contains('='))
+    };
+    let mut i = 1;
+    while let Some(takes_value) = args.get(i).and_then(|a| a.to_str()).and_then(global) {
+        i += if takes_value { 2 } else { 1 };
+    }
+
+    let has_command = match args.get(i) {
+        Some(arg) => arg.to_str().is_none_or(|arg| {
+            matches!(arg, "help" | "-h" | "--help") || command.find_subcommand(arg).is_some()
+        }),
+        // A search can be given entirely by environment variables.
+        None => !std::env::vars_os().any(|(name, _)| name.to_string_lossy().starts_with("YTMS_")),
+    };
+    if !has_command {
+        args.insert(i.min(args.len()), "search".into());
+    }
+    if args.get(i).is_some_and(|arg| arg == "search") {
+        config::insert_options(&mut args, i + 1, &command)?;
+    }
+    Ok(args)
+}
@@ -0,0 +1,100 @@
+use std::{ffi::OsString, fs, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::{Arg, ValueSource};
+
+/// Finds the option a key in the config file gives, by its long name or one of its aliases.
+/// Underscores can stand in for hyphens.
+fn find_option<'a, 'help>(options: &'a [&'a Arg<'help>], key: &str) -> Option<&'a Arg<'help>> {
+    let key = key.replace('_', "-");
+    options.iter().copied().find(|option| {
+        option.get_long() == Some(key.as_str())
+            || option
+                .get_all_aliases()
+                .is_some_and(|aliases| aliases.contains(&key.as_str()))
+    })
+}
+
+/// Turns a value from the config file into the arguments which would give it on the command
+/// line.
+fn to_arguments(option: &Arg, value: &toml::Value, arguments: &mut Vec<OsString>) -> Result<()> {
+    let long = option
+        .get_long()
+        .expect("options are found by their long names");
+    let value = match value {
+        toml::Value::Array(values) => {
+            for value in values {
+                to_arguments(option, value, arguments)?;
+            }
+            return Ok(());
+        }
+        toml::Value::Boolean(set) if !option.is_takes_value_set() => {
+            if *set {
+                arguments.push(format!("--{long}").into());
+            }
+            return Ok(());
+        }
+        toml::Value::String(value) => value.clone(),
+        toml::Value::Integer(value) => value.to_string(),
+        toml::Value::Float(value) => value.to_string(),
+        toml::Value::Boolean(value) => value.to_string(),
+        _ => bail!("`{long}` must be a string, number, boolean or list of them"),
+    };
+    // Kept in one argument, so that values starting with `-` aren't taken for options.
+    arguments.push(format!("--{long}={value}").into());
+    Ok(())
+}
+
+/// Inserts the options from the `--config` file, if one was given, into the search
+/// arguments at `at`, leaving out those which were also given on the command line or by
+/// environment variables.
+pub fn insert_options(args: &mut Vec<OsString>, at: usize, command: &clap::Command) -> Result<()> {
+    // Errors, like missing options the file might give, are reported by the real parse.
+    let Ok(matches) = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(args.iter())
+    else {
+        return Ok(());
+    };
+    let Some(("search", matches)) = matches.subcommand() else {
+        return Ok(());
+    };
+    let Some(path) = matches.get_one::<PathBuf>("config") else {
+        return Ok(());
+    };
+
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Error reading config file {}", path.display()))?;
+    let table: toml::Table = toml::from_str(&text)
+        .with_context(|| format!("Error parsing config file {}", path.display()))?;
+
+    let search = command
+        .find_subcommand("search")
+        .expect("search is a subcommand");
+    let options: Vec<_> = search
+        .get_arguments()
+        .chain(command.get_arguments().filter(|a| a.is_global_set()))
+        .filter(|a| {
+            a.get_long()
+                .is_some_and(|long| !matches!(long, "config" | "help"))
+        })
+        .collect();
+
+    let mut arguments = Vec::new();
+    for (key, value) in &table {
+        let Some(option) = find_option(&options, key) else {
+            bail!("Unknown option `{key}` in config file {}", path.display());
+        };
+        if matches!(
+            matches.value_source(option.get_id()),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+        to_arguments(option, value, &mut arguments)
+            .with_context(|| format!("Error in config file {}", path.display()))?;
+    }
+    args.splice(at..at, arguments);
+    Ok(())
+}
@@ -0,0 +1,359 @@
+use std::io::{self, BufRead, Read};
+
+use zstd::{
+    stream::raw::{Decoder, InBuffer, Operation, OutBuffer},
+    zstd_safe::DParameter,
+};
+
+pub const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// Settings controlling how compressed inputs are decoded.
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// Skip to the next frame when a corrupt frame is found, instead of failing the input.
+    pub skip_corrupt_frames: bool,
+    /// Split multi-frame files into chunks of frames which can be searched in parallel.
+    pub split_frames: bool,
+    /// The minimum compressed size of each chunk when splitting files.
+    pub frame_chunk_size: u64,
+    /// Dictionary used for decompressing all inputs.
+    pub dictionary: Option<Vec<u8>>,
+    /// The largest window allowed, as a power of two, if not zstd's default.
+    pub window_log_max: Option<u32>,
+}
+
+impl DecodeOptions {
+    /// Creates a low-level decoder with these options applied.
+    pub fn raw_decoder(&self) -> io::Result<Decoder<'static>> {
+        let mut decoder = match &self.dictionary {
+            Some(dictionary) => Decoder::with_dictionary(dictionary)?,
+            None => Decoder::new()?,
+        };
+        if let Some(log) = self.window_log_max {
+            decoder.set_parameter(DParameter::WindowLogMax(log))?;
+        }
+        Ok(decoder)
+    }
+}
+
+/// Whether the error is from a frame needing a larger window than the decoder allows.
+fn is_window_error(error: &io::Error) -> bool {
+    error
+        .to_string()
+        .contains("Frame requires too much memory for decoding")
+}
+
+/// Adds the setting to change to errors from frames needing a larger window than allowed.
+pub fn explain_window_error(error: io::Error) -> io::Error {
+    if !is_window_error(&error) {
+        return error;
+    }
+    io::Error::new(
+        error.kind(),
+        format!(
+            "{error} (it was probably compressed with `--long`, so needs a larger \
+             `--zstd-window-log`, up to 31)"
+        ),
+    )
+}
+
+/// Passes reads through, explaining any errors from frames needing a larger window.
+pub struct ExplainWindowErrors<R>(pub R);
+
+impl<R: Read> Read for ExplainWindowErrors<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(explain_window_error)
+    }
+}
+
+/// A zstd decoder which, on hitting a corrupt frame, discards compressed data until it
+/// finds the start of the next frame and carries on from there.
+///
+/// Each frame's output is held back until the whole frame has been decoded and its checksum
+/// checked, so nothing from a corrupt frame is passed on. If the frame before the corrupt
+/// one ended part way through a line, the partial line is cut off with a newline so that it
+/// doesn't get glued onto the next frame, and the data up to the first newline in the next
+/// frame is dropped.
+pub struct RecoveringDecoder<R> {
+    reader: R,
+    decoder: Decoder<'static>,
+    name: String,
+    in_frame: bool,
+    /// Compressed bytes consumed from the reader.
+    position: u64,
+    /// Where the frame being decoded starts in the compressed stream.
+    frame_start: u64,
+    /// The frame being decoded, held back until it's been verified.
+    frame: Vec<u8>,
+    /// Verified output which hasn't been read yet, from `ready_pos` on.
+    ready: Vec<u8>,
+    ready_pos: usize,
+    /// Whether the last byte of verified output was a newline.
+    at_line_start: bool,
+    pending_newline: bool,
+    discard_partial_line: bool,
+    decompressed_bytes: u64,
+}
+
+/// How much is decoded at a time into the frame being held back.
+const DECODE_STEP: usize = 128 * 1024;
+
+impl<R: BufRead> RecoveringDecoder<R> {
+    pub fn new(reader: R, name: String, options: &DecodeOptions) -> io::Result<Self> {
+        Ok(Self {
+            reader,
+            decoder: options.raw_decoder()?,
+            name,
+            in_frame: false,
+            position: 0,
+            frame_start: 0,
+            frame: Vec::new(),
+            ready: Vec::new(),
+            ready_pos: 0,
+            at_line_start: true,
+            pending_newline: false,
+            discard_partial_line: false,
+            decompressed_bytes: 0,
+        })
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.reader.consume(len);
+        self.position += len as u64;
+    }
+
+    /// Consumes compressed bytes until the reader is positioned on a frame's magic number,
+    /// or the end of the stream. Returns the number of bytes skipped.
+    fn skip_to_next_frame(&mut self) -> io::Result<u64> {
+        let mut skipped = 0;
+        // zstd doesn't consume anything when the frame header is corrupt, leaving us on the
+        // magic number of the frame we're giving up on.
+        if self.position == self.frame_start && !self.reader.fill_buf()?.is_empty() {
+            self.consume(1);
+            skipped += 1;
+        }
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.len() < ZSTD_MAGIC.len() {
+                // Either the end of the stream, or a tail too short to hold the magic.
+                // Consume it and ask for more, giving up if there's nothing more to read.
+                let len = buf.len();
+                self.consume(len);
+                skipped += len as u64;
+                if len == 0 || self.reader.fill_buf()?.is_empty() {
+                    return Ok(skipped);
+                }
+                continue;
+            }
+
+            match buf.windows(ZSTD_MAGIC.len()).position(|w| w == ZSTD_MAGIC) {
+                Some(pos) => {
+                    self.consume(pos);
+                    return Ok(skipped + pos as u64);
+                }
+                None => {
+                    // Keep the last few bytes, in case the magic straddles the buffer boundary.
+                    let len = buf.len() - (ZSTD_MAGIC.len() - 1);
+                    self.consume(len);
+                    skipped += len as u64;
+                }
+            }
+        }
+    }
+
+    fn recover(&mut self, error: io::Error) -> io::Result<()> {
+        self.decoder.reinit()?;
+        self.frame.clear();
+        let skipped = self.skip_to_next_frame()?;
+        eprintln!(
+            "Corrupt frame in {} after {} decompressed bytes ({error}), dropped it and \
+             skipped {skipped} compressed bytes",
+            self.name, self.decompressed_bytes
+        );
+        self.in_frame = false;
+        self.frame_start = self.position;
+        self.pending_newline = !self.at_line_start;
+        self.discard_partial_line = !self.at_line_start;
+        Ok(())
+    }
+
+    /// Passes on the frame which has just been decoded.
+    fn release_frame(&mut self) {
+        let mut start = 0;
+        if self.discard_partial_line {
+            match self.frame.iter().position(|&b| b == b'\n') {
+                Some(pos) => {
+                    self.discard_partial_line = false;
+                    start = pos + 1;
+                }
+                None => start = self.frame.len(),
+            }
+        }
+        std::mem::swap(&mut self.ready, &mut self.frame);
+        self.frame.clear();
+        self.ready_pos = start;
+        self.decompressed_bytes += (self.ready.len() - start) as u64;
+        if let Some(&last) = self.ready[start..].last() {
+            self.at_line_start = last == b'\n';
+        }
+    }
+
+    /// Decodes until a whole frame is ready to be passed on, or the end of the stream.
+    /// Returns `false` at the end of the stream.
+    fn decode_frame(&mut self) -> io::Result<bool> {
+        loop {
+            let input = self.reader.fill_buf()?;
+            if input.is_empty() {
+                if self.in_frame {
+                    eprintln!(
+                        "Truncated final frame in {} after {} decompressed bytes",
+                        self.name, self.decompressed_bytes
+                    );
+                    self.in_frame = false;
+                    // What there is of it is passed on, as it was never found to be corrupt.
+                    self.release_frame();
+                    return Ok(true);
+                }
+                return Ok(false);
+            }
+
+            if !self.in_frame {
+                self.frame_start = self.position;
+            }
+            let filled = self.frame.len();
+            self.frame.resize(filled + DECODE_STEP, 0);
+            let mut in_buf = InBuffer::around(input);
+            let mut out_buf = OutBuffer::around(&mut self.frame[filled..]);
+            let result = self.decoder.run(&mut in_buf, &mut out_buf);
+            let consumed = in_buf.pos();
+            let written = out_buf.pos();
+            self.frame.truncate(filled + written);
+            self.consume(consumed);
+
+            match result {
+                Ok(0) => {
+                    self.in_frame = false;
+                    self.release_frame();
+                    return Ok(true);
+                }
+                Ok(_) => self.in_frame = true,
+                // The frame isn't corrupt, we just can't decode it, and nor will we be able to
+                // decode any others.
+                Err(e) if is_window_error(&e) => return Err(explain_window_error(e)),
+                Err(e) => self.recover(e)?,
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for RecoveringDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            if self.pending_newline {
+                self.pending_newline = false;
+                buf[0] = b'\n';
+                return Ok(1);
+            }
+
+            let ready = &self.ready[self.ready_pos..];
+            if !ready.is_empty() {
+                let len = ready.len().min(buf.len());
+                buf[..len].copy_from_slice(&ready[..len]);
+                self.ready_pos += len;
+                return Ok(len);
+            }
+
+            if !self.decode_frame()? {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// Compresses each of the texts as a frame of its own, with a checksum, returning the
+    /// stream along with where each frame starts.
+    fn frames(texts: &[&str]) -> (Vec<u8>, Vec<usize>) {
+        let mut stream = Vec::new();
+        let mut starts = Vec::new();
+        for text in texts {
+            starts.push(stream.len());
+            let mut encoder = zstd::Encoder::new(Vec::new(), 3).unwrap();
+            encoder.include_checksum(true).unwrap();
+            encoder.write_all(text.as_bytes()).unwrap();
+            stream.extend(encoder.finish().unwrap());
+        }
+        (stream, starts)
+    }
+
+    fn recover(stream: &[u8]) -> String {
+        let options = DecodeOptions::default();
+        let mut decoder = RecoveringDecoder::new(stream, "test".to_owned(), &options).unwrap();
+        let mut text = String::new();
+        decoder.read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn intact_frames_pass_through() {
+        let (stream, _) = frames(&["a1\na2\n", "b1\nb2\n", "c1\nc2\n"]);
+        assert_eq!(recover(&stream), "a1\na2\nb1\nb2\nc1\nc2\n");
+    }
+
+    #[test]
+    fn skips_frame_with_corrupt_header() {
+        let (mut stream, starts) = frames(&["a1\na2\n", "b1\nb2\n", "c1\nc2\n"]);
+        // Sets the reserved bit of the second frame's header descriptor.
+        stream[starts[1] + 4] |= 0x08;
+        assert_eq!(recover(&stream), "a1\na2\nc1\nc2\n");
+    }
+
+    #[test]
+    fn skips_corrupt_first_frame() {
+        let (mut stream, _) = frames(&["a1\na2\n", "b1\nb2\n"]);
+        stream[4] |= 0x08;
+        assert_eq!(recover(&stream), "b1\nb2\n");
+    }
+
+    #[test]
+    fn drops_whole_frame_failing_checksum() {
+        let (mut stream, starts) = frames(&["a1\na2\n", "b1\nb2\n", "c1\nc2\n"]);
+        // The last byte of the second frame is part of its checksum.
+        stream[starts[2] - 1] ^= 0xFF;
+        assert_eq!(recover(&stream), "a1\na2\nc1\nc2\n");
+    }
+
+    #[test]
+    fn drops_large_frame_failing_checksum() {
+        // Large enough to be decoded a piece at a time.
+        let large = "b\n".repeat(DECODE_STEP);
+        let (mut stream, starts) = frames(&["a1\n", &large, "c1\n"]);
+        stream[starts[2] - 1] ^= 0xFF;
+        assert_eq!(recover(&stream), "a1\nc1\n");
+    }
+
+    #[test]
+    fn cuts_off_line_split_by_corrupt_frame() {
+        let (mut stream, starts) = frames(&["a1\na2\npart", "ial\nb2\n", "c1\nc2\n"]);
+        stream[starts[1] + 4] |= 0x08;
+        // The partial line is ended where the corrupt frame was, and the rest of the line
+        // it was cut from, in the next frame, is dropped.
+        assert_eq!(recover(&stream), "a1\na2\npart\nc2\n");
+    }
+
+    #[test]
+    fn keeps_truncated_final_frame() {
+        let (stream, starts) = frames(&["a1\na2\n", "b1\nb2\n"]);
+        let text = recover(&stream[..starts[1] + (stream.len() - starts[1]) / 2]);
+        assert!(text.starts_with("a1\na2\n"), "{text:?}");
+    }
+}
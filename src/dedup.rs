@@ -0,0 +1,94 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::record::RecordView;
+
+/// Reads the record's `id` field, if it has one.
+pub fn record_id(line: &str) -> Option<Cow<'_, str>> {
+    RecordView::new(line).id()
+}
+
+/// Hashes the record's `id` field, if it has one.
+pub fn record_id_hash(record: &RecordView) -> Option<u64> {
+    Some(xxh3_64(record.id()?.as_bytes()))
+}
+
+/// The set of IDs already written to a query's output.
+///
+/// Only 64-bit hashes of the IDs are kept, to bound the memory use. These are also appended
+/// to a file next to the output so that the set survives between runs, but only once the
+/// records they're for have been flushed to the output: otherwise a run which crashed would
+/// leave out the records it never got to write when resumed.
+pub struct SeenIds {
+    seen: HashSet<u64>,
+    /// IDs seen since they were last saved.
+    pending: Vec<u64>,
+    file: BufWriter<File>,
+}
+
+impl SeenIds {
+    /// Loads the previously seen IDs from `path`, unless `reset` is set, in which case it's
+    /// emptied.
+    pub fn open(path: &Path, reset: bool) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(!reset)
+            .write(true)
+            .truncate(reset)
+            .open(path)
+            .with_context(|| anyhow!("Error opening seen IDs file {}", path.display()))?;
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)
+            .with_context(|| anyhow!("Error reading seen IDs file {}", path.display()))?;
+        // Any trailing partial hash was from an interrupted write, and gets ignored.
+        let seen = contents
+            .chunks_exact(8)
+            .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+
+        Ok(Self {
+            seen,
+            pending: Vec::new(),
+            file: BufWriter::new(file),
+        })
+    }
+
+    /// Records the ID, returning `false` if it's already been seen.
+    pub fn insert(&mut self, id_hash: u64) -> bool {
+        if !self.seen.insert(id_hash) {
+            return false;
+        }
+        self.pending.push(id_hash);
+        true
+    }
+
+    /// Takes the IDs seen since they were last saved, to be saved by [`SeenIds::save`] once
+    /// their records have been flushed.
+    pub fn take_pending(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Puts back IDs which couldn't be saved, to be tried again next time.
+    pub fn restore_pending(&mut self, mut ids: Vec<u64>) {
+        ids.append(&mut self.pending);
+        self.pending = ids;
+    }
+
+    /// Appends the IDs to the file.
+    pub fn save(&mut self, ids: &[u64]) -> io::Result<()> {
+        for id_hash in ids {
+            self.file.write_all(&id_hash.to_le_bytes())?;
+        }
+        self.file.flush()
+    }
+}
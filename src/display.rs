@@ -0,0 +1,309 @@
+use std::{
+    io::{self, IsTerminal, Read},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::input::Input;
+
+/// How often the bars are brought up to date.
+const TICK: Duration = Duration::from_millis(200);
+
+const OVERALL_TEMPLATE: &str =
+    "{elapsed_precise} [{wide_bar}] {binary_bytes}/{binary_total_bytes} ETA {eta} {msg}";
+const FILE_TEMPLATE: &str =
+    "  {wide_msg} [{bar:30}] {binary_bytes}/{binary_total_bytes} {binary_bytes_per_sec}";
+/// For inputs whose size isn't known, like URLs.
+const STREAM_TEMPLATE: &str = "  {wide_msg} {spinner} {binary_bytes} {binary_bytes_per_sec}";
+
+/// The progress bars, once they've been started.
+static DISPLAY: OnceLock<Display> = OnceLock::new();
+
+/// Progress bars showing how far through the run the search is: one for the whole run, and
+/// one for each file being searched. Progress is measured in compressed bytes read, as
+/// that's all that's known about the inputs up front, and the ETA follows from that.
+pub struct Display {
+    bars: MultiProgress,
+    overall: ProgressBar,
+    /// Whether the bars are drawn, rather than only kept track of.
+    shown: bool,
+    started: Instant,
+    /// Set at the end of the run, to stop updating the bars.
+    finished: AtomicBool,
+    files: AtomicU64,
+    files_done: AtomicU64,
+    /// The compressed size of the inputs to be searched.
+    total_bytes: AtomicU64,
+    /// The compressed size of the inputs which have been searched.
+    done_bytes: AtomicU64,
+    lines: AtomicU64,
+    /// Decompressed bytes searched.
+    bytes: AtomicU64,
+    /// Matches found for each query, by its index.
+    query_matches: Mutex<Vec<u64>>,
+    searching: Mutex<Vec<Arc<FileState>>>,
+}
+
+/// How far through a file the search is, updated by the thread reading it.
+struct FileState {
+    name: String,
+    bar: ProgressBar,
+    size: Option<u64>,
+    /// Compressed bytes read so far.
+    read: AtomicU64,
+}
+
+/// The bar of a file being searched. Dropping it takes the bar away and counts the file as
+/// done, whether or not it was searched all the way through.
+pub struct FileProgress {
+    display: &'static Display,
+    state: Arc<FileState>,
+}
+
+/// How far the run has got, for the dashboard to show.
+pub struct Snapshot {
+    pub elapsed: Duration,
+    pub files: u64,
+    pub files_done: u64,
+    /// Compressed bytes, as for the overall bar.
+    pub total_bytes: u64,
+    pub done_bytes: u64,
+    pub lines: u64,
+    /// Decompressed bytes searched.
+    pub bytes: u64,
+    pub query_matches: Vec<u64>,
+    pub searching: Vec<FileSnapshot>,
+}
+
+/// How far through a file being searched the search is.
+pub struct FileSnapshot {
+    pub name: String,
+    pub size: Option<u64>,
+    /// Compressed bytes read so far.
+    pub read: u64,
+}
+
+/// Counts the bytes read from a file's raw stream, for its bar.
+struct CountingReader {
+    inner: Box<dyn Read + Send>,
+    state: Arc<FileState>,
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .expect("the templates are valid")
+        .progress_chars("=> ")
+}
+
+fn subtract(counter: &AtomicU64, amount: u64) {
+    let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+        Some(value.saturating_sub(amount))
+    });
+}
+
+/// Starts showing the progress bars on stderr, if it's a terminal. Status messages are
+/// printed above the bars from then on.
+pub fn start() {
+    if io::stderr().is_terminal() {
+        init(ProgressDrawTarget::stderr(), true);
+    }
+}
+
+/// Starts keeping track of the progress without showing the bars, for the dashboard to
+/// show it instead.
+pub fn start_hidden() {
+    init(ProgressDrawTarget::hidden(), false);
+}
+
+fn init(target: ProgressDrawTarget, shown: bool) {
+    let bars = MultiProgress::with_draw_target(target);
+    let overall = bars.add(ProgressBar::new(0).with_style(style(OVERALL_TEMPLATE)));
+    let display = DISPLAY.get_or_init(|| Display {
+        bars,
+        overall,
+        shown,
+        started: Instant::now(),
+        finished: AtomicBool::new(false),
+        files: AtomicU64::new(0),
+        files_done: AtomicU64::new(0),
+        total_bytes: AtomicU64::new(0),
+        done_bytes: AtomicU64::new(0),
+        lines: AtomicU64::new(0),
+        bytes: AtomicU64::new(0),
+        query_matches: Mutex::new(Vec::new()),
+        searching: Mutex::new(Vec::new()),
+    });
+    std::thread::spawn(move || {
+        while !display.finished.load(Ordering::Relaxed) {
+            std::thread::sleep(TICK);
+            display.update();
+        }
+    });
+}
+
+/// The progress bars, if they're being shown.
+pub fn get() -> Option<&'static Display> {
+    DISPLAY.get()
+}
+
+/// The progress bars, if they're being drawn.
+pub fn shown() -> Option<&'static Display> {
+    get().filter(|display| display.shown)
+}
+
+/// Takes an input which isn't going to be searched after all off the totals.
+pub fn skip(input: &Input) {
+    if let Some(display) = get() {
+        subtract(&display.files, 1);
+        subtract(&display.total_bytes, input.source_size().unwrap_or(0));
+    }
+}
+
+/// Counts lines which have been searched, and their size, for the overall speed.
+pub fn add_searched(lines: u64, bytes: u64) {
+    if let Some(display) = get() {
+        display.lines.fetch_add(lines, Ordering::Relaxed);
+        display.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Counts matches for the query with the given index.
+pub fn add_matches(query: usize, matches: u64) {
+    if let Some(display) = get() {
+        let mut query_matches = display.query_matches.lock().unwrap();
+        if query_matches.len() <= query {
+            query_matches.resize(query + 1, 0);
+        }
+        query_matches[query] += matches;
+    }
+}
+
+impl Display {
+    /// Adds inputs to be searched to the totals.
+    pub fn add_inputs(&self, inputs: &[Input]) {
+        let size = inputs.iter().filter_map(Input::source_size).sum();
+        self.files.fetch_add(inputs.len() as u64, Ordering::Relaxed);
+        self.total_bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// Adds a bar for an input that's starting to be searched.
+    pub fn start_file(&'static self, input: &Input) -> FileProgress {
+        let size = input.source_size();
+        let bar = match size {
+            Some(size) => ProgressBar::new(size).with_style(style(FILE_TEMPLATE)),
+            None => ProgressBar::no_length().with_style(style(STREAM_TEMPLATE)),
+        };
+        let name = input.to_string();
+        let state = Arc::new(FileState {
+            bar: self.bars.add(bar.with_message(name.clone())),
+            name,
+            size,
+            read: AtomicU64::new(0),
+        });
+        self.searching.lock().unwrap().push(state.clone());
+        FileProgress {
+            display: self,
+            state,
+        }
+    }
+
+    /// Prints a message above the bars.
+    pub fn println(&self, message: String) {
+        let _ = self.bars.println(message);
+    }
+
+    /// Stops updating the bars, leaving the overall one showing where the run got to.
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+        self.update();
+        self.overall.abandon();
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let searching: Vec<_> = self
+            .searching
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|file| FileSnapshot {
+                name: file.name.clone(),
+                size: file.size,
+                read: file.read.load(Ordering::Relaxed),
+            })
+            .collect();
+        let done_bytes = self.done_bytes.load(Ordering::Relaxed)
+            + searching
+                .iter()
+                .map(|file| file.size.map_or(0, |size| file.read.min(size)))
+                .sum::<u64>();
+        Snapshot {
+            elapsed: self.started.elapsed(),
+            files: self.files.load(Ordering::Relaxed),
+            files_done: self.files_done.load(Ordering::Relaxed),
+            total_bytes: self.total_bytes.load(Ordering::Relaxed),
+            done_bytes,
+            lines: self.lines.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            query_matches: self.query_matches.lock().unwrap().clone(),
+            searching,
+        }
+    }
+
+    fn update(&self) {
+        let mut position = self.done_bytes.load(Ordering::Relaxed);
+        for file in self.searching.lock().unwrap().iter() {
+            let read = file.read.load(Ordering::Relaxed);
+            file.bar.set_position(read);
+            position += file.size.map_or(0, |size| read.min(size));
+        }
+        self.overall
+            .set_length(self.total_bytes.load(Ordering::Relaxed));
+        self.overall.set_position(position);
+
+        let lines_per_sec =
+            self.lines.load(Ordering::Relaxed) as f64 / self.started.elapsed().as_secs_f64();
+        self.overall.set_message(format!(
+            "{}/{} files, {lines_per_sec:.0} lines/sec",
+            self.files_done.load(Ordering::Relaxed),
+            self.files.load(Ordering::Relaxed),
+        ));
+    }
+}
+
+impl FileProgress {
+    /// Wraps the raw stream of the file, so that the bar follows how much has been read.
+    pub fn counted(&self, raw: Box<dyn Read + Send>) -> Box<dyn Read + Send> {
+        Box::new(CountingReader {
+            inner: raw,
+            state: self.state.clone(),
+        })
+    }
+}
+
+impl Drop for FileProgress {
+    fn drop(&mut self) {
+        let display = self.display;
+        display
+            .searching
+            .lock()
+            .unwrap()
+            .retain(|state| !Arc::ptr_eq(state, &self.state));
+        display.bars.remove(&self.state.bar);
+        let size = self.state.size.unwrap_or(0);
+        display.done_bytes.fetch_add(size, Ordering::Relaxed);
+        display.files_done.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Read for CountingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.state.read.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
@@ -0,0 +1,226 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{dedup::record_id, sink::Sink};
+
+/// How long to wait before the first retry. This doubles with each attempt.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Sends matches to an Elasticsearch or OpenSearch server with bulk index requests.
+///
+/// Documents are indexed using the record's `id` field as the document ID where it has one,
+/// so re-sending a file's matches after an interrupted run doesn't create duplicates.
+pub struct BulkIndexer {
+    agent: ureq::Agent,
+    endpoint: String,
+    /// The index for each query.
+    indices: Vec<String>,
+    batch_size: usize,
+    retries: u32,
+    pending: Mutex<Vec<BulkAction>>,
+}
+
+/// A single document to index, as the two lines of a bulk request.
+struct BulkAction {
+    action: String,
+    doc: String,
+}
+
+#[derive(Deserialize)]
+struct BulkResponse {
+    errors: bool,
+    #[serde(default)]
+    items: Vec<HashMap<String, BulkItem>>,
+}
+
+#[derive(Deserialize)]
+struct BulkItem {
+    status: u16,
+    error: Option<Value>,
+}
+
+impl BulkIndexer {
+    /// Creates an indexer sending to the server at `url`. Each query's matches go to an
+    /// index named after its filename, without the extension, following `index_prefix`.
+    pub fn new<'a>(
+        url: &str,
+        index_prefix: &str,
+        queries: impl IntoIterator<Item = &'a str>,
+        batch_size: usize,
+        retries: u32,
+    ) -> Self {
+        let indices = queries
+            .into_iter()
+            .map(|query| {
+                let name = query.rsplit_once('.').map_or(query, |(stem, _)| stem);
+                // Index names have to be lowercase, and can't contain most punctuation.
+                let name: String = name
+                    .chars()
+                    .map(|c| match c {
+                        'a'..='z' | '0'..='9' | '-' | '_' => c,
+                        'A'..='Z' => c.to_ascii_lowercase(),
+                        _ => '-',
+                    })
+                    .collect();
+                format!("{index_prefix}{name}")
+            })
+            .collect();
+
+        Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(120))
+                .build(),
+            endpoint: format!("{}/_bulk", url.trim_end_matches('/')),
+            indices,
+            batch_size: batch_size.max(1),
+            retries,
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Queues a query's matched record, sending the batch if it's full.
+    ///
+    /// A full batch is sent on the calling thread, which holds up that thread's search
+    /// until the server has accepted it, so a slow server can't leave an ever growing pile
+    /// of matches in memory.
+    pub fn index(&self, query: usize, record: &str) -> Result<()> {
+        let doc = record.trim_end_matches(['\n', '\r']);
+        let mut action = serde_json::json!({ "index": { "_index": self.indices[query] } });
+        if let Some(id) = record_id(doc) {
+            action["index"]["_id"] = Value::from(id.into_owned());
+        }
+
+        let batch = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.push(BulkAction {
+                action: action.to_string(),
+                doc: doc.to_owned(),
+            });
+            if pending.len() < self.batch_size {
+                return Ok(());
+            }
+            std::mem::take(&mut *pending)
+        };
+
+        self.send(batch)
+    }
+
+    /// Sends any queued records.
+    pub fn flush(&self) -> Result<()> {
+        let batch = std::mem::take(&mut *self.pending.lock().unwrap());
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.send(batch)
+    }
+
+    /// Sends a batch, retrying the whole batch on connection errors and server errors, and
+    /// retrying individual documents which were rejected because the server was too busy.
+    fn send(&self, mut batch: Vec<BulkAction>) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            let mut body = String::new();
+            for BulkAction { action, doc } in &batch {
+                body.push_str(action);
+                body.push('\n');
+                body.push_str(doc);
+                body.push('\n');
+            }
+
+            let result = self
+                .agent
+                .post(&self.endpoint)
+                .set("Content-Type", "application/x-ndjson")
+                .send_string(&body);
+
+            let error = match result {
+                Ok(response) => {
+                    let response: BulkResponse = serde_json::from_str(&response.into_string()?)?;
+                    if !response.errors {
+                        return Ok(());
+                    }
+
+                    batch = Self::failed_actions(batch, response.items);
+                    if batch.is_empty() {
+                        return Ok(());
+                    }
+                    anyhow!("{} documents rejected as too many requests", batch.len())
+                }
+                Err(ureq::Error::Status(status, response)) if status == 429 || status >= 500 => {
+                    let text = response.into_string().unwrap_or_default();
+                    anyhow!("server returned {status}: {text}")
+                }
+                Err(ureq::Error::Status(status, response)) => {
+                    let text = response.into_string().unwrap_or_default();
+                    bail!("Elasticsearch returned {status}: {text}");
+                }
+                Err(e @ ureq::Error::Transport(_)) => e.into(),
+            };
+
+            if attempt >= self.retries {
+                bail!("Error sending to Elasticsearch after {attempt} retries: {error}");
+            }
+            let delay = RETRY_DELAY * 2u32.saturating_pow(attempt);
+            eprintln!("Error sending to Elasticsearch ({error}), retrying in {delay:?}");
+            std::thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// Picks out the documents which can be retried from a bulk response, reporting the
+    /// ones which can't.
+    fn failed_actions(
+        batch: Vec<BulkAction>,
+        items: Vec<HashMap<String, BulkItem>>,
+    ) -> Vec<BulkAction> {
+        let mut retry = Vec::new();
+        let mut failed = 0;
+        let mut first_error = None;
+        for (action, item) in batch.into_iter().zip(items) {
+            let Some(item) = item.into_values().next() else {
+                continue;
+            };
+            match item.status {
+                200..=299 => {}
+                429 => retry.push(action),
+                _ => {
+                    failed += 1;
+                    first_error = first_error.or(item.error);
+                }
+            }
+        }
+
+        // These will be rejected again if we retry, so just report them.
+        if failed > 0 {
+            eprintln!(
+                "Elasticsearch rejected {failed} documents, the first with: {}",
+                first_error.unwrap_or_default()
+            );
+        }
+        retry
+    }
+}
+
+impl Sink for BulkIndexer {
+    fn wants_lines(&self) -> bool {
+        true
+    }
+
+    /// Indexes the line, rather than the record, which might not be JSON.
+    fn write_match(&self, query: usize, _record: &[u8], line: Option<&[u8]>) -> Result<(), String> {
+        let line = line.expect("lines are kept for sinks which want them");
+        let doc = std::str::from_utf8(line).expect("matched lines are valid UTF-8");
+        self.index(query, doc).map_err(|e| format!("{e:#}"))
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        BulkIndexer::flush(self).map_err(|e| format!("{e:#}"))
+    }
+
+    fn finalize(self: Box<Self>) -> Result<(), String> {
+        Sink::flush(&*self)
+    }
+}
@@ -0,0 +1,59 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use crate::management::ResumePoint;
+
+/// A record of the inputs which couldn't be searched, appended to `errors.log` in the output
+/// folder, so there's something to go back to after a long run besides the scrollback.
+pub struct ErrorLog {
+    path: PathBuf,
+    /// Opened with the first error, so that runs without any don't leave an empty log.
+    file: Mutex<Option<File>>,
+}
+
+impl ErrorLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Adds an error for an input, with how far through its decompressed stream the search
+    /// had got if that's known. Problems writing to the log are printed, as there's nowhere
+    /// else for them to go.
+    pub fn record(&self, input: &str, position: Option<ResumePoint>, error: &str) {
+        let time = humantime::format_rfc3339_seconds(SystemTime::now());
+        let position = match position {
+            Some(point) => format!(" (after line {}, byte {})", point.lines, point.bytes),
+            None => String::new(),
+        };
+        // Errors can span lines, but each entry is kept to one.
+        let error = error.replace('\n', " ");
+
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+            {
+                Ok(opened) => *file = Some(opened),
+                Err(e) => {
+                    eprintln!("Error opening {}: {e}", self.path.display());
+                    return;
+                }
+            }
+        }
+        if let Some(log) = file.as_mut() {
+            if let Err(e) = writeln!(log, "{time} {input}{position}: {error}") {
+                eprintln!("Error writing to {}: {e}", self.path.display());
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    io::{self, BufRead, Read},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use aho_corasick::AhoCorasick;
+use anyhow::{anyhow, Context, Result};
+
+use crate::{
+    decode::DecodeOptions, input::Input, lines::count_lines, prefilter::Prefilter, search_line,
+    source::Source, status, Query,
+};
+
+/// How much is read from a sample at a time, to be searched as a search would.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// What was found in the start of a sample file.
+#[derive(Debug, Default)]
+struct Sample {
+    /// Compressed bytes read.
+    raw_bytes: u64,
+    bytes: u64,
+    lines: u64,
+    elapsed: Duration,
+    /// The matches for each query, and the size of the lines they matched.
+    matches: Vec<u64>,
+    matched_bytes: Vec<u64>,
+}
+
+/// Counts the bytes read from a sample's raw stream.
+struct CountingReader {
+    inner: Box<dyn Read + Send>,
+    read: Arc<AtomicU64>,
+}
+
+impl Read for CountingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// Decompresses and searches the start of the input, up to `limit` decompressed bytes,
+/// without writing out the matches.
+fn sample(
+    input: &Input,
+    queries: &[Query],
+    searchers: &[AhoCorasick],
+    prefilter: &Prefilter,
+    decode_options: &DecodeOptions,
+    limit: u64,
+) -> Result<Sample> {
+    let started = Instant::now();
+    let raw_bytes = Arc::new(AtomicU64::new(0));
+    let raw = Box::new(CountingReader {
+        inner: input.open()?,
+        read: raw_bytes.clone(),
+    });
+    let mut reader = input.decode(raw, decode_options)?;
+
+    let mut sample = Sample {
+        matches: vec![0; queries.len()],
+        matched_bytes: vec![0; queries.len()],
+        ..Sample::default()
+    };
+    let mut does_match = vec![false; searchers.len()];
+    let mut text = Vec::with_capacity(CHUNK_SIZE);
+    while sample.bytes < limit {
+        // Whole lines, so that none are split between chunks.
+        text.clear();
+        while text.len() < CHUNK_SIZE && reader.read_until(b'\n', &mut text)? > 0 {}
+        if text.is_empty() {
+            break;
+        }
+        sample.bytes += text.len() as u64;
+        sample.lines += count_lines(&text);
+        for (_, line) in prefilter.candidates(&text) {
+            search_line(line, searchers, &mut does_match);
+            for (i, query) in queries.iter().enumerate() {
+                if query.matches_line(does_match[i], line, &searchers[i]) {
+                    sample.matches[i] += 1;
+                    sample.matched_bytes[i] += line.len() as u64 + 1;
+                }
+            }
+        }
+    }
+    sample.raw_bytes = raw_bytes.load(Ordering::Relaxed);
+    sample.elapsed = started.elapsed();
+    Ok(sample)
+}
+
+/// Picks up to `count` inputs of known size, spread from the smallest to the largest.
+fn pick_samples(inputs: &[Input], count: usize) -> Vec<&Input> {
+    let mut sized: Vec<_> = inputs
+        .iter()
+        .filter_map(|input| Some((input.source_size()?, input)))
+        .filter(|(size, _)| *size > 0)
+        .collect();
+    sized.sort_by_key(|(size, _)| *size);
+    let count = count.min(sized.len());
+    if count <= 1 {
+        return sized.last().map(|(_, input)| *input).into_iter().collect();
+    }
+    (0..count)
+        .map(|i| sized[i * (sized.len() - 1) / (count - 1)].1)
+        .collect()
+}
+
+/// Searches the start of a few of the inputs, and projects from how quickly that went and
+/// what was found how long searching all of them would take, and how big the outputs would
+/// be. Output sizes are for the lines as they were read, before any formatting.
+#[allow(clippy::too_many_arguments)]
+pub fn estimate(
+    inputs: &[Input],
+    queries: &[Query],
+    searchers: &[AhoCorasick],
+    prefilter: &Prefilter,
+    decode_options: &DecodeOptions,
+    threads: usize,
+    samples: usize,
+    sample_size: u64,
+) -> Result<()> {
+    let mut total = Sample {
+        matches: vec![0; queries.len()],
+        matched_bytes: vec![0; queries.len()],
+        ..Sample::default()
+    };
+    for input in pick_samples(inputs, samples) {
+        status!("Sampling {input}...");
+        let sample = sample(
+            input,
+            queries,
+            searchers,
+            prefilter,
+            decode_options,
+            sample_size,
+        )
+        .with_context(|| anyhow!("Error sampling {input}"))?;
+        total.raw_bytes += sample.raw_bytes;
+        total.bytes += sample.bytes;
+        total.lines += sample.lines;
+        total.elapsed += sample.elapsed;
+        for i in 0..queries.len() {
+            total.matches[i] += sample.matches[i];
+            total.matched_bytes[i] += sample.matched_bytes[i];
+        }
+    }
+    if total.raw_bytes == 0 || total.elapsed.is_zero() {
+        println!("No input files of known size with anything in them to sample");
+        return Ok(());
+    }
+
+    let sizes: Vec<u64> = inputs.iter().filter_map(Input::source_size).collect();
+    let unknown = inputs.len() - sizes.len();
+    let input_bytes: u64 = sizes.iter().sum();
+    let largest = sizes.iter().copied().max().unwrap_or(0);
+    // Compressed bytes searched per second by each thread, with a file to each.
+    let rate = total.raw_bytes as f64 / total.elapsed.as_secs_f64();
+    let scale = input_bytes as f64 / total.raw_bytes as f64;
+    // No quicker than the largest file takes on its own.
+    let secs = (input_bytes as f64 / (rate * threads.max(1) as f64)).max(largest as f64 / rate);
+
+    println!(
+        "Sampled {:.1} MB decompressed from {:.1} MB in {:.1}s, {:.1} MB/sec compressed for \
+        each thread",
+        total.bytes as f64 / 1e6,
+        total.raw_bytes as f64 / 1e6,
+        total.elapsed.as_secs_f64(),
+        rate / 1e6
+    );
+    println!(
+        "{} files to search, {:.1} GB compressed, about {:.1} GB and {:.0} lines decompressed",
+        inputs.len(),
+        input_bytes as f64 / 1e9,
+        total.bytes as f64 * scale / 1e9,
+        total.lines as f64 * scale
+    );
+    if unknown > 0 {
+        println!("{unknown} files of unknown size, like URLs, aren't included in the estimate");
+    }
+    println!(
+        "Estimated time with {threads} threads: {}",
+        humantime::format_duration(Duration::from_secs(secs.ceil() as u64))
+    );
+    println!("Estimated output:");
+    for (i, query) in queries.iter().enumerate() {
+        println!(
+            "  {}: {:.0} matches, {:.1} MB",
+            query.filename,
+            total.matches[i] as f64 * scale,
+            total.matched_bytes[i] as f64 * scale / 1e6
+        );
+    }
+    Ok(())
+}
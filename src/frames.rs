@@ -0,0 +1,399 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+};
+
+use zstd::stream::raw::{Decoder, InBuffer, Operation, OutBuffer};
+
+use crate::decode::{explain_window_error, DecodeOptions};
+
+const ZSTD_MAGIC: u32 = 0xFD2F_B528;
+const SKIPPABLE_MAGIC_MASK: u32 = 0xFFFF_FFF0;
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+const SEEK_TABLE_MAGIC: u32 = 0x184D_2A5E;
+const SEEKABLE_FOOTER_MAGIC: u32 = 0x8F92_EAB1;
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Finds the compressed byte range of each zstd frame in the file.
+///
+/// Files in the zstd seekable format have this stored in a seek table at the end, otherwise
+/// we walk the frame and block headers, skipping over the block contents.
+pub fn frame_ranges(path: &Path) -> io::Result<Vec<Range<u64>>> {
+    let mut file = BufReader::new(File::open(path)?);
+    if let Some(ranges) = read_seek_table(&mut file)? {
+        return Ok(ranges);
+    }
+
+    file.seek(SeekFrom::Start(0))?;
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    while !file.fill_buf()?.is_empty() {
+        let magic = read_u32(&mut file)?;
+        let len = if magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC {
+            let size = read_u32(&mut file)?;
+            file.seek_relative(size.into())?;
+            8 + u64::from(size)
+        } else if magic == ZSTD_MAGIC {
+            let len = 4 + skip_frame_body(&mut file)?;
+            ranges.push(offset..offset + len);
+            len
+        } else {
+            return Err(invalid_data("unknown frame magic number"));
+        };
+        offset += len;
+    }
+
+    Ok(ranges)
+}
+
+/// Skips over the frame following the magic number, returning how many bytes it took up.
+fn skip_frame_body(file: &mut BufReader<File>) -> io::Result<u64> {
+    let mut descriptor = [0];
+    file.read_exact(&mut descriptor)?;
+    let descriptor = descriptor[0];
+
+    let fcs_flag = descriptor >> 6;
+    let single_segment = descriptor & 0x20 != 0;
+    let has_checksum = descriptor & 0x04 != 0;
+    let dict_id_flag = descriptor & 0x03;
+
+    let window_size = if single_segment { 0 } else { 1 };
+    let dict_id_size = [0, 1, 2, 4][usize::from(dict_id_flag)];
+    let fcs_size = match fcs_flag {
+        0 if single_segment => 1,
+        0 => 0,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+    let header_rest = window_size + dict_id_size + fcs_size;
+    file.seek_relative(header_rest)?;
+    let mut len = 1 + header_rest as u64;
+
+    loop {
+        let mut header = [0; 3];
+        file.read_exact(&mut header)?;
+        let header = u32::from_le_bytes([header[0], header[1], header[2], 0]);
+        let last_block = header & 1 != 0;
+        let block_type = (header >> 1) & 0x3;
+        let block_size = header >> 3;
+        let content_size = match block_type {
+            0 | 2 => block_size,
+            1 => 1,
+            _ => return Err(invalid_data("reserved block type")),
+        };
+        file.seek_relative(content_size.into())?;
+        len += 3 + u64::from(content_size);
+
+        if last_block {
+            break;
+        }
+    }
+
+    if has_checksum {
+        file.seek_relative(4)?;
+        len += 4;
+    }
+
+    Ok(len)
+}
+
+fn read_seek_table(file: &mut BufReader<File>) -> io::Result<Option<Vec<Range<u64>>>> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    if file_len < 17 {
+        return Ok(None);
+    }
+
+    file.seek(SeekFrom::End(-9))?;
+    let num_frames = read_u32(file)?;
+    let mut descriptor = [0];
+    file.read_exact(&mut descriptor)?;
+    if read_u32(file)? != SEEKABLE_FOOTER_MAGIC {
+        return Ok(None);
+    }
+
+    let entry_size: u64 = if descriptor[0] & 0x80 != 0 { 12 } else { 8 };
+    let table_len = 8 + entry_size * u64::from(num_frames) + 9;
+    if table_len > file_len {
+        return Err(invalid_data("seek table larger than file"));
+    }
+
+    file.seek(SeekFrom::End(-(table_len as i64)))?;
+    if read_u32(file)? != SEEK_TABLE_MAGIC {
+        return Err(invalid_data("bad seek table magic number"));
+    }
+    file.seek_relative(4)?;
+
+    let mut ranges = Vec::with_capacity(num_frames as usize);
+    let mut offset = 0;
+    for _ in 0..num_frames {
+        let compressed_size = u64::from(read_u32(file)?);
+        file.seek_relative(entry_size as i64 - 4)?;
+        ranges.push(offset..offset + compressed_size);
+        offset += compressed_size;
+    }
+
+    Ok(Some(ranges))
+}
+
+/// Groups consecutive frames into chunks of at least `chunk_size` compressed bytes.
+pub fn group_frames(frames: &[Range<u64>], chunk_size: u64) -> Vec<Range<u64>> {
+    let mut chunks: Vec<Range<u64>> = Vec::new();
+    for frame in frames {
+        match chunks.last_mut() {
+            Some(chunk) if chunk.end - chunk.start < chunk_size => chunk.end = frame.end,
+            _ => chunks.push(frame.clone()),
+        }
+    }
+    chunks
+}
+
+/// Decodes the lines belonging to a chunk of frames.
+///
+/// Lines don't respect frame boundaries, so a chunk skips everything up to the first newline
+/// (unless it starts the file), and keeps reading into the following frames until it finds the
+/// newline ending its last line. The skipped data is always the same data the previous chunk
+/// reads past its end, so every line is seen by exactly one chunk.
+pub struct ChunkReader {
+    reader: BufReader<File>,
+    decoder: Decoder<'static>,
+    position: u64,
+    end: u64,
+    past_end: bool,
+    discarding: bool,
+    finished: bool,
+}
+
+impl ChunkReader {
+    pub fn new(path: &Path, chunk: Range<u64>, options: &DecodeOptions) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(chunk.start))?;
+        Ok(Self {
+            reader: BufReader::new(file),
+            decoder: options.raw_decoder()?,
+            position: chunk.start,
+            end: chunk.end,
+            past_end: false,
+            discarding: chunk.start != 0,
+            finished: false,
+        })
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.finished && !buf.is_empty() {
+            let input = self.reader.fill_buf()?;
+            if input.is_empty() {
+                self.finished = true;
+                break;
+            }
+
+            let mut in_buf = InBuffer::around(input);
+            let mut out_buf = OutBuffer::around(&mut *buf);
+            let hint = self
+                .decoder
+                .run(&mut in_buf, &mut out_buf)
+                .map_err(explain_window_error)?;
+            let consumed = in_buf.pos();
+            let mut written = out_buf.pos();
+            self.reader.consume(consumed);
+            self.position += consumed as u64;
+
+            // The decoder can hold on to decoded data, so we're only done with our own frames
+            // once one has been fully flushed and it ended at or after the chunk's end.
+            let past_end = self.past_end;
+            if hint == 0 && self.position >= self.end {
+                self.past_end = true;
+            }
+
+            if self.discarding {
+                match buf[..written].iter().position(|&b| b == b'\n') {
+                    Some(pos) => {
+                        self.discarding = false;
+                        buf.copy_within(pos + 1..written, 0);
+                        written -= pos + 1;
+                        // If we've run past our own frames, the line we just skipped ended
+                        // where our data would have, so there's nothing left for us.
+                        if past_end {
+                            self.finished = true;
+                            return Ok(0);
+                        }
+                    }
+                    None => written = 0,
+                }
+            }
+
+            // Once our frames are done, we only want the rest of the current line.
+            if past_end {
+                if let Some(pos) = buf[..written].iter().position(|&b| b == b'\n') {
+                    written = pos + 1;
+                    self.finished = true;
+                }
+            }
+
+            if written > 0 {
+                return Ok(written);
+            }
+        }
+
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// Compresses each of the texts as a frame of its own, returning the stream along with
+    /// each frame's range in it.
+    fn frames(texts: &[&str], checksum: bool) -> (Vec<u8>, Vec<Range<u64>>) {
+        let mut stream = Vec::new();
+        let mut ranges = Vec::new();
+        for text in texts {
+            let start = stream.len() as u64;
+            let mut encoder = zstd::Encoder::new(Vec::new(), 3).unwrap();
+            encoder.include_checksum(checksum).unwrap();
+            encoder.write_all(text.as_bytes()).unwrap();
+            stream.extend(encoder.finish().unwrap());
+            ranges.push(start..stream.len() as u64);
+        }
+        (stream, ranges)
+    }
+
+    /// Writes the stream to a file of its own for the test.
+    fn temp_file(name: &str, stream: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "ytmetasearch-frames-{}-{name}.zst",
+            std::process::id()
+        ));
+        std::fs::write(&path, stream).unwrap();
+        path
+    }
+
+    fn read_chunks(path: &Path, chunks: &[Range<u64>]) -> Vec<String> {
+        chunks
+            .iter()
+            .map(|chunk| {
+                let mut reader =
+                    ChunkReader::new(path, chunk.clone(), &DecodeOptions::default()).unwrap();
+                let mut text = String::new();
+                reader.read_to_string(&mut text).unwrap();
+                text
+            })
+            .collect()
+    }
+
+    #[test]
+    fn walks_frame_headers() {
+        let (mut stream, mut ranges) = frames(&["a\n", "b\n"], true);
+        // A skippable frame between the frames isn't one of them.
+        stream.extend(SKIPPABLE_MAGIC.to_le_bytes());
+        stream.extend(3u32.to_le_bytes());
+        stream.extend([0; 3]);
+        let (more, more_ranges) = frames(&["c\n"], false);
+        let offset = stream.len() as u64;
+        stream.extend(more);
+        ranges.extend(more_ranges.iter().map(|r| r.start + offset..r.end + offset));
+
+        let path = temp_file("walk", &stream);
+        let found = frame_ranges(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(found.unwrap(), ranges);
+    }
+
+    #[test]
+    fn reads_seek_table() {
+        let (mut stream, ranges) = frames(&["a\n", "bb\n", "ccc\n"], false);
+        let mut table = Vec::new();
+        for range in &ranges {
+            table.extend((range.end as u32 - range.start as u32).to_le_bytes());
+            table.extend(0u32.to_le_bytes());
+        }
+        table.extend((ranges.len() as u32).to_le_bytes());
+        table.push(0);
+        table.extend(SEEKABLE_FOOTER_MAGIC.to_le_bytes());
+        stream.extend(SEEK_TABLE_MAGIC.to_le_bytes());
+        stream.extend((table.len() as u32).to_le_bytes());
+        stream.extend(table);
+
+        let path = temp_file("seek-table", &stream);
+        let found = frame_ranges(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(found.unwrap(), ranges);
+    }
+
+    #[test]
+    fn fails_on_corrupt_frame_header() {
+        let (mut stream, ranges) = frames(&["a\n", "b\n"], false);
+        stream[ranges[1].start as usize] ^= 0xFF;
+        let path = temp_file("corrupt-walk", &stream);
+        let found = frame_ranges(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(found.is_err());
+    }
+
+    #[test]
+    fn groups_frames_into_chunks() {
+        let frames = [0..10, 10..15, 15..40, 40..45, 45..50];
+        assert_eq!(group_frames(&frames, 12), [0..15, 15..40, 40..50]);
+        assert_eq!(group_frames(&frames, 1), frames);
+        assert_eq!(group_frames(&frames, 100), vec![0..50]);
+        assert_eq!(group_frames(&[], 100), []);
+    }
+
+    #[test]
+    fn chunks_see_each_line_once() {
+        // Lines which end with their frames, start in one and end in the next, and span
+        // whole frames.
+        let texts = [
+            "one\ntwo\n",
+            "thr",
+            "ee\nfo",
+            "ur\n",
+            "fi",
+            "v",
+            "e\nsix\nse",
+            "ven",
+            "\n",
+            "eight\nnine",
+        ];
+        let all: String = texts.concat();
+        let (stream, ranges) = frames(&texts, true);
+        let path = temp_file("chunks", &stream);
+        for chunk_size in 1..=stream.len() as u64 {
+            let chunks = group_frames(&ranges, chunk_size);
+            let read = read_chunks(&path, &chunks);
+            assert_eq!(read.concat(), all, "chunk size {chunk_size}: {read:?}");
+            for text in &read[..read.len() - 1] {
+                assert!(text.is_empty() || text.ends_with('\n'), "{read:?}");
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn chunk_fails_on_corrupt_frame() {
+        let (mut stream, ranges) = frames(&["a\n", "b\n", "c\n"], true);
+        stream[ranges[1].end as usize - 1] ^= 0xFF;
+        let path = temp_file("corrupt-chunk", &stream);
+        let mut reader =
+            ChunkReader::new(&path, ranges[1].clone(), &DecodeOptions::default()).unwrap();
+        let result = reader.read_to_string(&mut String::new());
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
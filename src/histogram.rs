@@ -0,0 +1,178 @@
+use std::{collections::BTreeMap, io::Write, path::PathBuf, str::FromStr, sync::atomic::Ordering};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::{
+    logging::STATUS_TO_STDERR,
+    output::{create_output, csv_escape, open_lines, read_record},
+    record::RecordView,
+    schema::normalize_date,
+    status,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct HistogramArgs {
+    /// The result files to count the matches in, each a column of the table, as written by
+    /// the `raw` or `jsonl` output formats. Files ending in `.zst` or `.gz` are decompressed.
+    #[clap(required = true)]
+    files: Vec<PathBuf>,
+    /// The date field to bucket the records by, and the size of the buckets: `day`, `month`
+    /// or `year`.
+    #[clap(long = "by", short = 'b', default_value = "upload_date:month")]
+    by: Bucketing,
+    /// Where to write the table, as CSV. Defaults to stdout.
+    #[clap(long = "output", short = 'o')]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+struct Bucketing {
+    field: String,
+    size: BucketSize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BucketSize {
+    Day,
+    Month,
+    Year,
+}
+
+impl FromStr for Bucketing {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (field, size) = value.split_once(':').unwrap_or((value, "month"));
+        let size = match size {
+            "day" => BucketSize::Day,
+            "month" => BucketSize::Month,
+            "year" => BucketSize::Year,
+            _ => bail!("unknown bucket size `{size}`, expected `day`, `month` or `year`"),
+        };
+        Ok(Self {
+            field: field.to_owned(),
+            size,
+        })
+    }
+}
+
+/// A date, as the year, month and day.
+type Date = (u32, u32, u32);
+
+fn parse_date(date: &str) -> Option<Date> {
+    let year = date.get(..4)?.parse().ok()?;
+    let month = date.get(4..6)?.parse().ok()?;
+    let day = date.get(6..8)?.parse().ok()?;
+    ((1..=12).contains(&month) && (1..=31).contains(&day)).then_some((year, month, day))
+}
+
+impl BucketSize {
+    /// The first day of the bucket the date is in.
+    fn bucket(self, (year, month, day): Date) -> Date {
+        match self {
+            BucketSize::Day => (year, month, day),
+            BucketSize::Month => (year, month, 1),
+            BucketSize::Year => (year, 1, 1),
+        }
+    }
+
+    /// The first day of the bucket after this one.
+    fn next(self, (year, month, day): Date) -> Date {
+        let next_month = |year, month| match month {
+            12 => (year + 1, 1, 1),
+            _ => (year, month + 1, 1),
+        };
+        match self {
+            BucketSize::Day if day < days_in_month(year, month) => (year, month, day + 1),
+            BucketSize::Day | BucketSize::Month => next_month(year, month),
+            BucketSize::Year => (year + 1, 1, 1),
+        }
+    }
+
+    fn label(self, (year, month, day): Date) -> String {
+        match self {
+            BucketSize::Day => format!("{year:04}-{month:02}-{day:02}"),
+            BucketSize::Month => format!("{year:04}-{month:02}"),
+            BucketSize::Year => format!("{year:04}"),
+        }
+    }
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Counts the records in each result file by the date bucket they fall in, writing a table
+/// with a row for each bucket from the first to the last, including any with no matches in
+/// between, and a column for each file.
+pub fn histogram(args: &HistogramArgs) -> Result<()> {
+    // The table can be written to stdout, so keep it clear of status messages.
+    STATUS_TO_STDERR.store(args.output.is_none(), Ordering::Relaxed);
+    let Bucketing { field, size } = &args.by;
+    let mut counts: BTreeMap<Date, Vec<u64>> = BTreeMap::new();
+    let mut undated = 0;
+    let mut line = String::new();
+    for (column, path) in args.files.iter().enumerate() {
+        let mut reader =
+            open_lines(path).with_context(|| anyhow!("Error opening {}", path.display()))?;
+        while read_record(&mut reader, &mut line)
+            .with_context(|| anyhow!("Error reading {}", path.display()))?
+        {
+            let record = RecordView::new(line.trim_end());
+            let inner = record.inner_record();
+            let date = inner
+                .as_ref()
+                .unwrap_or(&record)
+                .field(field)
+                .and_then(|value| normalize_date(&value))
+                .and_then(|date| parse_date(&date));
+            let Some(date) = date else {
+                undated += 1;
+                continue;
+            };
+            let row = counts
+                .entry(size.bucket(date))
+                .or_insert_with(|| vec![0; args.files.len()]);
+            row[column] += 1;
+        }
+    }
+    if undated > 0 {
+        status!("Left out {undated} records without a date in `{field}`");
+    }
+
+    let write = |out: &mut dyn Write| -> Result<()> {
+        write!(out, "{field}")?;
+        for path in &args.files {
+            write!(out, ",{}", csv_escape(&path.display().to_string()))?;
+        }
+        writeln!(out)?;
+
+        let empty = vec![0; args.files.len()];
+        let (Some(&first), Some(&last)) = (counts.keys().next(), counts.keys().next_back()) else {
+            return Ok(out.flush()?);
+        };
+        let mut bucket = first;
+        while bucket <= last {
+            write!(out, "{}", size.label(bucket))?;
+            for count in counts.get(&bucket).unwrap_or(&empty) {
+                write!(out, ",{count}")?;
+            }
+            writeln!(out)?;
+            bucket = size.next(bucket);
+        }
+        out.flush()?;
+        Ok(())
+    };
+    match &args.output {
+        Some(path) => write(&mut create_output(path)?)
+            .with_context(|| anyhow!("Error writing {}", path.display())),
+        None => write(&mut std::io::stdout().lock()),
+    }
+}
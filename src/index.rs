@@ -0,0 +1,338 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    decode::DecodeOptions,
+    input::{canonical_path, Input, InputSelector},
+    inverted::{InvertedIndexBuilder, INVERTED_MAGIC},
+    lines::{count_lines, Lines, ReaderLines},
+    management::with_suffix,
+    source::Source,
+    status, thread_pool, Query,
+};
+
+/// Identifies a trigram index file, and the version of its format.
+const TRIGRAM_MAGIC: &[u8; 8] = b"YTMSIDX\x01";
+
+/// One bit for every possible trigram.
+const TRIGRAM_WORDS: usize = (1 << 24) / 64;
+
+#[derive(Debug, clap::Args)]
+pub struct IndexArgs {
+    /// Folder containing the input files to index.
+    #[clap(long = "input-folder", short = 'i', alias = "files-folder")]
+    files_folder: String,
+    /// Glob pattern, relative to the input folder, used to find input files.
+    #[clap(long = "glob", short = 'g', default_value = "**/*.zst")]
+    glob: String,
+    /// Glob pattern, relative to the input folder, of files to skip. Can be repeated.
+    #[clap(long = "exclude", short = 'x')]
+    exclude: Vec<String>,
+    /// Where to write the indexes, laid out like the input folder. Searches given the same
+    /// `--index-folder` use them to skip inputs.
+    #[clap(long = "index-folder")]
+    index_folder: PathBuf,
+    /// Also build an inverted index of the tokens in each record's fields, for the `query`
+    /// subcommand to answer searches from.
+    #[clap(long = "inverted")]
+    inverted: bool,
+    /// Index every file again, even if its index is up to date.
+    #[clap(long = "force")]
+    force: bool,
+    /// How many files to index at once. Defaults to one for each CPU core.
+    #[clap(long = "threads", short = 'j')]
+    threads: Option<usize>,
+    /// The largest window the zstd decoder will accept, as for searching.
+    #[clap(long = "zstd-window-log", value_parser = clap::value_parser!(u32).range(10..=31))]
+    zstd_window_log: Option<u32>,
+}
+
+/// Which trigrams appear in an input, so that a search can tell that an input can't match
+/// any of its queries without reading it.
+///
+/// Every possible trigram gets a bit, so unlike a bloom filter there are no false positives,
+/// and as most are never seen the set compresses down to a small file. Trigrams are ASCII
+/// lowercased, to match how the expressions are matched.
+pub struct TrigramIndex {
+    trigrams: Vec<u64>,
+}
+
+fn trigram(a: u8, b: u8, c: u8) -> usize {
+    usize::from(a.to_ascii_lowercase()) << 16
+        | usize::from(b.to_ascii_lowercase()) << 8
+        | usize::from(c.to_ascii_lowercase())
+}
+
+/// The header at the start of each index file, identifying the input it was built from.
+pub struct Header {
+    /// The fingerprint of the input when it was indexed, to tell whether it's changed since.
+    pub fingerprint: String,
+    pub lines: u64,
+    /// The decompressed size of the input.
+    pub bytes: u64,
+}
+
+impl Header {
+    pub fn write(&self, writer: &mut impl Write, magic: &[u8; 8]) -> io::Result<()> {
+        writer.write_all(magic)?;
+        writer.write_all(&(self.fingerprint.len() as u32).to_le_bytes())?;
+        writer.write_all(self.fingerprint.as_bytes())?;
+        writer.write_all(&self.lines.to_le_bytes())?;
+        writer.write_all(&self.bytes.to_le_bytes())
+    }
+
+    pub fn read(reader: &mut impl Read, magic: &[u8; 8]) -> io::Result<Self> {
+        let mut found = [0; 8];
+        reader.read_exact(&mut found)?;
+        if &found != magic {
+            return Err(io::Error::other(
+                "not an index file, or from another version",
+            ));
+        }
+        let len = read_u32(reader)?;
+        let mut fingerprint = vec![0; len as usize];
+        reader.read_exact(&mut fingerprint)?;
+        Ok(Self {
+            fingerprint: String::from_utf8(fingerprint)
+                .map_err(|_| io::Error::other("invalid fingerprint"))?,
+            lines: read_u64(reader)?,
+            bytes: read_u64(reader)?,
+        })
+    }
+
+    /// Reads just the header of an index file.
+    fn read_file(path: &Path, magic: &[u8; 8]) -> io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?), magic)
+    }
+}
+
+pub fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Writes an index file through a temporary file, so that a half-written index is never
+/// mistaken for a whole one.
+pub fn write_index_file(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp_path = with_suffix(path, ".tmp");
+    let mut file = BufWriter::new(File::create(&temp_path)?);
+    write(&mut file)?;
+    file.into_inner()?.sync_all()?;
+    std::fs::rename(temp_path, path)
+}
+
+impl TrigramIndex {
+    fn new() -> Self {
+        Self {
+            trigrams: vec![0; TRIGRAM_WORDS],
+        }
+    }
+
+    /// Records the trigrams in a batch of whole lines.
+    fn add(&mut self, lines: &[u8]) {
+        for window in lines.windows(3) {
+            let trigram = trigram(window[0], window[1], window[2]);
+            self.trigrams[trigram / 64] |= 1 << (trigram % 64);
+        }
+    }
+
+    fn contains(&self, trigram: usize) -> bool {
+        self.trigrams[trigram / 64] & (1 << (trigram % 64)) != 0
+    }
+
+    /// Whether the input might contain the expression. Expressions shorter than a trigram
+    /// always might.
+    fn may_contain(&self, expression: &str) -> bool {
+        expression
+            .as_bytes()
+            .windows(3)
+            .all(|w| self.contains(trigram(w[0], w[1], w[2])))
+    }
+
+    /// Whether any line of the input might match one of the queries. Queries writing the
+    /// lines which don't match can match anything.
+    pub fn may_match(&self, queries: &[Query]) -> bool {
+        queries
+            .iter()
+            .any(|q| q.invert || q.expressions.iter().any(|e| self.may_contain(e)))
+    }
+
+    fn distinct(&self) -> u32 {
+        self.trigrams.iter().map(|word| word.count_ones()).sum()
+    }
+
+    fn write(&self, path: &Path, header: &Header) -> io::Result<()> {
+        write_index_file(path, |file| {
+            header.write(file, TRIGRAM_MAGIC)?;
+            let mut encoder = zstd::Encoder::new(file, 3)?;
+            for word in &self.trigrams {
+                encoder.write_all(&word.to_le_bytes())?;
+            }
+            encoder.finish()?;
+            Ok(())
+        })
+    }
+
+    fn read(path: &Path) -> io::Result<(Header, Self)> {
+        let mut file = BufReader::new(File::open(path)?);
+        let header = Header::read(&mut file, TRIGRAM_MAGIC)?;
+        let mut decoder = zstd::Decoder::with_buffer(file)?;
+        let mut trigrams = Vec::with_capacity(TRIGRAM_WORDS);
+        for _ in 0..TRIGRAM_WORDS {
+            trigrams.push(read_u64(&mut decoder)?);
+        }
+        Ok((header, Self { trigrams }))
+    }
+}
+
+/// Where an index of an input file is kept in the index folder, with the extension for
+/// the kind of index added. Only files in the input folder are indexed.
+pub fn index_path(index_folder: &Path, input: &Input, root: &Path, ext: &str) -> Option<PathBuf> {
+    let Input::File(_) = input else {
+        return None;
+    };
+    let relative = input.management_path(Some(root))?;
+    if relative.is_absolute() {
+        return None;
+    }
+    Some(with_suffix(&index_folder.join(relative), ext))
+}
+
+/// Whether the index file exists and was built from the input as it is now.
+pub fn up_to_date(path: &Path, magic: &[u8; 8], fingerprint: &str) -> bool {
+    Header::read_file(path, magic).is_ok_and(|header| header.fingerprint == fingerprint)
+}
+
+/// Loads the trigram index of the input, if it has one which is still up to date.
+pub fn load_index(
+    index_folder: &Path,
+    input: &Input,
+    root: &Path,
+) -> Result<Option<(Header, TrigramIndex)>> {
+    let Some(path) = index_path(index_folder, input, root, ".idx").filter(|path| path.exists())
+    else {
+        return Ok(None);
+    };
+    let (header, index) = TrigramIndex::read(&path)
+        .with_context(|| anyhow!("Error reading index {}", path.display()))?;
+    if input.fingerprint()? != Some(header.fingerprint.clone()) {
+        status!("{input} has changed since it was indexed");
+        return Ok(None);
+    }
+    Ok(Some((header, index)))
+}
+
+fn index_input(
+    args: &IndexArgs,
+    input: &Input,
+    root: &Path,
+    options: &DecodeOptions,
+) -> Result<()> {
+    let Some(trigram_path) = index_path(&args.index_folder, input, root, ".idx") else {
+        return Ok(());
+    };
+    let inverted_path =
+        index_path(&args.index_folder, input, root, ".inv").filter(|_| args.inverted);
+    let fingerprint = input
+        .fingerprint()?
+        .ok_or_else(|| anyhow!("Error fingerprinting {input}"))?;
+    let stale = |path: &PathBuf, magic| args.force || !up_to_date(path, magic, &fingerprint);
+    let mut trigrams = stale(&trigram_path, TRIGRAM_MAGIC).then(TrigramIndex::new);
+    let mut inverted = inverted_path
+        .as_ref()
+        .filter(|path| stale(path, INVERTED_MAGIC))
+        .map(|_| InvertedIndexBuilder::default());
+    if trigrams.is_none() && inverted.is_none() {
+        status!("Skipping file {input} (index is up to date)");
+        return Ok(());
+    }
+
+    status!("Indexing {input}...");
+    let mut header = Header {
+        fingerprint,
+        lines: 0,
+        bytes: 0,
+    };
+    let mut lines = ReaderLines::new(input.lines(options)?);
+    while let Some(batch) = lines
+        .next_batch()
+        .with_context(|| anyhow!("Error reading {input}"))?
+    {
+        if let Some(trigrams) = &mut trigrams {
+            trigrams.add(batch);
+        }
+        if let Some(inverted) = &mut inverted {
+            inverted.add(batch, header.lines);
+        }
+        header.lines += count_lines(batch);
+        header.bytes += batch.len() as u64;
+    }
+
+    let mut indexed = Vec::new();
+    if let Some(trigrams) = trigrams {
+        trigrams
+            .write(&trigram_path, &header)
+            .with_context(|| anyhow!("Error writing index {}", trigram_path.display()))?;
+        indexed.push(format!("{} distinct trigrams", trigrams.distinct()));
+    }
+    if let (Some(inverted), Some(path)) = (inverted, inverted_path) {
+        indexed.push(format!("{} distinct tokens", inverted.tokens()));
+        inverted
+            .write(&path, &header)
+            .with_context(|| anyhow!("Error writing index {}", path.display()))?;
+    }
+    status!(
+        "Indexed {input}: {} lines, {}",
+        header.lines,
+        indexed.join(", ")
+    );
+    Ok(())
+}
+
+/// Builds an index of each input file, for searches to skip the files that can't match.
+pub fn index(args: &IndexArgs) -> Result<()> {
+    let selection = InputSelector::new(&args.files_folder, &args.glob, &args.exclude)?.find()?;
+    for (path, reason) in &selection.excluded {
+        status!("Skipping file {} ({reason})", path.display());
+    }
+    let root = canonical_path(Path::new(&args.files_folder));
+    let options = DecodeOptions {
+        window_log_max: args.zstd_window_log,
+        ..DecodeOptions::default()
+    };
+
+    let failed = AtomicUsize::new(0);
+    thread_pool(args.threads)?.install(|| {
+        selection.inputs.par_iter().for_each(|input| {
+            if let Err(e) = index_input(args, input, &root, &options) {
+                eprintln!("{e:#}");
+                failed.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+    });
+
+    match failed.into_inner() {
+        0 => Ok(()),
+        failed => bail!("{failed} files couldn't be indexed"),
+    }
+}
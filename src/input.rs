@@ -0,0 +1,655 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::DeflateDecoder;
+use glob::{glob, Pattern};
+use memmap2::Mmap;
+use xxhash_rust::xxh3::{xxh3_64, Xxh3};
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::{
+    decode::ZSTD_MAGIC,
+    remote::open_url,
+    source::{Source, ZstdFile},
+};
+
+/// A single compressed stream to be searched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    File(PathBuf),
+    Url(String),
+    Stdin,
+    /// A member of a zip archive, searched and tracked as if it were a separate file.
+    ZipMember {
+        archive: PathBuf,
+        member: String,
+    },
+    /// A file split into numbered parts (`file.zst.001`, `file.zst.002`, ...), which are
+    /// concatenated and tracked under the name without the number.
+    MultiPart {
+        path: PathBuf,
+        parts: Vec<PathBuf>,
+    },
+}
+
+impl Input {
+    /// The path recorded in the management file once this input has been searched. Local
+    /// paths are canonicalized, so that the same file is recorded the same way however it
+    /// was found, and then made relative to `root`, the canonicalized input folder, so that
+    /// the progress still applies if the dataset is moved. Files outside of it are recorded
+    /// by their absolute path.
+    ///
+    /// Stdin isn't tracked, as there's no way to tell whether it'll be the same stream next time.
+    pub fn management_path(&self, root: Option<&Path>) -> Option<PathBuf> {
+        match self {
+            Input::Url(url) => Some(PathBuf::from(url)),
+            // Only the archive is on disk to be resolved; the member's path is inside it.
+            Input::ZipMember { archive, member } => {
+                Some(relative_to(canonical_path(archive), root).join(member))
+            }
+            _ => self
+                .source_path()
+                .map(|path| relative_to(canonical_path(&path), root)),
+        }
+    }
+
+    /// The path of the input as it was found, or the URL it's read from.
+    pub fn source_path(&self) -> Option<PathBuf> {
+        match self {
+            Input::File(path) => Some(path.clone()),
+            Input::Url(url) => Some(PathBuf::from(url)),
+            Input::Stdin => None,
+            Input::ZipMember { archive, member } => Some(archive.join(member)),
+            Input::MultiPart { path, .. } => Some(path.clone()),
+        }
+    }
+
+    /// The size of the input on disk, if known without opening it.
+    pub fn size(&self) -> Option<u64> {
+        match self {
+            Input::File(path) => std::fs::metadata(path).ok().map(|m| m.len()),
+            Input::MultiPart { .. } => self.source_size(),
+            Input::Url(_) | Input::Stdin | Input::ZipMember { .. } => None,
+        }
+    }
+
+    /// The size of the file this input is read from, if it's a local file.
+    pub fn source_size(&self) -> Option<u64> {
+        match self {
+            Input::File(path) | Input::ZipMember { archive: path, .. } => {
+                std::fs::metadata(path).ok().map(|m| m.len())
+            }
+            Input::MultiPart { parts, .. } => parts
+                .iter()
+                .map(|p| std::fs::metadata(p).ok().map(|m| m.len()))
+                .sum(),
+            Input::Url(_) | Input::Stdin => None,
+        }
+    }
+
+    /// A cheap fingerprint of the file's contents, made from its size and a hash of its first
+    /// and last blocks. Only local files can be fingerprinted.
+    pub fn fingerprint(&self) -> Result<Option<String>> {
+        const BLOCK_SIZE: u64 = 1024 * 1024;
+
+        let Input::File(path) = self else {
+            return Ok(None);
+        };
+
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut hasher = Xxh3::new();
+        let mut buf = Vec::new();
+
+        (&mut file).take(BLOCK_SIZE).read_to_end(&mut buf)?;
+        hasher.update(&buf);
+
+        if size > BLOCK_SIZE {
+            buf.clear();
+            file.seek(SeekFrom::Start(
+                size.saturating_sub(BLOCK_SIZE).max(BLOCK_SIZE),
+            ))?;
+            file.read_to_end(&mut buf)?;
+            hasher.update(&buf);
+        }
+
+        Ok(Some(format!("{size:x}-{:016x}", hasher.digest())))
+    }
+
+    /// Memory-maps the input, if it's a local file that isn't compressed.
+    ///
+    /// The file mustn't be changed while it's mapped.
+    pub fn map(&self) -> Result<Option<Mmap>> {
+        let Input::File(path) = self else {
+            return Ok(None);
+        };
+        let file = File::open(path)?;
+        // Empty files can't be mapped, but there's nothing to search in them anyway.
+        if file.metadata()?.len() == 0 {
+            return Ok(None);
+        }
+
+        // SAFETY: The map is only read from, and we've asked for the file not to be changed
+        // while it's being searched.
+        let map = unsafe { Mmap::map(&file)? };
+        if map.starts_with(&ZSTD_MAGIC) {
+            return Ok(None);
+        }
+        Ok(Some(map))
+    }
+}
+
+impl Source for Input {
+    fn open(&self) -> Result<Box<dyn Read + Send>> {
+        match self {
+            Input::File(path) => ZstdFile::new(path.clone()).open(),
+            Input::Url(url) => Ok(Box::new(open_url(url)?)),
+            Input::Stdin => Ok(Box::new(std::io::stdin())),
+            Input::ZipMember { archive, member } => open_zip_member(archive, member),
+            Input::MultiPart { parts, .. } => Ok(Box::new(MultiPartReader {
+                parts: parts.clone().into_iter(),
+                current: None,
+            })),
+        }
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::File(path) => path.display().fmt(f),
+            Input::Url(url) => url.fmt(f),
+            Input::Stdin => "<stdin>".fmt(f),
+            Input::ZipMember { archive, member } => {
+                write!(f, "{}[{member}]", archive.display())
+            }
+            Input::MultiPart { path, parts } => {
+                write!(f, "{} ({} parts)", path.display(), parts.len())
+            }
+        }
+    }
+}
+
+/// Strips `root` from the start of a path, if it's inside it.
+pub fn relative_to(path: PathBuf, root: Option<&Path>) -> PathBuf {
+    match root.and_then(|root| path.strip_prefix(root).ok()) {
+        Some(relative) => relative.to_path_buf(),
+        None => path,
+    }
+}
+
+/// Resolves a path to an absolute one without symlinks, or returns it as it is if that's
+/// not possible. Paths which don't exist themselves, such as the combined name of a split
+/// file, are resolved relative to their folder.
+pub fn canonical_path(path: &Path) -> PathBuf {
+    if let Ok(path) = std::fs::canonicalize(path) {
+        return path;
+    }
+
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return path.to_path_buf();
+    };
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    match std::fs::canonicalize(parent) {
+        Ok(parent) => parent.join(name),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// One of `count` slices of the inputs, numbered from 1, for splitting a search between
+/// machines. Which shard an input is in depends only on its name, so every machine agrees
+/// on it without having to coordinate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Shard {
+    /// Parses `K/N`, e.g. `2/8` for the second of eight shards.
+    pub fn parse(value: &str) -> Result<Self> {
+        let (index, count) = value
+            .split_once('/')
+            .ok_or_else(|| anyhow!("expected a shard such as `1/4`"))?;
+        let index: u64 = index
+            .trim()
+            .parse()
+            .with_context(|| anyhow!("invalid shard number `{index}`"))?;
+        let count: u64 = count
+            .trim()
+            .parse()
+            .with_context(|| anyhow!("invalid shard count `{count}`"))?;
+        if count == 0 || index == 0 || index > count {
+            bail!("shard number must be between 1 and {count}");
+        }
+        Ok(Self { index, count })
+    }
+
+    /// Whether the input with this name, relative to the input folder, is in the shard.
+    pub fn contains(&self, name: &str) -> bool {
+        xxh3_64(name.as_bytes()) % self.count == self.index - 1
+    }
+}
+
+/// The reason given for skipping the files in the other shards.
+pub const OTHER_SHARD: &str = "in another --shard";
+
+/// Finds the input files in a folder.
+pub struct InputSelector {
+    folder: String,
+    glob_pattern: String,
+    /// The parts of split files whose whole name matches `glob_pattern`.
+    parts_pattern: String,
+    exclude: Vec<Pattern>,
+    newer_than: Option<SystemTime>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    shard: Option<Shard>,
+}
+
+/// The result of searching the input folder.
+pub struct Selection {
+    pub inputs: Vec<Input>,
+    /// Files matching the glob which were excluded, along with the reason why.
+    pub excluded: Vec<(PathBuf, &'static str)>,
+}
+
+impl InputSelector {
+    pub fn new(folder: &str, pattern: &str, exclude: &[String]) -> Result<Self> {
+        if !Path::new(folder).is_dir() {
+            bail!("Error: files_folder must be a directory");
+        }
+
+        let exclude = exclude
+            .iter()
+            .map(|p| Pattern::new(p).with_context(|| anyhow!("Invalid exclude pattern `{p}`")))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            folder: folder.to_owned(),
+            glob_pattern: format!("{folder}/{pattern}"),
+            parts_pattern: format!("{folder}/{pattern}.[0-9][0-9]*"),
+            exclude,
+            newer_than: None,
+            min_size: None,
+            max_size: None,
+            shard: None,
+        })
+    }
+
+    /// Only select files whose size in bytes lies within the given bounds.
+    pub fn size_range(mut self, min_size: Option<u64>, max_size: Option<u64>) -> Self {
+        self.min_size = min_size;
+        self.max_size = max_size;
+        self
+    }
+
+    /// Only select files modified after the given time.
+    pub fn newer_than(mut self, time: Option<SystemTime>) -> Self {
+        self.newer_than = time;
+        self
+    }
+
+    /// Only select the files in the given shard.
+    pub fn shard(mut self, shard: Option<Shard>) -> Self {
+        self.shard = shard;
+        self
+    }
+
+    fn exclusion_reason(&self, path: &Path) -> Option<&'static str> {
+        let relative = path.strip_prefix(&self.folder).unwrap_or(path);
+        if self.exclude.iter().any(|p| p.matches_path(relative)) {
+            return Some("excluded");
+        }
+
+        if let Some(shard) = self.shard {
+            // The parts of a split file all go to the shard of the whole file. The name is
+            // taken relative to the input folder, with `/` between folders, so that it's the
+            // same on every machine wherever the folder is.
+            let whole = part_number(path).map(|(base, _)| base);
+            let whole = whole.as_deref().unwrap_or(path);
+            let relative = whole.strip_prefix(&self.folder).unwrap_or(whole);
+            let name = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if !shard.contains(&name) {
+                return Some(OTHER_SHARD);
+            }
+        }
+
+        None
+    }
+
+    /// Checks an input against `--newer-than` and the size bounds. A split file is checked
+    /// as a whole, going by the total size of its parts and when the newest was modified.
+    fn filter_reason(&self, paths: &[PathBuf]) -> Option<&'static str> {
+        if self.newer_than.is_none() && self.min_size.is_none() && self.max_size.is_none() {
+            return None;
+        }
+
+        let mut size = 0;
+        let mut modified = None;
+        for path in paths {
+            let Ok(metadata) = std::fs::metadata(path) else {
+                // Let the error get reported when we try to search it.
+                return None;
+            };
+            size += metadata.len();
+            modified = modified.max(metadata.modified().ok());
+        }
+
+        if let Some(newer_than) = self.newer_than {
+            if modified.is_none_or(|m| m <= newer_than) {
+                return Some("not modified since --newer-than");
+            }
+        }
+
+        if self.min_size.is_some_and(|min| size < min) {
+            return Some("smaller than --min-size");
+        }
+        if self.max_size.is_some_and(|max| size > max) {
+            return Some("larger than --max-size");
+        }
+
+        None
+    }
+
+    pub fn find(&self) -> Result<Selection> {
+        // Split files are found by the name of the whole file, as well as any parts the
+        // pattern matches itself.
+        let mut files = BTreeSet::new();
+        for (pattern, parts) in [(&self.glob_pattern, false), (&self.parts_pattern, true)] {
+            for path in glob(pattern).with_context(|| anyhow!("Error finding input files"))? {
+                let path = path.with_context(|| anyhow!("Error finding input files"))?;
+                if !path.is_dir() && (!parts || part_number(&path).is_some()) {
+                    files.insert(path);
+                }
+            }
+        }
+
+        let mut selection = Selection {
+            inputs: Vec::new(),
+            excluded: Vec::new(),
+        };
+
+        let mut multi_parts: BTreeMap<PathBuf, Vec<(u32, PathBuf)>> = BTreeMap::new();
+        for path in files {
+            if let Some(reason) = self.exclusion_reason(&path) {
+                selection.excluded.push((path, reason));
+            } else if let Some((base, number)) = part_number(&path) {
+                multi_parts.entry(base).or_default().push((number, path));
+            } else if let Some(reason) = self.filter_reason(std::slice::from_ref(&path)) {
+                selection.excluded.push((path, reason));
+            } else if path.extension().is_some_and(|ext| ext == "zip") {
+                match zip_members(&path) {
+                    Ok(members) => selection.inputs.extend(members),
+                    Err(e) => eprintln!("Error opening {}: {e}", path.display()),
+                }
+            } else {
+                selection.inputs.push(Input::File(path));
+            }
+        }
+
+        for (path, mut parts) in multi_parts {
+            parts.sort_unstable_by_key(|(number, _)| *number);
+            let first = parts[0].0;
+            let contiguous = parts
+                .iter()
+                .enumerate()
+                .all(|(i, (number, _))| *number == first + i as u32);
+            if !contiguous || first > 1 {
+                eprintln!("Skipping file {} (missing parts)", path.display());
+                continue;
+            }
+
+            let parts: Vec<_> = parts.into_iter().map(|(_, path)| path).collect();
+            if let Some(reason) = self.filter_reason(&parts) {
+                selection.excluded.push((path, reason));
+                continue;
+            }
+            selection.inputs.push(Input::MultiPart { path, parts });
+        }
+
+        Ok(selection)
+    }
+}
+
+/// If the path is part of a split zstd file (e.g. `file.zst.002`), returns the path of the
+/// whole file and the part number.
+fn part_number(path: &Path) -> Option<(PathBuf, u32)> {
+    let ext = path.extension()?.to_str()?;
+    if ext.len() < 2 || !ext.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let whole = path.with_extension("");
+    if whole.extension()? != "zst" {
+        return None;
+    }
+    Some((whole, ext.parse().ok()?))
+}
+
+/// Reads each part of a split file in turn, opening them as they're needed.
+struct MultiPartReader {
+    parts: std::vec::IntoIter<PathBuf>,
+    current: Option<File>,
+}
+
+impl Read for MultiPartReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let file = match &mut self.current {
+                Some(file) => file,
+                None => match self.parts.next() {
+                    Some(path) => self.current.insert(File::open(path)?),
+                    None => return Ok(0),
+                },
+            };
+
+            match file.read(buf)? {
+                0 if !buf.is_empty() => self.current = None,
+                read => return Ok(read),
+            }
+        }
+    }
+}
+
+/// Lists the files contained in a zip archive as separate inputs.
+fn zip_members(archive: &Path) -> Result<Vec<Input>> {
+    let zip = ZipArchive::new(File::open(archive)?)?;
+    let members = zip
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(|member| Input::ZipMember {
+            archive: archive.to_path_buf(),
+            member: member.to_owned(),
+        })
+        .collect();
+    Ok(members)
+}
+
+fn open_zip_member(archive: &Path, member: &str) -> Result<Box<dyn Read + Send>> {
+    // `ZipFile` borrows the archive, so instead we find where the member's data lives and
+    // read it directly from the file.
+    let mut zip = ZipArchive::new(File::open(archive)?)?;
+    let entry = zip.by_name(member)?;
+    if entry.encrypted() {
+        bail!("member `{member}` is encrypted");
+    }
+    let data_start = entry.data_start();
+    let compressed_size = entry.compressed_size();
+    let compression = entry.compression();
+    drop(entry);
+
+    let mut file = zip.into_inner();
+    file.seek(SeekFrom::Start(data_start))?;
+    let data = file.take(compressed_size);
+
+    match compression {
+        CompressionMethod::Stored => Ok(Box::new(data)),
+        CompressionMethod::Deflated => Ok(Box::new(DeflateDecoder::new(data))),
+        // Zstd-compressed members are picked up by the magic number check in `open_decoded`.
+        CompressionMethod::ZSTD => Ok(Box::new(data)),
+        method => bail!("member `{member}` uses unsupported compression {method}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_numbered_zst_files_are_parts() {
+        assert_eq!(
+            part_number(Path::new("dumps/file.zst.002")),
+            Some((PathBuf::from("dumps/file.zst"), 2))
+        );
+        assert_eq!(
+            part_number(Path::new("file.jsonl.zst.010")),
+            Some((PathBuf::from("file.jsonl.zst"), 10))
+        );
+        assert_eq!(part_number(Path::new("data.20")), None);
+        assert_eq!(part_number(Path::new("file.jsonl.001")), None);
+        assert_eq!(part_number(Path::new("file.zst.1")), None);
+        assert_eq!(part_number(Path::new("file.zst.01a")), None);
+        assert_eq!(part_number(Path::new("file.zst")), None);
+    }
+
+    #[test]
+    fn parses_shards() {
+        assert_eq!(Shard::parse("2/8").unwrap(), Shard { index: 2, count: 8 });
+        assert_eq!(
+            Shard::parse(" 1 / 1 ").unwrap(),
+            Shard { index: 1, count: 1 }
+        );
+        for shard in ["0/4", "5/4", "1/0", "1", "a/4", "1/b", "-1/4"] {
+            assert!(Shard::parse(shard).is_err(), "`{shard}` was accepted");
+        }
+    }
+
+    #[test]
+    fn every_input_is_in_exactly_one_shard() {
+        let shards: Vec<_> = (1..=4).map(|index| Shard { index, count: 4 }).collect();
+        let mut sizes = [0; 4];
+        for i in 0..400 {
+            let name = format!("dumps/{i:04}.jsonl.zst");
+            let containing: Vec<_> = (0..4).filter(|&s| shards[s].contains(&name)).collect();
+            assert_eq!(containing.len(), 1, "{name} is in shards {containing:?}");
+            sizes[containing[0]] += 1;
+            // It only depends on the name, so every machine agrees.
+            assert!(shards[containing[0]].contains(&name));
+        }
+        // Roughly even, rather than everything landing in one shard.
+        assert!(sizes.iter().all(|&size| size > 50), "{sizes:?}");
+    }
+
+    #[test]
+    fn finds_split_files_with_default_glob() {
+        let folder =
+            std::env::temp_dir().join(format!("ytmetasearch-parts-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(folder.join("sub")).unwrap();
+        for name in [
+            "whole.zst",
+            "sub/split.zst.001",
+            "sub/split.zst.002",
+            "gap.zst.001",
+            "gap.zst.003",
+            "data.20",
+        ] {
+            std::fs::write(folder.join(name), "").unwrap();
+        }
+
+        let selector = InputSelector::new(folder.to_str().unwrap(), "**/*.zst", &[]).unwrap();
+        let selection = selector.find();
+        std::fs::remove_dir_all(&folder).unwrap();
+        let mut inputs = selection.unwrap().inputs;
+        inputs.sort_by_key(|input| input.to_string());
+        assert_eq!(
+            inputs,
+            [
+                Input::MultiPart {
+                    path: folder.join("sub/split.zst"),
+                    parts: vec![
+                        folder.join("sub/split.zst.001"),
+                        folder.join("sub/split.zst.002")
+                    ],
+                },
+                Input::File(folder.join("whole.zst")),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_parts_matched_by_glob_once() {
+        let folder =
+            std::env::temp_dir().join(format!("ytmetasearch-glob-parts-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        for name in ["split.zst.001", "split.zst.002", "data.20"] {
+            std::fs::write(folder.join(name), "").unwrap();
+        }
+
+        let selector = InputSelector::new(folder.to_str().unwrap(), "*", &[]).unwrap();
+        let selection = selector.find();
+        std::fs::remove_dir_all(&folder).unwrap();
+        let mut inputs = selection.unwrap().inputs;
+        inputs.sort_by_key(|input| input.to_string());
+        assert_eq!(
+            inputs,
+            [
+                Input::File(folder.join("data.20")),
+                Input::MultiPart {
+                    path: folder.join("split.zst"),
+                    parts: vec![folder.join("split.zst.001"), folder.join("split.zst.002")],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_split_files_as_a_whole() {
+        let folder =
+            std::env::temp_dir().join(format!("ytmetasearch-filter-parts-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        for (name, size) in [
+            ("split.zst.001", 100),
+            ("split.zst.002", 10),
+            ("small.zst", 10),
+        ] {
+            std::fs::write(folder.join(name), vec![0; size]).unwrap();
+        }
+
+        let find = |min_size, max_size| {
+            let selector = InputSelector::new(folder.to_str().unwrap(), "*.zst", &[])
+                .unwrap()
+                .size_range(min_size, max_size);
+            let selection = selector.find().unwrap();
+            let mut excluded: Vec<_> = selection.excluded.into_iter().map(|(p, _)| p).collect();
+            excluded.sort();
+            (selection.inputs.len(), excluded)
+        };
+        // The second part is smaller than the bound, but the file as a whole isn't.
+        let (inputs, excluded) = find(Some(50), None);
+        assert_eq!(inputs, 1);
+        assert_eq!(excluded, [folder.join("small.zst")]);
+        // Each part fits under the bound, but the whole file doesn't.
+        let (inputs, excluded) = find(None, Some(105));
+        assert_eq!(inputs, 1);
+        assert_eq!(excluded, [folder.join("split.zst")]);
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
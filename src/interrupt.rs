@@ -0,0 +1,84 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Set once the user has asked us to stop.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Set if we're stopping because the time limit was reached.
+static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+/// Set if we're stopping because an input failed with `--fail-fast`.
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Whether Ctrl-C or SIGTERM has been received.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Stops the search as Ctrl-C would, for when it's asked for some other way.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// Stops the search as Ctrl-C would once the time limit is up.
+pub fn stop_after(limit: Duration) {
+    std::thread::spawn(move || {
+        std::thread::sleep(limit);
+        if !interrupted() {
+            TIMED_OUT.store(true, Ordering::Relaxed);
+            interrupt();
+        }
+    });
+}
+
+/// Whether we're stopping because the time limit given to [`stop_after`] was reached.
+pub fn timed_out() -> bool {
+    TIMED_OUT.load(Ordering::Relaxed)
+}
+
+/// Stops the search as Ctrl-C would because an input couldn't be searched.
+pub fn stop_on_failure() {
+    if !interrupted() {
+        FAILED.store(true, Ordering::Relaxed);
+        interrupt();
+    }
+}
+
+/// Whether we're stopping because of [`stop_on_failure`].
+pub fn failed() -> bool {
+    FAILED.load(Ordering::Relaxed)
+}
+
+/// Sleeps for the given time, waking early if interrupted.
+pub fn sleep(duration: Duration) {
+    let end = Instant::now() + duration;
+    while !interrupted() {
+        let remaining = end.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return;
+        }
+        std::thread::sleep(remaining.min(Duration::from_millis(100)));
+    }
+}
+
+/// Catches Ctrl-C and SIGTERM, so that the search can stop cleanly with its progress saved
+/// rather than being killed part way through. A second Ctrl-C kills it straight away.
+#[cfg(unix)]
+pub fn install_handler() {
+    extern "C" fn handle(signal: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+        // Only storing the flag and resetting the handler are safe to do in here.
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+    }
+
+    let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_handler() {}
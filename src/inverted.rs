@@ -0,0 +1,280 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use serde_json::{Map, Value};
+
+use crate::{
+    index::{read_u32, read_u64, write_index_file, Header},
+    lines::SliceLines,
+};
+
+/// Identifies an inverted index file, and the version of its format.
+pub const INVERTED_MAGIC: &[u8; 8] = b"YTMSINV\x01";
+
+/// The field that lines which aren't JSON objects are indexed under, as a whole.
+const WHOLE_LINE: &str = "";
+
+/// Splits the text into runs of alphanumeric characters, returning where each starts and
+/// ends. An expression found in the text has the same tokens in the middle, and its first
+/// and last tokens are part of the text's.
+fn token_ranges(text: &str) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                ranges.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        ranges.push((s, text.len()));
+    }
+    ranges
+}
+
+/// The text of each of the record's top-level fields. Strings are unescaped, and anything
+/// else is left as JSON. Lines which aren't JSON objects are treated as one field.
+pub fn field_texts(line: &str) -> Vec<(String, String)> {
+    match serde_json::from_str::<Map<String, Value>>(line) {
+        Ok(record) => record
+            .into_iter()
+            .map(|(field, value)| match value {
+                Value::String(text) => (field, text),
+                value => (field, value.to_string()),
+            })
+            .collect(),
+        Err(_) => vec![(WHOLE_LINE.to_owned(), line.trim_end().to_owned())],
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(reader: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::other("invalid varint"))
+}
+
+fn write_string(out: &mut impl Write, text: &str) -> io::Result<()> {
+    out.write_all(&(text.len() as u32).to_le_bytes())?;
+    out.write_all(text.as_bytes())
+}
+
+fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    let mut bytes = vec![0; read_u32(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| io::Error::other("invalid string"))
+}
+
+/// Collects the lines each token appears in, for each field, while the input is read.
+#[derive(Default)]
+pub struct InvertedIndexBuilder {
+    postings: HashMap<String, HashMap<String, Vec<u64>>>,
+}
+
+impl InvertedIndexBuilder {
+    /// Indexes a batch of whole lines, starting with line `first_line` counting from 0.
+    pub fn add(&mut self, lines: &[u8], first_line: u64) {
+        for (line_number, line) in (first_line..).zip(SliceLines::new(lines)) {
+            for (field, text) in field_texts(&String::from_utf8_lossy(line)) {
+                let postings = self.postings.entry(field).or_default();
+                for (start, end) in token_ranges(&text) {
+                    let lines = postings
+                        .entry(text[start..end].to_ascii_lowercase())
+                        .or_default();
+                    if lines.last() != Some(&line_number) {
+                        lines.push(line_number);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The number of distinct tokens, counting each field separately.
+    pub fn tokens(&self) -> usize {
+        self.postings.values().map(HashMap::len).sum()
+    }
+
+    /// Writes out the index: the header, then the compressed dictionary of where each
+    /// token's lines are, then the lines themselves, as delta-encoded varints.
+    pub fn write(self, path: &Path, header: &Header) -> io::Result<()> {
+        let mut fields: Vec<_> = self.postings.into_iter().collect();
+        fields.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        let mut dictionary = Vec::new();
+        let mut postings = Vec::new();
+        dictionary.write_all(&(fields.len() as u32).to_le_bytes())?;
+        for (field, tokens) in fields {
+            let mut tokens: Vec<_> = tokens.into_iter().collect();
+            tokens.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+            write_string(&mut dictionary, &field)?;
+            dictionary.write_all(&(tokens.len() as u32).to_le_bytes())?;
+            for (token, lines) in tokens {
+                write_string(&mut dictionary, &token)?;
+                dictionary.write_all(&(postings.len() as u64).to_le_bytes())?;
+                dictionary.write_all(&(lines.len() as u32).to_le_bytes())?;
+                let mut last = 0;
+                for line in lines {
+                    write_varint(&mut postings, line - last);
+                    last = line;
+                }
+            }
+        }
+        let dictionary = zstd::encode_all(&dictionary[..], 3)?;
+
+        write_index_file(path, |file| {
+            header.write(file, INVERTED_MAGIC)?;
+            file.write_all(&(dictionary.len() as u64).to_le_bytes())?;
+            file.write_all(&dictionary)?;
+            file.write_all(&postings)
+        })
+    }
+}
+
+/// Where a token's lines are in the postings, and how many there are.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    offset: u64,
+    count: u32,
+}
+
+/// An inverted index read back from disk. Only the dictionary is loaded, and the lines
+/// for each token are read as they're needed.
+pub struct InvertedIndex {
+    file: BufReader<File>,
+    /// Where the postings start in the file.
+    postings_start: u64,
+    fields: BTreeMap<String, BTreeMap<String, Entry>>,
+}
+
+impl InvertedIndex {
+    pub fn open(path: &Path) -> io::Result<(Header, Self)> {
+        let mut file = BufReader::new(File::open(path)?);
+        let header = Header::read(&mut file, INVERTED_MAGIC)?;
+        let dictionary_len = read_u64(&mut file)?;
+        let postings_start = file.stream_position()? + dictionary_len;
+        let mut dictionary = zstd::Decoder::new((&mut file).take(dictionary_len))?;
+
+        let mut fields = BTreeMap::new();
+        for _ in 0..read_u32(&mut dictionary)? {
+            let field = read_string(&mut dictionary)?;
+            let mut tokens = BTreeMap::new();
+            for _ in 0..read_u32(&mut dictionary)? {
+                let token = read_string(&mut dictionary)?;
+                let offset = read_u64(&mut dictionary)?;
+                let count = read_u32(&mut dictionary)?;
+                tokens.insert(token, Entry { offset, count });
+            }
+            fields.insert(field, tokens);
+        }
+        drop(dictionary);
+
+        Ok((
+            header,
+            Self {
+                file,
+                postings_start,
+                fields,
+            },
+        ))
+    }
+
+    fn lines(&mut self, entry: Entry) -> io::Result<Vec<u64>> {
+        self.file
+            .seek(SeekFrom::Start(self.postings_start + entry.offset))?;
+        let mut lines = Vec::with_capacity(entry.count as usize);
+        let mut line = 0;
+        for _ in 0..entry.count {
+            line += read_varint(&mut self.file)?;
+            lines.push(line);
+        }
+        Ok(lines)
+    }
+
+    /// The lines, counting from 0, which might have the expression in the given field, or
+    /// any field. Returns `None` if the expression has no tokens to look up, so every line
+    /// might.
+    pub fn candidates(
+        &mut self,
+        expression: &str,
+        field: Option<&str>,
+    ) -> io::Result<Option<BTreeSet<u64>>> {
+        let expression = expression.to_ascii_lowercase();
+        let ranges = token_ranges(&expression);
+        if ranges.is_empty() {
+            return Ok(None);
+        }
+
+        // For each field, the entries of the tokens which could hold each of the
+        // expression's tokens. Where the expression starts or ends part way through a
+        // token, any token ending or starting with that part could.
+        let mut wanted: Vec<Vec<Vec<Entry>>> = Vec::new();
+        for (name, tokens) in &self.fields {
+            if field.is_some_and(|field| field != name) {
+                continue;
+            }
+            let matching = |&(start, end): &(usize, usize)| -> Vec<Entry> {
+                let token = &expression[start..end];
+                let entries = tokens.iter();
+                match (start == 0, end == expression.len()) {
+                    (false, false) => tokens.get(token).copied().into_iter().collect(),
+                    (false, true) => tokens
+                        .range(token.to_owned()..)
+                        .take_while(|(t, _)| t.starts_with(token))
+                        .map(|(_, e)| *e)
+                        .collect(),
+                    (true, false) => entries
+                        .filter(|(t, _)| t.ends_with(token))
+                        .map(|(_, e)| *e)
+                        .collect(),
+                    (true, true) => entries
+                        .filter(|(t, _)| t.contains(token))
+                        .map(|(_, e)| *e)
+                        .collect(),
+                }
+            };
+            wanted.push(ranges.iter().map(matching).collect());
+        }
+
+        let mut candidates = BTreeSet::new();
+        for tokens in wanted {
+            let mut in_field: Option<BTreeSet<u64>> = None;
+            for entries in tokens {
+                let mut lines = BTreeSet::new();
+                for entry in entries {
+                    lines.extend(self.lines(entry)?);
+                }
+                in_field = Some(match in_field {
+                    Some(found) => found.intersection(&lines).copied().collect(),
+                    None => lines,
+                });
+                if in_field.as_ref().is_some_and(BTreeSet::is_empty) {
+                    break;
+                }
+            }
+            candidates.extend(in_field.unwrap_or_default());
+        }
+        Ok(Some(candidates))
+    }
+}
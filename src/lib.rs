@@ -0,0 +1,506 @@
+//! Searches large, compressed JSON lines dumps for records containing any of a set of
+//! expressions, as the `ytmetasearch` binary does.
+//!
+//! The binary's `search` command adds a lot around the search itself: output files and
+//! formats, resuming from a management file, deduplication and so on. For embedding the
+//! search in something else, the engine is available on its own:
+//!
+//! - [`QuerySet`] loads the queries from a query file.
+//! - [`Searcher`] builds the matchers for the queries, and searches lines, streams and inputs
+//!   with them, handing the matches to a [`MatchSink`].
+//! - [`InputSelector`] finds the [`Input`]s in a folder. These, like anything else which
+//!   implements [`Source`], can be opened and decompressed into a stream of lines, with
+//!   [`DecodeOptions`].
+//! - [`Management`] is the record of the files a search has completed, as kept in a
+//!   management file.
+//!
+//! ```no_run
+//! use ytmetasearch::{DecodeOptions, InputSelector, QuerySet, Searcher};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let searcher = Searcher::new(QuerySet::load("queries.json".as_ref())?);
+//! let selection = InputSelector::new("dumps", "**/*.zst", &[])?.find()?;
+//! for input in &selection.inputs {
+//!     let mut print = |query: usize, line: &[u8]| {
+//!         let filename = &searcher.queries()[query].filename;
+//!         println!("{filename}: {}", String::from_utf8_lossy(line));
+//!         Ok(())
+//!     };
+//!     searcher.search_source(input, &DecodeOptions::default(), &mut print)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::HashMap,
+    io::BufRead,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+mod aggregate;
+mod bench;
+mod categories;
+mod channels;
+/// The command line interface of the `ytmetasearch` binary.
+pub mod cli;
+mod config;
+mod decode;
+mod dedup;
+mod display;
+mod elastic;
+mod error_log;
+mod estimate;
+mod frames;
+mod histogram;
+mod index;
+mod input;
+mod interrupt;
+mod inverted;
+mod lines;
+mod logging;
+mod malformed;
+mod manage;
+mod management;
+mod management_db;
+mod merge;
+mod notify;
+mod output;
+mod pipeline;
+mod pool;
+mod prefilter;
+mod progress_file;
+mod query;
+mod record;
+mod remote;
+mod report;
+mod sample;
+mod schema;
+mod search;
+mod serve;
+mod sink;
+mod sort;
+mod source;
+mod stats;
+mod tags;
+mod top;
+mod tui;
+mod validate;
+mod writer;
+
+pub use decode::DecodeOptions;
+pub use input::{Input, InputSelector, Selection, Shard};
+pub use management::{
+    lock_management, write_management, Change, FileStats, Management, ResumePoint,
+};
+pub use source::{decode_zstd, InMemory, Source, ZstdFile};
+
+use categories::CategoryFilter;
+use channels::ChannelList;
+use lines::{count_lines, Lines, ReaderLines};
+use output::Template;
+use prefilter::Prefilter;
+use record::RecordView;
+use tags::TagMatch;
+
+/// Prints a status message, unless `--quiet` is given.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::LogLevel::Info) {
+            $crate::logging::print_status($crate::logging::LogLevel::Info, format!($($arg)*));
+        }
+    };
+}
+pub(crate) use status;
+
+/// Prints a detailed status message, only shown with `--verbose`.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::LogLevel::Debug) {
+            $crate::logging::print_status($crate::logging::LogLevel::Debug, format!($($arg)*));
+        }
+    };
+}
+pub(crate) use debug;
+
+/// Parses a size in bytes, with an optional binary suffix (`K`, `M`, `G`, `T`).
+fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let digits_end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, suffix) = value.split_at(digits_end);
+    let number: u64 = number
+        .parse()
+        .with_context(|| anyhow!("expected a size such as `100M`"))?;
+
+    let suffix = suffix.trim().to_ascii_uppercase();
+    let multiplier: u64 = match suffix.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => bail!("unknown size suffix `{suffix}`"),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("size too large"))
+}
+
+/// One of the queries from a query file.
+#[derive(Debug, Deserialize)]
+pub struct Query {
+    /// The file the query's matches are written to, relative to the output folder.
+    pub filename: String,
+    /// A line matches the query if it contains any of these, ignoring ASCII case.
+    pub expressions: Vec<String>,
+    /// Only write the first record seen with each `id`, across all input files.
+    #[serde(default)]
+    pub dedup: bool,
+    /// Only keep these top-level keys of each matched record in the output.
+    #[serde(default)]
+    pub output_fields: Vec<String>,
+    /// Write each match using this template instead of the `--output-format`, e.g.
+    /// `{id}\t{title}`.
+    #[serde(default)]
+    pub(crate) template: Option<Template>,
+    /// Write the lines which match none of the expressions, instead of those which match.
+    #[serde(default)]
+    pub invert: bool,
+    /// Only consider the records from the channels in this file, a text file with a
+    /// channel ID on each line or a CSV file with a `channel_id` column. Relative paths are
+    /// relative to the query file. An inverted query with no expressions writes every
+    /// record from the channels, and likewise for `categories`.
+    #[serde(default)]
+    pub channel_list: Option<PathBuf>,
+    /// The channels read from the `channel_list`, or `--channel-list`.
+    #[serde(skip)]
+    pub(crate) channels: Option<Arc<ChannelList>>,
+    /// Only consider the records in these categories, given by their YouTube category IDs
+    /// (e.g. `"25"`) or names (e.g. `"News & Politics"`), which are checked against the
+    /// record's `categories` rather than looked for in the line.
+    #[serde(default)]
+    pub(crate) categories: Option<CategoryFilter>,
+    /// Match the expressions against each of the record's `tags` instead of anywhere in the
+    /// line: either the whole tag (`"exact"`) or any part of it (`"substring"`).
+    #[serde(default)]
+    pub(crate) match_tags: Option<TagMatch>,
+}
+
+impl Query {
+    /// Whether the query is limited to some channels or categories.
+    pub(crate) fn filters_records(&self) -> bool {
+        self.channels.is_some() || self.categories.is_some()
+    }
+
+    /// Whether a line might match the query, going by whether it contains any of the
+    /// expressions, before its record is looked at.
+    pub(crate) fn may_match(&self, line_matched: bool) -> bool {
+        // An inverted query matching tags can still match a line containing an expression,
+        // if it isn't in any of the tags.
+        line_matched != self.invert || (line_matched && self.match_tags.is_some())
+    }
+
+    /// Whether the record matches the query, given whether its line contains any of the
+    /// expressions, which `searcher` was built from.
+    pub(crate) fn matches(
+        &self,
+        line_matched: bool,
+        record: &RecordView,
+        searcher: &AhoCorasick,
+    ) -> bool {
+        // A tag can't contain an expression unless the line does, so only those lines' tags
+        // need looking at.
+        let matched = line_matched
+            && self
+                .match_tags
+                .is_none_or(|tags| tags.matches(record, searcher));
+        matched != self.invert
+            && self
+                .channels
+                .as_ref()
+                .is_none_or(|channels| channels.contains(record))
+            && self
+                .categories
+                .as_ref()
+                .is_none_or(|categories| categories.contains(record))
+    }
+
+    /// [`Query::matches`], for a line which hasn't been parsed yet.
+    pub(crate) fn matches_line(
+        &self,
+        line_matched: bool,
+        line: &[u8],
+        searcher: &AhoCorasick,
+    ) -> bool {
+        if !self.filters_records() && self.match_tags.is_none() {
+            return line_matched != self.invert;
+        }
+        self.may_match(line_matched)
+            && std::str::from_utf8(line)
+                .is_ok_and(|line| self.matches(line_matched, &RecordView::new(line), searcher))
+    }
+}
+
+/// Reads the queries from the query file, returning its contents along with them.
+fn load_queries(path: &Path) -> Result<(String, Vec<Query>)> {
+    let query_file =
+        std::fs::read_to_string(path).with_context(|| anyhow!("Error opening query file"))?;
+    let mut queries = parse_queries(&query_file)?;
+    load_channel_lists(&mut queries, path.parent().unwrap_or(Path::new("")))?;
+    Ok((query_file, queries))
+}
+
+/// Reads the queries' channel lists, with relative paths taken from `base`. Queries sharing a
+/// list share its channels.
+fn load_channel_lists(queries: &mut [Query], base: &Path) -> Result<()> {
+    let mut lists: HashMap<PathBuf, Arc<ChannelList>> = HashMap::new();
+    for query in queries {
+        let Some(path) = &query.channel_list else {
+            continue;
+        };
+        let path = base.join(path);
+        let channels = match lists.get(&path) {
+            Some(channels) => channels.clone(),
+            None => {
+                let channels = Arc::new(ChannelList::load(&path)?);
+                lists.insert(path, channels.clone());
+                channels
+            }
+        };
+        query.channels = Some(channels);
+    }
+    Ok(())
+}
+
+fn parse_queries(query_file: &str) -> Result<Vec<Query>> {
+    let queries: Vec<Query> =
+        serde_json::from_str(query_file).with_context(|| anyhow!("Error parsing query file"))?;
+    // Where each output file is first used, as queries sharing one would have their matches
+    // mixed together, and their counts and progress mixed up.
+    let mut outputs: HashMap<PathBuf, usize> = HashMap::new();
+    for (i, query) in queries.iter().enumerate() {
+        // Filenames can include subfolders, but have to stay inside the output folder.
+        let path = Path::new(&query.filename);
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            bail!(
+                "Query filename `{}` must be a relative path within the output folder",
+                query.filename
+            );
+        }
+        // Compared by their components, so that `a//b.jsonl` is the same as `a/b.jsonl`.
+        if let Some(first) = outputs.insert(path.components().collect(), i) {
+            bail!(
+                "Queries {} and {} both have the filename `{}`, but each query needs its own",
+                first + 1,
+                i + 1,
+                query.filename
+            );
+        }
+    }
+    Ok(queries)
+}
+
+/// How the queries' expressions are matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Automaton {
+    /// Quick to build and small, but slower to search with.
+    #[default]
+    Nfa,
+    /// Faster to search with, but slower to build and can use a lot more memory with many
+    /// expressions.
+    Dfa,
+}
+
+fn build_searchers(queries: &[Query], automaton: Automaton) -> Vec<AhoCorasick> {
+    queries
+        .iter()
+        .map(|q| {
+            AhoCorasickBuilder::new()
+                .ascii_case_insensitive(true)
+                .dfa(automaton == Automaton::Dfa)
+                .build(&q.expressions)
+        })
+        .collect()
+}
+
+fn search_line(line: &[u8], queries: &[AhoCorasick], does_match: &mut [bool]) {
+    for (does_match, query) in does_match.iter_mut().zip(queries) {
+        *does_match = query.is_match(line);
+    }
+}
+
+/// Builds a thread pool with the given number of threads, or one per CPU core.
+fn thread_pool(threads: Option<usize>) -> Result<rayon::ThreadPool> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.unwrap_or(0))
+        .build()
+        .with_context(|| anyhow!("Error starting threads"))
+}
+
+/// The queries to search for, as given by a query file: a JSON array of objects, each with
+/// the `filename` its matches go to and the `expressions` to look for.
+#[derive(Debug)]
+pub struct QuerySet {
+    queries: Vec<Query>,
+}
+
+impl QuerySet {
+    /// Reads the queries from a query file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let (_, queries) = load_queries(path)?;
+        Ok(Self { queries })
+    }
+
+    /// Parses the queries from the contents of a query file. Relative `channel_list` paths
+    /// are relative to the current folder.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let mut queries = parse_queries(json)?;
+        load_channel_lists(&mut queries, Path::new(""))?;
+        Ok(Self { queries })
+    }
+
+    pub fn queries(&self) -> &[Query] {
+        &self.queries
+    }
+}
+
+/// Where a [`Searcher`] hands the lines matched by its queries.
+///
+/// Closures taking the query's index and the line can be used as sinks.
+pub trait MatchSink {
+    /// Called with each line a query matches, in the order they're found, along with the
+    /// query's index in the [`QuerySet`]. The line doesn't include its line ending. A line
+    /// matching several queries is given once for each. Returning an error stops the search.
+    fn matched(&mut self, query: usize, line: &[u8]) -> Result<()>;
+}
+
+impl<F: FnMut(usize, &[u8]) -> Result<()>> MatchSink for F {
+    fn matched(&mut self, query: usize, line: &[u8]) -> Result<()> {
+        self(query, line)
+    }
+}
+
+/// The totals from a [`Searcher`]'s search of a stream.
+#[derive(Debug, Clone, Default)]
+pub struct SearchStats {
+    pub lines: u64,
+    /// Decompressed bytes read.
+    pub bytes: u64,
+    /// The number of matches for each query.
+    pub matches: Vec<u64>,
+}
+
+/// Matches lines against the queries in a [`QuerySet`] as the `search` command does, without
+/// any of the formatting or bookkeeping around it.
+pub struct Searcher {
+    queries: QuerySet,
+    searchers: Vec<AhoCorasick>,
+    prefilter: Prefilter,
+}
+
+impl Searcher {
+    pub fn new(queries: QuerySet) -> Self {
+        Self::with_automaton(queries, Automaton::default())
+    }
+
+    /// Builds the matchers with the given kind of automaton, trading memory and build time
+    /// for search speed.
+    pub fn with_automaton(queries: QuerySet, automaton: Automaton) -> Self {
+        Self {
+            searchers: build_searchers(&queries.queries, automaton),
+            prefilter: Prefilter::new(&queries.queries, automaton),
+            queries,
+        }
+    }
+
+    pub fn queries(&self) -> &[Query] {
+        &self.queries.queries
+    }
+
+    /// The indexes of the queries which match the line.
+    pub fn matching<'a>(&'a self, line: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        self.searchers
+            .iter()
+            .zip(&self.queries.queries)
+            .enumerate()
+            .filter(move |(_, (searcher, query))| {
+                query.matches_line(searcher.is_match(line), line, searcher)
+            })
+            .map(|(i, _)| i)
+    }
+
+    /// Searches each line read from the reader, handing the matches to the sink.
+    pub fn search_reader(
+        &self,
+        reader: impl BufRead,
+        sink: &mut impl MatchSink,
+    ) -> Result<SearchStats> {
+        let queries = &self.queries.queries;
+        let mut stats = SearchStats {
+            matches: vec![0; queries.len()],
+            ..SearchStats::default()
+        };
+        let mut lines = ReaderLines::new(reader);
+        let mut does_match = vec![false; queries.len()];
+        while let Some(batch) = lines.next_batch()? {
+            stats.lines += count_lines(batch);
+            stats.bytes += batch.len() as u64;
+            for (_, line) in self.prefilter.candidates(batch) {
+                let line = line.strip_suffix(b"\n").unwrap_or(line);
+                search_line(line, &self.searchers, &mut does_match);
+                for (i, query) in queries.iter().enumerate() {
+                    if query.matches_line(does_match[i], line, &self.searchers[i]) {
+                        stats.matches[i] += 1;
+                        sink.matched(i, line)?;
+                    }
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Opens and decodes the source's lines, and searches them as [`search_reader`] does.
+    ///
+    /// [`search_reader`]: Searcher::search_reader
+    pub fn search_source(
+        &self,
+        source: &dyn Source,
+        decode_options: &DecodeOptions,
+        sink: &mut impl MatchSink,
+    ) -> Result<SearchStats> {
+        let reader = source.lines(decode_options)?;
+        self.search_reader(reader, sink)
+            .with_context(|| anyhow!("Error searching {source}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_take_binary_suffixes() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("4K").unwrap(), 4 << 10);
+        assert_eq!(parse_size("100M").unwrap(), 100 << 20);
+        assert_eq!(parse_size(" 2g ").unwrap(), 2 << 30);
+        assert_eq!(parse_size("1T").unwrap(), 1 << 40);
+        assert_eq!(parse_size("3 MB").unwrap(), 3 << 20);
+        assert_eq!(parse_size("3MiB").unwrap(), 3 << 20);
+        assert_eq!(parse_size("10B").unwrap(), 10);
+    }
+
+    #[test]
+    fn invalid_sizes_are_refused() {
+        for size in ["", "M", "1.5G", "10X", "-1", "20000000T"] {
+            assert!(parse_size(size).is_err(), "`{size}` was accepted");
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::{self, BufRead};
+
+use memchr::{memchr, memchr_iter, memrchr};
+
+/// Roughly how much text to hand out in each batch of lines, where it's up to us.
+const BATCH_SIZE: usize = 16 * 1024;
+
+/// Counts the lines in the text, including a last line without a line ending.
+pub fn count_lines(text: &[u8]) -> u64 {
+    let mut lines = memchr_iter(b'\n', text).count() as u64;
+    if !text.is_empty() && !text.ends_with(b"\n") {
+        lines += 1;
+    }
+    lines
+}
+
+/// Somewhere to read lines from, in batches of whole lines so that the bookkeeping can be
+/// done once per batch rather than once per line.
+pub trait Lines {
+    /// Reads the next batch of lines, each including its line ending, returning `None` at
+    /// the end.
+    fn next_batch(&mut self) -> io::Result<Option<&[u8]>>;
+}
+
+/// Reads the lines of a stream, handing out the whole lines in its buffer at a time.
+pub struct ReaderLines<R> {
+    reader: R,
+    /// How much of the reader's buffer was handed out in the last batch.
+    consumed: usize,
+    /// Holds lines too long to fit in the reader's buffer.
+    long_line: Vec<u8>,
+}
+
+impl<R: BufRead> ReaderLines<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            consumed: 0,
+            long_line: Vec::new(),
+        }
+    }
+}
+
+impl<R: BufRead> Lines for ReaderLines<R> {
+    fn next_batch(&mut self) -> io::Result<Option<&[u8]>> {
+        self.reader.consume(std::mem::take(&mut self.consumed));
+
+        let buf = self.reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        match memrchr(b'\n', buf) {
+            Some(end) => {
+                self.consumed = end + 1;
+                // Filling the buffer again just returns what's already in it.
+                Ok(Some(&self.reader.fill_buf()?[..=end]))
+            }
+            // The line doesn't fit in the buffer, or is the last one and has no line ending.
+            None => {
+                self.long_line.clear();
+                self.reader.read_until(b'\n', &mut self.long_line)?;
+                Ok(Some(&self.long_line))
+            }
+        }
+    }
+}
+
+/// Splits the lines out of text that's already in memory, without copying them.
+pub struct SliceLines<'a> {
+    text: &'a [u8],
+}
+
+impl<'a> SliceLines<'a> {
+    pub fn new(text: &'a [u8]) -> Self {
+        Self { text }
+    }
+}
+
+impl<'a> Iterator for SliceLines<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.text.is_empty() {
+            return None;
+        }
+        let end = memchr(b'\n', self.text).map_or(self.text.len(), |i| i + 1);
+        let (line, rest) = self.text.split_at(end);
+        self.text = rest;
+        Some(line)
+    }
+}
+
+impl Lines for SliceLines<'_> {
+    fn next_batch(&mut self) -> io::Result<Option<&[u8]>> {
+        if self.text.is_empty() {
+            return Ok(None);
+        }
+        let end = match self.text.get(BATCH_SIZE..) {
+            Some(rest) => memchr(b'\n', rest).map_or(self.text.len(), |i| BATCH_SIZE + i + 1),
+            None => self.text.len(),
+        };
+        let (batch, rest) = self.text.split_at(end);
+        self.text = rest;
+        Ok(Some(batch))
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        OnceLock,
+    },
+    time::{Duration, SystemTime},
+};
+
+use serde::{Serialize, Serializer};
+
+use crate::{display, report::Summary, tui};
+
+/// Set when matches are streamed to stdout, so that status messages go to stderr instead.
+pub static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// How much is printed, as a [`LogLevel`].
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// How things are printed, once it's been set.
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+/// How much is printed while running. Errors are always printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    /// Only errors.
+    Error,
+    /// What's happening to each file as it's searched or skipped.
+    Info,
+    /// Also the details of how each file was searched, and how long it took.
+    Debug,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Lines of text, for people to read.
+    Text,
+    /// A JSON object for each event on a line of its own, with when it happened, for log
+    /// aggregators to follow the run. Progress bars aren't shown.
+    Json,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct LogArgs {
+    /// Print more detail about each file, as with `--log-level debug`.
+    #[clap(long = "verbose", env = "YTMS_VERBOSE", short = 'v', global = true)]
+    verbose: bool,
+    /// Only print errors, as with `--log-level error`.
+    #[clap(
+        long = "quiet",
+        env = "YTMS_QUIET",
+        global = true,
+        conflicts_with = "verbose"
+    )]
+    quiet: bool,
+    /// How much to print while running.
+    #[clap(
+        long = "log-level",
+        env = "YTMS_LOG_LEVEL",
+        value_enum,
+        global = true,
+        conflicts_with_all = &["verbose", "quiet"]
+    )]
+    log_level: Option<LogLevel>,
+    /// How to print what's happening.
+    #[clap(
+        long = "log-format",
+        env = "YTMS_LOG_FORMAT",
+        value_enum,
+        global = true,
+        default_value = "text"
+    )]
+    log_format: LogFormat,
+}
+
+impl LogArgs {
+    pub fn level(&self) -> LogLevel {
+        match self.log_level {
+            Some(level) => level,
+            None if self.verbose => LogLevel::Debug,
+            None if self.quiet => LogLevel::Error,
+            None => LogLevel::Info,
+        }
+    }
+
+    /// Sets how much is printed, and how.
+    pub fn apply(&self) {
+        LEVEL.store(self.level() as u8, Ordering::Relaxed);
+        let _ = FORMAT.set(self.log_format);
+    }
+}
+
+/// Whether messages at this level are printed.
+pub fn enabled(level: LogLevel) -> bool {
+    LEVEL.load(Ordering::Relaxed) >= level as u8
+}
+
+/// Whether events are printed as JSON.
+pub fn json() -> bool {
+    FORMAT.get() == Some(&LogFormat::Json)
+}
+
+fn as_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// Something that happened during the run. With `--log-format json` each is printed as a
+/// JSON object, tagged with its kind as `event`, otherwise the ones worth reading are
+/// printed as text.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A status message.
+    Message {
+        level: LogLevel,
+        message: &'a str,
+    },
+    RunStarted {
+        inputs: usize,
+        queries: usize,
+    },
+    FileStarted {
+        file: &'a str,
+    },
+    FileFinished {
+        file: &'a str,
+        lines: u64,
+        /// Decompressed bytes read.
+        bytes: u64,
+        /// Lines which matched any of the queries.
+        found: u64,
+        /// The number of matches for each query.
+        query_matches: BTreeMap<&'a str, u64>,
+        #[serde(rename = "secs", serialize_with = "as_secs")]
+        elapsed: Duration,
+        /// Time spent handing the matches to the output files.
+        #[serde(rename = "writing_secs", serialize_with = "as_secs")]
+        writing: Duration,
+    },
+    FileFailed {
+        file: &'a str,
+        error: &'a str,
+    },
+    /// Everything found up to this point in the file has been written out, and recorded
+    /// in the management file so the search can carry on from here.
+    Checkpoint {
+        file: &'a str,
+        lines: u64,
+        bytes: u64,
+    },
+    /// The output files have been flushed.
+    Flushed {
+        outputs: usize,
+        errors: usize,
+    },
+    RunFinished {
+        #[serde(rename = "secs", serialize_with = "as_secs")]
+        elapsed: Duration,
+        interrupted: bool,
+    },
+    /// The totals at the end of the run.
+    Summary(&'a Summary),
+}
+
+impl Event<'_> {
+    fn level(&self) -> LogLevel {
+        match self {
+            Event::Message { level, .. } => *level,
+            Event::FileFailed { .. } => LogLevel::Error,
+            _ => LogLevel::Info,
+        }
+    }
+
+    /// How the event is printed as text, if it is. The progress bars stand in for the
+    /// messages about each file while they're shown.
+    fn text(&self) -> Option<String> {
+        let bars = display::shown().is_some();
+        match self {
+            Event::Message { message, .. } => Some(message.to_string()),
+            Event::FileStarted { file } if !bars => Some(format!("Searching {file}...")),
+            Event::FileFinished {
+                lines,
+                found,
+                elapsed,
+                ..
+            } if !bars => Some(format!(
+                "Took {elapsed:?} to search {lines} lines, found {found} results"
+            )),
+            Event::FileFailed { error, .. } => Some(error.to_string()),
+            Event::Summary(summary) => Some(summary.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Prints the event, if its level is enabled.
+pub fn log(event: Event) {
+    if !enabled(event.level()) {
+        return;
+    }
+    let line = if json() {
+        #[derive(Serialize)]
+        struct Line<'a> {
+            time: String,
+            #[serde(flatten)]
+            event: &'a Event<'a>,
+        }
+        let time = humantime::format_rfc3339_millis(SystemTime::now()).to_string();
+        match serde_json::to_string(&Line {
+            time,
+            event: &event,
+        }) {
+            Ok(line) => line,
+            Err(_) => return,
+        }
+    } else {
+        match event.text() {
+            Some(text) => text,
+            None => return,
+        }
+    };
+
+    if let Some(dashboard) = tui::get() {
+        dashboard.message(line);
+    } else if event.level() == LogLevel::Error && !json() {
+        eprintln!("{line}");
+    } else if let Some(display) = display::shown() {
+        display.println(line);
+    } else if STATUS_TO_STDERR.load(Ordering::Relaxed) {
+        eprintln!("{line}");
+    } else {
+        println!("{line}");
+    }
+}
+
+/// Prints a status message at the given level, as an event when logging as JSON.
+pub fn print_status(level: LogLevel, message: String) {
+    log(Event::Message {
+        level,
+        message: &message,
+    });
+}
@@ -0,0 +1,96 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use serde::de::IgnoredAny;
+use serde_json::json;
+
+use crate::record::RecordView;
+
+/// Checks each line is a record, for `--check-records`, appending those which aren't to
+/// `malformed.jsonl` in the output folder along with where they were found. Truncated lines
+/// from interrupted scrapes would otherwise be searched like any other, and written out if
+/// what's left of them matches.
+pub struct MalformedLog {
+    path: PathBuf,
+    /// The fields every record has to have.
+    required: Vec<String>,
+    /// Opened with the first malformed line, so that runs without any don't leave an empty
+    /// file.
+    file: Mutex<Option<File>>,
+}
+
+impl MalformedLog {
+    pub fn new(path: PathBuf, required: Vec<String>) -> Self {
+        Self {
+            path,
+            required,
+            file: Mutex::new(None),
+        }
+    }
+
+    /// What's wrong with the line, if anything: whether it's a JSON object, with each of the
+    /// required fields.
+    fn problem(&self, line: &[u8]) -> Option<String> {
+        let Ok(line) = std::str::from_utf8(line) else {
+            return Some("not valid UTF-8".to_owned());
+        };
+        let record = RecordView::new(line);
+        if record.fields().is_none() {
+            return Some(match serde_json::from_str::<IgnoredAny>(line) {
+                Ok(_) => "not a JSON object".to_owned(),
+                Err(e) => format!("not valid JSON: {e}"),
+            });
+        }
+        let missing: Vec<_> = self
+            .required
+            .iter()
+            .filter(|field| record.field(field).is_none_or(|value| value.is_null()))
+            .map(|field| format!("`{field}`"))
+            .collect();
+        (!missing.is_empty()).then(|| format!("missing {}", missing.join(", ")))
+    }
+
+    /// Checks the line from the input, recording it if it isn't a record. Returns whether it
+    /// is. Blank lines are left alone. Problems writing to the file are printed, as there's
+    /// nowhere else for them to go.
+    pub fn check(&self, input: &str, line_number: Option<u64>, line: &[u8]) -> bool {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        if line.trim_ascii().is_empty() {
+            return true;
+        }
+        let Some(problem) = self.problem(line) else {
+            return true;
+        };
+        let entry = json!({
+            "file": input,
+            "line": line_number,
+            "problem": problem,
+            "text": String::from_utf8_lossy(line),
+        });
+
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+            {
+                Ok(opened) => *file = Some(opened),
+                Err(e) => {
+                    eprintln!("Error opening {}: {e}", self.path.display());
+                    return false;
+                }
+            }
+        }
+        if let Some(log) = file.as_mut() {
+            if let Err(e) = writeln!(log, "{entry}") {
+                eprintln!("Error writing to {}: {e}", self.path.display());
+            }
+        }
+        false
+    }
+}
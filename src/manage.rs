@@ -0,0 +1,334 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use glob::Pattern;
+
+use crate::{
+    input::{canonical_path, Input},
+    management::{lock_management, write_management, Management},
+};
+
+#[derive(Debug, clap::Args)]
+pub struct ManageArgs {
+    #[clap(subcommand)]
+    action: ManageAction,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum ManageAction {
+    /// List the completed, partially searched and failed files.
+    List(ManagementFile),
+    /// Forget the progress of the files matching the glob patterns, so they're searched again
+    /// by the next run. Relative patterns are matched against the paths as they're recorded,
+    /// relative to the input folder.
+    Forget {
+        #[clap(flatten)]
+        management: ManagementFile,
+        #[clap(required = true)]
+        patterns: Vec<String>,
+    },
+    /// Forget the progress of every file, so the next run searches everything again.
+    Reset(ManagementFile),
+    /// Combine the progress from several management files, such as from runs on different
+    /// machines searching parts of the same dataset.
+    Merge(MergeArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct ManagementFile {
+    #[clap(long = "search-management-file", short = 'm')]
+    path: PathBuf,
+    /// Wait for a search using the management file to finish, instead of exiting.
+    #[clap(long = "wait-for-lock")]
+    wait_for_lock: bool,
+    /// The input folder the search was run on. Files are recorded relative to it, so this is
+    /// needed to match absolute paths, and to update management files from older versions.
+    #[clap(long = "input-folder", short = 'i')]
+    files_folder: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Args)]
+struct MergeArgs {
+    /// Management files to merge.
+    #[clap(required = true, min_values = 2)]
+    files: Vec<PathBuf>,
+    /// Where to write the merged management file.
+    #[clap(long = "output", short = 'o')]
+    output: PathBuf,
+    /// Wait for searches using the management files to finish, instead of exiting.
+    #[clap(long = "wait-for-lock")]
+    wait_for_lock: bool,
+}
+
+fn list(management: &Management) {
+    println!(
+        "{} completed files, {} lines",
+        management.c_files.len(),
+        management.c_lines
+    );
+    for file in &management.c_files {
+        match management.c_stats.get(file) {
+            Some(stats) => println!(
+                "  {}: {} lines, {} matches",
+                file.display(),
+                stats.lines,
+                stats.matches.values().sum::<u64>()
+            ),
+            None => println!("  {}", file.display()),
+        }
+    }
+
+    if !management.partial.is_empty() {
+        println!("{} partially searched files", management.partial.len());
+        for (file, point) in &management.partial {
+            println!("  {}: up to line {}", file.display(), point.lines);
+        }
+    }
+
+    if !management.failed_files.is_empty() {
+        println!("{} failed files", management.failed_files.len());
+        for (file, error) in &management.failed_files {
+            println!("  {}: {error}", file.display());
+        }
+    }
+}
+
+/// Removes the files matching any of the patterns, returning how many were forgotten.
+fn forget(management: &mut Management, patterns: &[Pattern], root: Option<&Path>) -> usize {
+    let absolute = |path: &Path| match root {
+        Some(root) => root.join(path),
+        None => path.to_path_buf(),
+    };
+    let matching = |path: &Path| {
+        patterns
+            .iter()
+            .any(|p| p.matches_path(path) || p.matches_path(&absolute(path)))
+    };
+    let files: Vec<PathBuf> = management
+        .c_files
+        .iter()
+        .chain(management.partial.keys())
+        .chain(management.failed_files.keys())
+        .filter(|path| matching(path))
+        .cloned()
+        .collect();
+
+    for file in &files {
+        if management.c_files.remove(file) {
+            if let Some(stats) = management.c_stats.remove(file) {
+                management.c_lines = management.c_lines.saturating_sub(stats.lines);
+            }
+            // The fingerprint would otherwise make `--dedup-inputs` skip the file, if it's
+            // still there to be fingerprinted.
+            if let Ok(Some(fingerprint)) = Input::File(absolute(file)).fingerprint() {
+                management.c_hashes.remove(&fingerprint);
+            }
+        }
+        management.partial.remove(file);
+        management.failed_files.remove(file);
+    }
+
+    files.len()
+}
+
+/// Adds the progress from `other` to `merged`, returning how many of its completed files
+/// had already been completed in `merged`.
+fn merge_into(merged: &mut Management, other: Management) -> usize {
+    let mut duplicates = 0;
+    let mut lines = other.c_lines;
+    for file in other.c_files {
+        let stats = other.c_stats.get(&file);
+        if merged.c_files.contains(&file) {
+            duplicates += 1;
+            // Don't count the lines twice, where we know how many there were.
+            lines = lines.saturating_sub(stats.map_or(0, |s| s.lines));
+            continue;
+        }
+
+        merged.partial.remove(&file);
+        merged.failed_files.remove(&file);
+        if let Some(stats) = stats {
+            merged.c_stats.insert(file.clone(), stats.clone());
+        }
+        merged.c_files.insert(file);
+    }
+    merged.c_lines += lines;
+    merged.c_hashes.extend(other.c_hashes);
+
+    // Where both got part way through a file, carry on from whichever got further.
+    for (file, point) in other.partial {
+        if merged.c_files.contains(&file) {
+            continue;
+        }
+        let entry = merged.partial.entry(file).or_insert(point);
+        if point.bytes > entry.bytes {
+            *entry = point;
+        }
+    }
+    for (file, error) in other.failed_files {
+        if !merged.c_files.contains(&file) {
+            merged.failed_files.entry(file).or_insert(error);
+        }
+    }
+
+    duplicates
+}
+
+fn merge(args: &MergeArgs) -> Result<()> {
+    let mut merged = Management::default();
+    let mut locks = Vec::new();
+    for path in &args.files {
+        locks.push(lock_management(path, args.wait_for_lock)?);
+        let management = Management::load_for_update(path, None)
+            .with_context(|| anyhow!("Error loading {}", path.display()))?;
+
+        match (&merged.query_hash, &management.query_hash) {
+            (Some(a), Some(b)) if a != b => bail!(
+                "{} was searched with different queries to the files before it",
+                path.display()
+            ),
+            (None, Some(hash)) => merged.query_hash = Some(hash.clone()),
+            _ => {}
+        }
+
+        let completed = management.c_files.len();
+        let duplicates = merge_into(&mut merged, management);
+        println!(
+            "Merged {} ({completed} completed files, {duplicates} already completed)",
+            path.display()
+        );
+    }
+
+    if !args.files.contains(&args.output) {
+        locks.push(lock_management(&args.output, args.wait_for_lock)?);
+    }
+    write_management(&args.output, &merged)
+        .with_context(|| anyhow!("Error writing {}", args.output.display()))?;
+    println!(
+        "Wrote {} with {} completed files, {} lines",
+        args.output.display(),
+        merged.c_files.len(),
+        merged.c_lines
+    );
+    Ok(())
+}
+
+/// Inspects or edits the progress recorded in a management file.
+pub fn manage(args: &ManageArgs) -> Result<()> {
+    let file = match &args.action {
+        ManageAction::Merge(args) => return merge(args),
+        ManageAction::List(file) | ManageAction::Reset(file) => file,
+        ManageAction::Forget { management, .. } => management,
+    };
+
+    if !file.path.exists() {
+        bail!("Management file {} doesn't exist", file.path.display());
+    }
+    let _lock = lock_management(&file.path, file.wait_for_lock)?;
+    let root = file.files_folder.as_deref().map(canonical_path);
+    let mut management = Management::load_for_update(&file.path, root.as_deref())?;
+
+    match &args.action {
+        ManageAction::List(_) => {
+            list(&management);
+            return Ok(());
+        }
+        ManageAction::Forget { patterns, .. } => {
+            let patterns = patterns
+                .iter()
+                .map(|p| Pattern::new(p).with_context(|| anyhow!("Invalid pattern `{p}`")))
+                .collect::<Result<Vec<_>>>()?;
+            let count = forget(&mut management, &patterns, root.as_deref());
+            println!("Forgot {count} files");
+        }
+        ManageAction::Reset(_) => {
+            // The queries haven't changed, so keep their hash.
+            management = Management {
+                query_hash: management.query_hash,
+                ..Management::default()
+            };
+            println!("Forgot all files");
+        }
+        ManageAction::Merge(_) => unreachable!(),
+    }
+
+    write_management(&file.path, &management)
+        .with_context(|| anyhow!("Error writing management file"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::management::{Change, FileStats, ResumePoint};
+
+    fn completed(file: &str, lines: u64) -> Change {
+        Change::Completed {
+            file: Some(file.into()),
+            fingerprint: None,
+            lines,
+            stats: Some(FileStats {
+                lines,
+                ..FileStats::default()
+            }),
+        }
+    }
+
+    fn checkpoint(file: &str, bytes: u64) -> Change {
+        Change::Checkpoint {
+            file: file.into(),
+            point: ResumePoint { lines: 1, bytes },
+        }
+    }
+
+    #[test]
+    fn files_completed_on_both_are_only_counted_once() {
+        let mut merged = Management::default();
+        merged.apply(completed("a.zst", 10));
+        let mut other = Management::default();
+        other.apply(completed("a.zst", 10));
+        other.apply(completed("b.zst", 5));
+
+        assert_eq!(merge_into(&mut merged, other), 1);
+        assert_eq!(merged.c_files.len(), 2);
+        assert_eq!(merged.c_lines, 15);
+        assert_eq!(merged.c_stats[Path::new("b.zst")].lines, 5);
+    }
+
+    #[test]
+    fn completion_on_either_replaces_progress_on_the_other() {
+        let mut merged = Management::default();
+        merged.apply(checkpoint("a.zst", 30));
+        merged.apply(Change::Failed {
+            file: "b.zst".into(),
+            error: "broken".to_owned(),
+        });
+        merged.apply(completed("c.zst", 1));
+        let mut other = Management::default();
+        other.apply(completed("a.zst", 2));
+        other.apply(completed("b.zst", 3));
+        other.apply(checkpoint("c.zst", 10));
+
+        merge_into(&mut merged, other);
+        assert!(merged.partial.is_empty());
+        assert!(merged.failed_files.is_empty());
+        assert_eq!(merged.c_files.len(), 3);
+    }
+
+    #[test]
+    fn carries_on_from_whichever_got_further() {
+        let mut merged = Management::default();
+        merged.apply(checkpoint("a.zst", 30));
+        merged.apply(checkpoint("b.zst", 30));
+        let mut other = Management::default();
+        other.apply(checkpoint("a.zst", 50));
+        other.apply(checkpoint("b.zst", 10));
+        other.apply(checkpoint("c.zst", 20));
+
+        merge_into(&mut merged, other);
+        let bytes = |file: &str| merged.partial[Path::new(file)].bytes;
+        assert_eq!(
+            (bytes("a.zst"), bytes("b.zst"), bytes("c.zst")),
+            (50, 30, 20)
+        );
+    }
+}
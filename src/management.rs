@@ -0,0 +1,719 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs::{File, OpenOptions, TryLockError},
+    io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{Receiver, RecvTimeoutError},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+    input::{canonical_path, relative_to},
+    management_db::{self, is_sqlite, Store},
+    status,
+};
+
+/// The version of the management file format written by this build. Files from before the
+/// format was versioned are version 0, and version 2 started recording paths relative to
+/// the input folder.
+pub(crate) const FORMAT_VERSION: u64 = 2;
+
+/// The progress of a search across runs, recording which files have been completed.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Management {
+    pub c_files: BTreeSet<PathBuf>,
+    pub c_lines: u64,
+    /// Content fingerprints of the completed files, used with `--dedup-inputs`.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub c_hashes: BTreeSet<String>,
+    /// A hash of the query definitions, to catch the queries changing between runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query_hash: Option<String>,
+    /// How far the search of each unfinished file got, so it can carry on from there.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub partial: BTreeMap<PathBuf, ResumePoint>,
+    /// What was found in each completed file.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub c_stats: BTreeMap<PathBuf, FileStats>,
+    /// Files which couldn't be searched, with the error, so they can be retried.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub failed_files: BTreeMap<PathBuf, String>,
+}
+
+/// Statistics for a completed file, kept so they can be looked at later without searching
+/// the file again.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct FileStats {
+    pub lines: u64,
+    /// The size of the file on disk, where it's known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    pub secs: f64,
+    /// The fingerprint of the file's contents, if it was fingerprinted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    /// Matches for each query, by its output filename.
+    pub matches: BTreeMap<String, u64>,
+}
+
+/// A position in an input's decompressed stream, at the start of a line.
+///
+/// Zstd frames can't be entered part way through, so a resumed search has to decompress
+/// and skip the data up to this point, but doesn't need to search it.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub struct ResumePoint {
+    pub lines: u64,
+    pub bytes: u64,
+}
+
+/// A single update to the management state, as recorded in the journal.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    /// An input has been searched to the end.
+    Completed {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file: Option<PathBuf>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fingerprint: Option<String>,
+        lines: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stats: Option<FileStats>,
+    },
+    /// The search of a file has got this far.
+    Checkpoint { file: PathBuf, point: ResumePoint },
+    /// A file couldn't be searched.
+    Failed { file: PathBuf, error: String },
+}
+
+impl Management {
+    /// Loads the management file, along with any changes left in its journal by a run which
+    /// didn't finish or is still going. `root` is the canonicalized input folder, which is
+    /// stripped from any paths recorded in full, either by older versions or runs given a
+    /// different folder.
+    ///
+    /// Nothing is written, so this is safe to use without holding [`lock_management`].
+    pub fn load(path: &Path, root: Option<&Path>) -> Result<Self> {
+        Ok(Self::load_with_journal(path, root)?.0)
+    }
+
+    /// Loads the management file like [`Management::load`], then folds any journal left by a
+    /// run which didn't finish into it, so the changes don't have to be replayed again. Only
+    /// for callers holding [`lock_management`], as the journal could otherwise still be in
+    /// use.
+    pub fn load_for_update(path: &Path, root: Option<&Path>) -> Result<Self> {
+        let (management, replayed) = Self::load_with_journal(path, root)?;
+        let Some(replayed) = replayed else {
+            return Ok(management);
+        };
+        if replayed > 0 {
+            status!("Recovered {replayed} changes from the management journal");
+            write_management(path, &management)
+                .with_context(|| anyhow!("Error writing management file"))?;
+        }
+        let journal_path = journal_path(path);
+        std::fs::remove_file(&journal_path)
+            .with_context(|| anyhow!("Error removing {}", journal_path.display()))?;
+        Ok(management)
+    }
+
+    /// Loads the management file and replays its journal, returning how many changes were
+    /// replayed if there was a journal.
+    fn load_with_journal(path: &Path, root: Option<&Path>) -> Result<(Self, Option<usize>)> {
+        // A SQLite management file is updated in place, so there's never a journal.
+        if is_sqlite(path) {
+            let mut management = management_db::load(path)?;
+            if let Some(root) = root {
+                management.make_relative(root);
+            }
+            return Ok((management, None));
+        }
+
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| anyhow!("Error opening management file"))?;
+        let value: serde_json::Value = serde_json::from_str(&contents)
+            .with_context(|| anyhow!("Error parsing management file"))?;
+        let version = match value.get("version") {
+            None => 0,
+            Some(version) => version
+                .as_u64()
+                .ok_or_else(|| anyhow!("Invalid management file version `{version}`"))?,
+        };
+        if version > FORMAT_VERSION {
+            bail!(
+                "Management file {} is format version {version}, but this version of \
+                ytmetasearch only understands up to version {FORMAT_VERSION}. It was probably \
+                written by a newer version",
+                path.display()
+            );
+        }
+
+        let mut management: Management = serde_json::from_value(value)
+            .with_context(|| anyhow!("Error parsing management file"))?;
+        management.migrate(version);
+        if let Some(root) = root {
+            management.make_relative(root);
+        }
+
+        let Ok(journal) = File::open(journal_path(path)) else {
+            return Ok((management, None));
+        };
+        let mut replayed = 0;
+        for line in BufReader::new(journal).lines() {
+            let line = line.with_context(|| anyhow!("Error reading management journal"))?;
+            // The last line may have been cut short if the run was killed while writing it,
+            // or is still being written, in which case the change it records is left out.
+            let Ok(change) = serde_json::from_str(&line) else {
+                break;
+            };
+            management.apply(change);
+            replayed += 1;
+        }
+        Ok((management, Some(replayed)))
+    }
+
+    /// Brings the state loaded from an older version of the management file up to date.
+    fn migrate(&mut self, from: u64) {
+        if from < 1 {
+            // Paths used to be recorded as they were given, rather than canonicalized.
+            self.c_files = self.c_files.iter().map(|p| canonical_path(p)).collect();
+            self.partial = std::mem::take(&mut self.partial)
+                .into_iter()
+                .map(|(p, point)| (canonical_path(&p), point))
+                .collect();
+            self.c_stats = std::mem::take(&mut self.c_stats)
+                .into_iter()
+                .map(|(p, stats)| (canonical_path(&p), stats))
+                .collect();
+            self.failed_files = std::mem::take(&mut self.failed_files)
+                .into_iter()
+                .map(|(p, error)| (canonical_path(&p), error))
+                .collect();
+        }
+    }
+
+    /// Makes the recorded paths inside `root` relative to it.
+    fn make_relative(&mut self, root: &Path) {
+        let root = Some(root);
+        self.c_files = std::mem::take(&mut self.c_files)
+            .into_iter()
+            .map(|p| relative_to(p, root))
+            .collect();
+        self.partial = std::mem::take(&mut self.partial)
+            .into_iter()
+            .map(|(p, point)| (relative_to(p, root), point))
+            .collect();
+        self.c_stats = std::mem::take(&mut self.c_stats)
+            .into_iter()
+            .map(|(p, stats)| (relative_to(p, root), stats))
+            .collect();
+        self.failed_files = std::mem::take(&mut self.failed_files)
+            .into_iter()
+            .map(|(p, error)| (relative_to(p, root), error))
+            .collect();
+    }
+
+    /// Applies a change. Changes which were already folded into the management file before
+    /// a crash may be replayed again, so this has to cope with seeing them twice.
+    pub fn apply(&mut self, change: Change) {
+        match change {
+            Change::Completed {
+                file,
+                fingerprint,
+                lines,
+                stats,
+            } => {
+                if let Some(file) = file {
+                    if self.c_files.contains(&file) {
+                        // A file is only completed again if it's changed since, in which case
+                        // the new search replaces the old one.
+                        let old = self.c_stats.get(&file);
+                        let old_fingerprint = old.and_then(|s| s.fingerprint.as_ref());
+                        if old_fingerprint.is_none() || old_fingerprint == fingerprint.as_ref() {
+                            return;
+                        }
+                        if let Some(old) = self.c_stats.remove(&file) {
+                            self.c_lines = self.c_lines.saturating_sub(old.lines);
+                            if let Some(old_fingerprint) = old.fingerprint {
+                                self.c_hashes.remove(&old_fingerprint);
+                            }
+                        }
+                    }
+                    self.partial.remove(&file);
+                    self.failed_files.remove(&file);
+                    if let Some(stats) = stats {
+                        self.c_stats.insert(file.clone(), stats);
+                    }
+                    self.c_files.insert(file);
+                }
+                if let Some(fingerprint) = fingerprint {
+                    self.c_hashes.insert(fingerprint);
+                }
+                self.c_lines += lines;
+            }
+            Change::Checkpoint { file, point } => {
+                if !self.c_files.contains(&file) {
+                    self.partial.insert(file, point);
+                }
+            }
+            Change::Failed { file, error } => {
+                if !self.c_files.contains(&file) {
+                    self.failed_files.insert(file, error);
+                }
+            }
+        }
+    }
+}
+
+/// Hashes the query definitions, ignoring formatting and the order of each query's keys.
+pub fn query_set_hash(query_file: &str) -> Result<String> {
+    let queries: serde_json::Value =
+        serde_json::from_str(query_file).with_context(|| anyhow!("Error parsing query file"))?;
+    Ok(format!("{:016x}", xxh3_64(queries.to_string().as_bytes())))
+}
+
+/// Where changes are appended when using `--management-journal`.
+fn journal_path(management_file: &Path) -> PathBuf {
+    with_suffix(management_file, ".journal")
+}
+
+/// The management file as it's written, tagged with the format version.
+#[derive(Serialize)]
+struct Versioned<'a> {
+    version: u64,
+    #[serde(flatten)]
+    management: &'a Management,
+}
+
+/// Writes out the management file. The new version is written to a temporary file which
+/// then replaces the old one, so that a crash can't leave it half written, and the
+/// previous version is kept as `.bak`. A SQLite management file is replaced in a single
+/// transaction instead.
+pub fn write_management(path: &Path, management: &Management) -> io::Result<()> {
+    if is_sqlite(path) {
+        return Store::open(path)
+            .and_then(|mut store| store.replace(management))
+            .map_err(|e| io::Error::other(format!("{e:#}")));
+    }
+    let rendered = serde_json::to_string_pretty(&Versioned {
+        version: FORMAT_VERSION,
+        management,
+    })?;
+    let temp_path = with_suffix(path, ".tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(rendered.as_bytes())?;
+    file.sync_all()?;
+    if path.exists() {
+        std::fs::copy(path, with_suffix(path, ".bak"))?;
+    }
+    std::fs::rename(temp_path, path)
+}
+
+/// The management state being updated as files are completed.
+pub struct Progress {
+    pub management: Management,
+    management_file: PathBuf,
+    /// With `--management-journal`, changes are appended here rather than rewriting the
+    /// whole management file each time it's saved.
+    journal: Option<BufWriter<File>>,
+    /// With a SQLite management file, only the rows of the files and fingerprints which
+    /// have changed since the last save are written.
+    store: Option<Store>,
+    changed_files: BTreeSet<PathBuf>,
+    changed_hashes: BTreeSet<String>,
+    /// Fingerprints of the files currently being searched, so that duplicates found in the
+    /// same run aren't searched in parallel.
+    pub in_progress_hashes: HashSet<String>,
+}
+
+impl Progress {
+    /// Any journal left by an earlier run has already been folded in by
+    /// [`Management::load_for_update`],
+    /// or belongs to progress which is being thrown away, so it's replaced.
+    ///
+    /// A SQLite management file is brought up to date with `management` in one
+    /// transaction, and then each change is upserted as it's saved. It doesn't need a
+    /// journal, so `--management-journal` is ignored.
+    pub fn new(management: Management, management_file: PathBuf, journal: bool) -> Result<Self> {
+        let store = if is_sqlite(&management_file) {
+            let mut store = Store::open(&management_file)?;
+            store
+                .replace(&management)
+                .with_context(|| anyhow!("Error writing management file"))?;
+            Some(store)
+        } else {
+            None
+        };
+        let path = journal_path(&management_file);
+        let journal = if journal && store.is_none() {
+            // The journal is only read alongside the management file, so that has to exist.
+            write_management(&management_file, &management)
+                .with_context(|| anyhow!("Error writing management file"))?;
+            let file = File::create(&path)
+                .with_context(|| anyhow!("Error creating {}", path.display()))?;
+            Some(BufWriter::new(file))
+        } else {
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| anyhow!("Error removing {}", path.display()))
+                }
+                _ => None,
+            }
+        };
+
+        Ok(Self {
+            management,
+            management_file,
+            journal,
+            store,
+            changed_files: BTreeSet::new(),
+            changed_hashes: BTreeSet::new(),
+            in_progress_hashes: HashSet::new(),
+        })
+    }
+
+    /// Updates the management state. This isn't written out until the next save.
+    pub fn record(&mut self, change: Change) {
+        if let Some(journal) = &mut self.journal {
+            let result = serde_json::to_writer(&mut *journal, &change)
+                .map_err(io::Error::from)
+                .and_then(|_| journal.write_all(b"\n"));
+            if let Err(e) = result {
+                eprintln!("Error writing management journal: {e}");
+            }
+        }
+        if self.store.is_some() {
+            self.note_changed(&change);
+        }
+        self.management.apply(change);
+    }
+
+    /// Notes which rows of a SQLite management file a change is going to touch, before
+    /// it's applied.
+    fn note_changed(&mut self, change: &Change) {
+        let file = match change {
+            Change::Completed {
+                file, fingerprint, ..
+            } => {
+                self.changed_hashes.extend(fingerprint.clone());
+                // A changed file's old fingerprint is dropped when it's completed again.
+                let old = file
+                    .as_ref()
+                    .and_then(|file| self.management.c_stats.get(file));
+                self.changed_hashes
+                    .extend(old.and_then(|stats| stats.fingerprint.clone()));
+                file.as_ref()
+            }
+            Change::Checkpoint { file, .. } | Change::Failed { file, .. } => Some(file),
+        };
+        self.changed_files.extend(file.cloned());
+    }
+
+    /// Writes out the changes since the last save, either to the journal, to the changed
+    /// rows of a SQLite management file, or by replacing the management file.
+    pub fn save(&mut self) {
+        let result = match (&mut self.store, &mut self.journal) {
+            (Some(store), _) => {
+                let files = std::mem::take(&mut self.changed_files);
+                let hashes = std::mem::take(&mut self.changed_hashes);
+                store
+                    .update(&self.management, &files, &hashes)
+                    .map_err(|e| io::Error::other(format!("{e:#}")))
+            }
+            (None, Some(journal)) => journal.flush().and_then(|_| journal.get_ref().sync_data()),
+            (None, None) => write_management(&self.management_file, &self.management),
+        };
+        if let Err(e) = result {
+            eprintln!("Error writing management file: {e}");
+        }
+    }
+
+    /// Folds the journal into the management file and empties it. Does nothing unless
+    /// using `--management-journal`.
+    pub fn compact(&mut self) {
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+        let result = write_management(&self.management_file, &self.management).and_then(|_| {
+            journal.flush()?;
+            let file = journal.get_mut();
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0)).map(drop)
+        });
+        if let Err(e) = result {
+            eprintln!("Error writing management file: {e}");
+        }
+    }
+}
+
+/// Messages to the thread which writes out the management file.
+pub enum SaveRequest {
+    /// A file has been completed.
+    Completed,
+    /// Save any changes straight away.
+    Now,
+    /// Save any changes, and stop.
+    Finish,
+}
+
+/// Writes out the management file as files are completed, after every `every` files, and
+/// at least once per `interval` while there are unsaved completions.
+pub fn run_saver(
+    progress: &Mutex<Progress>,
+    requests: Receiver<SaveRequest>,
+    every: usize,
+    interval: Duration,
+) {
+    let mut unsaved = 0;
+    let mut last_save = Instant::now();
+    loop {
+        let request = if unsaved == 0 {
+            requests.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            requests.recv_timeout(interval.saturating_sub(last_save.elapsed()))
+        };
+
+        let finish = matches!(
+            request,
+            Ok(SaveRequest::Finish) | Err(RecvTimeoutError::Disconnected)
+        );
+        let save = match request {
+            Ok(SaveRequest::Completed) => {
+                unsaved += 1;
+                unsaved >= every
+            }
+            Ok(SaveRequest::Now) => true,
+            Ok(SaveRequest::Finish) | Err(_) => unsaved > 0,
+        };
+
+        if save {
+            progress.lock().unwrap().save();
+            unsaved = 0;
+            last_save = Instant::now();
+        }
+        if finish {
+            progress.lock().unwrap().compact();
+            return;
+        }
+    }
+}
+
+/// Adds a suffix to the end of a path's file name, e.g. `foo.json` to `foo.json.bak`.
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// Takes an exclusive lock on `<management file>.lock`, held until the returned file is
+/// closed, so that two runs can't overwrite each other's progress.
+pub fn lock_management(path: &Path, wait: bool) -> Result<File> {
+    let lock_path = with_suffix(path, ".lock");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| anyhow!("Error opening lock file {}", lock_path.display()))?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) if wait => {
+            status!(
+                "Waiting for another run using {} to finish...",
+                path.display()
+            );
+            file.lock()
+                .with_context(|| anyhow!("Error locking {}", lock_path.display()))?;
+        }
+        Err(TryLockError::WouldBlock) => bail!(
+            "Another run is using the management file {}, pass --wait-for-lock to wait for it",
+            path.display()
+        ),
+        Err(TryLockError::Error(e)) => {
+            return Err(e).with_context(|| anyhow!("Error locking {}", lock_path.display()))
+        }
+    }
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completed(file: &str, fingerprint: Option<&str>, lines: u64) -> Change {
+        Change::Completed {
+            file: Some(file.into()),
+            fingerprint: fingerprint.map(str::to_owned),
+            lines,
+            stats: Some(FileStats {
+                lines,
+                fingerprint: fingerprint.map(str::to_owned),
+                ..FileStats::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn completing_a_file_clears_its_checkpoint_and_failure() {
+        let mut management = Management::default();
+        management.apply(Change::Checkpoint {
+            file: "a.zst".into(),
+            point: ResumePoint {
+                lines: 3,
+                bytes: 30,
+            },
+        });
+        management.apply(Change::Failed {
+            file: "a.zst".into(),
+            error: "broken".to_owned(),
+        });
+        management.apply(completed("a.zst", Some("fa"), 10));
+
+        assert!(management.c_files.contains(Path::new("a.zst")));
+        assert!(management.partial.is_empty());
+        assert!(management.failed_files.is_empty());
+        assert!(management.c_hashes.contains("fa"));
+        assert_eq!(management.c_lines, 10);
+
+        // Later checkpoints and failures of a completed file are ignored.
+        management.apply(Change::Checkpoint {
+            file: "a.zst".into(),
+            point: ResumePoint::default(),
+        });
+        management.apply(Change::Failed {
+            file: "a.zst".into(),
+            error: "broken".to_owned(),
+        });
+        assert!(management.partial.is_empty());
+        assert!(management.failed_files.is_empty());
+    }
+
+    #[test]
+    fn replayed_completions_are_only_counted_once() {
+        let mut management = Management::default();
+        management.apply(completed("a.zst", Some("fa"), 10));
+        management.apply(completed("a.zst", Some("fa"), 10));
+        assert_eq!(management.c_lines, 10);
+
+        // Without a fingerprint there's nothing to tell a changed file apart by.
+        management.apply(completed("b.zst", None, 5));
+        management.apply(completed("b.zst", Some("fb"), 7));
+        assert_eq!(management.c_lines, 15);
+    }
+
+    #[test]
+    fn a_changed_file_replaces_its_earlier_search() {
+        let mut management = Management::default();
+        management.apply(completed("a.zst", Some("old"), 10));
+        management.apply(completed("a.zst", Some("new"), 4));
+
+        assert_eq!(management.c_lines, 4);
+        assert_eq!(management.c_stats[Path::new("a.zst")].lines, 4);
+        assert_eq!(management.c_hashes, BTreeSet::from(["new".to_owned()]));
+    }
+
+    fn temp_folder(name: &str) -> PathBuf {
+        let folder =
+            std::env::temp_dir().join(format!("ytms-management-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        canonical_path(&folder)
+    }
+
+    #[test]
+    fn unversioned_files_have_their_paths_canonicalized() {
+        let folder = temp_folder("migrate");
+        std::fs::write(folder.join("a.zst"), "").unwrap();
+        let given = folder.join(".").join("a.zst");
+
+        let mut management = Management::default();
+        management.c_files.insert(given.clone());
+        management
+            .failed_files
+            .insert(given.clone(), "broken".to_owned());
+        management.migrate(0);
+        assert_eq!(management.c_files, BTreeSet::from([folder.join("a.zst")]));
+        assert!(management.failed_files.contains_key(&folder.join("a.zst")));
+
+        // Versioned files were already written with canonical paths.
+        let mut management = Management::default();
+        management.c_files.insert(given.clone());
+        management.migrate(1);
+        assert_eq!(management.c_files, BTreeSet::from([given]));
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn loads_unversioned_files_and_refuses_newer_ones() {
+        let folder = temp_folder("versions");
+        let path = folder.join("management.json");
+        let recorded = serde_json::json!({"c_files": [folder.join("a.zst")], "c_lines": 3});
+        std::fs::write(&path, recorded.to_string()).unwrap();
+        let management = Management::load(&path, Some(&folder)).unwrap();
+        assert_eq!(management.c_files, BTreeSet::from([PathBuf::from("a.zst")]));
+        assert_eq!(management.c_lines, 3);
+
+        let newer = FORMAT_VERSION + 1;
+        std::fs::write(
+            &path,
+            format!(r#"{{"version": {newer}, "c_files": [], "c_lines": 0}}"#),
+        )
+        .unwrap();
+        let error = Management::load(&path, None).unwrap_err();
+        assert!(error.to_string().contains("newer version"), "{error}");
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn only_paths_inside_the_root_are_made_relative() {
+        let root = Path::new("/data/dumps");
+        let mut management = Management::default();
+        management.c_files.insert(root.join("a.zst"));
+        management.c_files.insert("/elsewhere/b.zst".into());
+        management.c_files.insert("c.zst".into());
+        management
+            .partial
+            .insert(root.join("sub/d.zst"), ResumePoint::default());
+        management
+            .c_stats
+            .insert(root.join("a.zst"), FileStats::default());
+        management
+            .failed_files
+            .insert(root.join("e.zst"), "broken".to_owned());
+        management.make_relative(root);
+
+        assert_eq!(
+            management.c_files,
+            BTreeSet::from([
+                PathBuf::from("a.zst"),
+                PathBuf::from("/elsewhere/b.zst"),
+                PathBuf::from("c.zst"),
+            ])
+        );
+        assert!(management.partial.contains_key(Path::new("sub/d.zst")));
+        assert!(management.c_stats.contains_key(Path::new("a.zst")));
+        assert!(management.failed_files.contains_key(Path::new("e.zst")));
+    }
+
+    #[test]
+    fn completions_without_a_file_only_count_lines() {
+        let mut management = Management::default();
+        management.apply(Change::Completed {
+            file: None,
+            fingerprint: None,
+            lines: 8,
+            stats: None,
+        });
+        assert!(management.c_files.is_empty());
+        assert_eq!(management.c_lines, 8);
+    }
+}
@@ -0,0 +1,336 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Transaction};
+
+use crate::management::{FileStats, Management, ResumePoint, FORMAT_VERSION};
+
+/// Whether the management file is kept in SQLite, going by its extension, rather than as
+/// JSON.
+pub fn is_sqlite(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("sqlite" | "sqlite3" | "db")
+    )
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS state (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        version INTEGER NOT NULL,
+        c_lines INTEGER NOT NULL,
+        query_hash TEXT
+    );
+    CREATE TABLE IF NOT EXISTS files (
+        path TEXT PRIMARY KEY,
+        completed INTEGER NOT NULL,
+        stats TEXT,
+        resume_lines INTEGER,
+        resume_bytes INTEGER,
+        error TEXT
+    );
+    CREATE TABLE IF NOT EXISTS hashes (
+        fingerprint TEXT PRIMARY KEY
+    );
+";
+
+/// Loads the management state from a SQLite management file. The database is only opened
+/// for reading, so this can be done while a search is updating it.
+pub fn load(path: &Path) -> Result<Management> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| anyhow!("Error opening management file {}", path.display()))?;
+    read(&conn).with_context(|| anyhow!("Error reading management file {}", path.display()))
+}
+
+fn read(conn: &Connection) -> Result<Management> {
+    let state = conn
+        .query_row(
+            "SELECT version, c_lines, query_hash FROM state WHERE id = 0",
+            [],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            },
+        )
+        .optional()?;
+    let Some((version, c_lines, query_hash)) = state else {
+        return Ok(Management::default());
+    };
+    if version as u64 > FORMAT_VERSION {
+        bail!(
+            "The management file is format version {version}, but this version of \
+            ytmetasearch only understands up to version {FORMAT_VERSION}. It was probably \
+            written by a newer version"
+        );
+    }
+
+    let mut management = Management {
+        c_lines: c_lines as u64,
+        query_hash,
+        ..Management::default()
+    };
+    let mut files = conn
+        .prepare("SELECT path, completed, stats, resume_lines, resume_bytes, error FROM files")?;
+    let mut rows = files.query([])?;
+    while let Some(row) = rows.next()? {
+        let path = PathBuf::from(row.get::<_, String>(0)?);
+        if row.get::<_, bool>(1)? {
+            management.c_files.insert(path.clone());
+        }
+        if let Some(stats) = row.get::<_, Option<String>>(2)? {
+            let stats: FileStats = serde_json::from_str(&stats)
+                .with_context(|| anyhow!("Invalid stats for {}", path.display()))?;
+            management.c_stats.insert(path.clone(), stats);
+        }
+        if let (Some(lines), Some(bytes)) =
+            (row.get::<_, Option<i64>>(3)?, row.get::<_, Option<i64>>(4)?)
+        {
+            let point = ResumePoint {
+                lines: lines as u64,
+                bytes: bytes as u64,
+            };
+            management.partial.insert(path.clone(), point);
+        }
+        if let Some(error) = row.get::<_, Option<String>>(5)? {
+            management.failed_files.insert(path, error);
+        }
+    }
+
+    let mut hashes = conn.prepare("SELECT fingerprint FROM hashes")?;
+    for fingerprint in hashes.query_map([], |row| row.get(0))? {
+        management.c_hashes.insert(fingerprint?);
+    }
+    Ok(management)
+}
+
+/// A SQLite management file open for updating. Each input has a row of its own, which is
+/// upserted as it changes, rather than the whole file being rewritten.
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    /// Opens the management file, creating it if it doesn't exist.
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| anyhow!("Error opening management file {}", path.display()))?;
+        // Readers see the last committed state while a run is writing, rather than being
+        // locked out.
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)
+            .with_context(|| anyhow!("Error creating management file {}", path.display()))?;
+        Ok(Self { conn })
+    }
+
+    /// Replaces everything in the management file with the given state, in one transaction.
+    pub fn replace(&mut self, management: &Management) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute_batch("DELETE FROM files; DELETE FROM hashes;")?;
+        write_state(&tx, management)?;
+        let paths = management
+            .c_files
+            .iter()
+            .chain(management.partial.keys())
+            .chain(management.failed_files.keys())
+            .collect::<BTreeSet<_>>();
+        for path in paths {
+            write_file(&tx, management, path)?;
+        }
+        for fingerprint in &management.c_hashes {
+            tx.execute(
+                "INSERT OR IGNORE INTO hashes (fingerprint) VALUES (?1)",
+                [fingerprint],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Writes the rows of the files and fingerprints which have changed, as they now are in
+    /// `management`, in one transaction.
+    pub fn update(
+        &mut self,
+        management: &Management,
+        files: &BTreeSet<PathBuf>,
+        fingerprints: &BTreeSet<String>,
+    ) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        write_state(&tx, management)?;
+        for path in files {
+            write_file(&tx, management, path)?;
+        }
+        for fingerprint in fingerprints {
+            if management.c_hashes.contains(fingerprint) {
+                tx.execute(
+                    "INSERT OR IGNORE INTO hashes (fingerprint) VALUES (?1)",
+                    [fingerprint],
+                )?;
+            } else {
+                tx.execute("DELETE FROM hashes WHERE fingerprint = ?1", [fingerprint])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+fn write_state(tx: &Transaction, management: &Management) -> Result<()> {
+    tx.execute(
+        "INSERT INTO state (id, version, c_lines, query_hash) VALUES (0, ?1, ?2, ?3)
+        ON CONFLICT (id) DO UPDATE SET
+            version = excluded.version,
+            c_lines = excluded.c_lines,
+            query_hash = excluded.query_hash",
+        params![
+            FORMAT_VERSION as i64,
+            management.c_lines as i64,
+            management.query_hash
+        ],
+    )?;
+    Ok(())
+}
+
+/// Upserts the row for a file, or deletes it if nothing is recorded for it any more.
+fn write_file(tx: &Transaction, management: &Management, path: &Path) -> Result<()> {
+    let key = path
+        .to_str()
+        .ok_or_else(|| anyhow!("Path {} isn't valid UTF-8", path.display()))?;
+    let completed = management.c_files.contains(path);
+    let point = management.partial.get(path);
+    let error = management.failed_files.get(path);
+    if !completed && point.is_none() && error.is_none() {
+        tx.execute("DELETE FROM files WHERE path = ?1", [key])?;
+        return Ok(());
+    }
+
+    let stats = management
+        .c_stats
+        .get(path)
+        .map(serde_json::to_string)
+        .transpose()?;
+    tx.execute(
+        "INSERT INTO files (path, completed, stats, resume_lines, resume_bytes, error)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT (path) DO UPDATE SET
+            completed = excluded.completed,
+            stats = excluded.stats,
+            resume_lines = excluded.resume_lines,
+            resume_bytes = excluded.resume_bytes,
+            error = excluded.error",
+        params![
+            key,
+            completed,
+            stats,
+            point.map(|p| p.lines as i64),
+            point.map(|p| p.bytes as i64),
+            error
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::management::Change;
+
+    fn temp_db(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "ytms-management-{}-{name}.sqlite",
+            std::process::id()
+        ));
+        remove_db(&path);
+        path
+    }
+
+    /// Removes the database, along with the files SQLite keeps beside it in WAL mode.
+    fn remove_db(path: &Path) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(crate::management::with_suffix(path, suffix));
+        }
+    }
+
+    #[test]
+    fn round_trips_the_management_state() {
+        let path = temp_db("round-trip");
+        let mut management = Management {
+            query_hash: Some("abc".to_owned()),
+            ..Management::default()
+        };
+        management.apply(Change::Completed {
+            file: Some("a.zst".into()),
+            fingerprint: Some("fa".to_owned()),
+            lines: 10,
+            stats: Some(FileStats {
+                lines: 10,
+                ..FileStats::default()
+            }),
+        });
+        management.apply(Change::Checkpoint {
+            file: "b.zst".into(),
+            point: ResumePoint {
+                lines: 3,
+                bytes: 30,
+            },
+        });
+        management.apply(Change::Failed {
+            file: "c.zst".into(),
+            error: "broken".to_owned(),
+        });
+        Store::open(&path).unwrap().replace(&management).unwrap();
+
+        let loaded = load(&path).unwrap();
+        assert_eq!(loaded.c_files, management.c_files);
+        assert_eq!(loaded.c_lines, 10);
+        assert_eq!(loaded.c_hashes, management.c_hashes);
+        assert_eq!(loaded.query_hash.as_deref(), Some("abc"));
+        assert_eq!(loaded.partial[Path::new("b.zst")].bytes, 30);
+        assert_eq!(loaded.failed_files[Path::new("c.zst")], "broken");
+        assert_eq!(loaded.c_stats[Path::new("a.zst")].lines, 10);
+        remove_db(&path);
+    }
+
+    #[test]
+    fn updates_only_the_changed_rows() {
+        let path = temp_db("update");
+        let mut management = Management::default();
+        management.apply(Change::Checkpoint {
+            file: "a.zst".into(),
+            point: ResumePoint {
+                lines: 3,
+                bytes: 30,
+            },
+        });
+        let mut store = Store::open(&path).unwrap();
+        store.replace(&management).unwrap();
+
+        management.apply(Change::Completed {
+            file: Some("a.zst".into()),
+            fingerprint: None,
+            lines: 5,
+            stats: None,
+        });
+        management.apply(Change::Checkpoint {
+            file: "b.zst".into(),
+            point: ResumePoint { lines: 1, bytes: 8 },
+        });
+        // Only `a.zst` is written, so `b.zst` isn't recorded yet.
+        let changed = BTreeSet::from([PathBuf::from("a.zst")]);
+        store
+            .update(&management, &changed, &BTreeSet::new())
+            .unwrap();
+
+        let loaded = load(&path).unwrap();
+        assert!(loaded.c_files.contains(Path::new("a.zst")));
+        assert!(loaded.partial.is_empty());
+        assert_eq!(loaded.c_lines, 5);
+        drop(store);
+        remove_db(&path);
+    }
+}
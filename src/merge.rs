@@ -0,0 +1,138 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use xxhash_rust::xxh3::xxh3_128;
+
+use crate::{
+    aggregate::CHANNELS_SUFFIX,
+    output::{create_output, open_lines, read_record},
+};
+
+#[derive(Debug, clap::Args)]
+pub struct MergeArgs {
+    /// Output folders to merge.
+    #[clap(required = true, min_values = 2)]
+    dirs: Vec<PathBuf>,
+    /// Folder to write the merged files to.
+    #[clap(long = "output-dir", short = 'o')]
+    output_dir: PathBuf,
+}
+
+/// Files in an output folder which aren't query results.
+fn is_result_file(name: &str) -> bool {
+    name != "report.json"
+        && name != "errors.log"
+        && name != "malformed.jsonl"
+        && !name.ends_with(".seen-ids")
+        && !name.ends_with(CHANNELS_SUFFIX)
+}
+
+/// Finds the result files in an output folder, relative to the folder.
+fn find_results(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries =
+        std::fs::read_dir(dir).with_context(|| anyhow!("Error reading {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_dir() {
+            find_results(root, &path, files)?;
+        } else if name.ends_with(".partial") {
+            eprintln!("Skipping file {} (incomplete)", path.display());
+        } else if is_result_file(&name) {
+            files.push(path.strip_prefix(root).unwrap_or(&path).to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+/// The name of the merged file a result file goes in, without any compression extension.
+/// Numbered parts from rotated outputs (`name.0001`, `name.0002.zst`) are merged into a
+/// single file.
+fn merged_name(relative: &Path) -> (PathBuf, Option<&str>) {
+    let Some(name) = relative.file_name().and_then(|n| n.to_str()) else {
+        return (relative.to_path_buf(), None);
+    };
+    let (stem, compression) = match name.rsplit_once('.') {
+        Some((stem, ext @ ("zst" | "gz"))) => (stem, Some(ext)),
+        _ => (name, None),
+    };
+    let stem = match stem.rsplit_once('.') {
+        Some((base, part)) if part.len() == 4 && part.bytes().all(|b| b.is_ascii_digit()) => base,
+        _ => stem,
+    };
+
+    (relative.with_file_name(stem), compression)
+}
+
+/// Merges the query results from several output folders, such as from runs on different
+/// machines, dropping records which appear more than once.
+pub fn merge_output(args: &MergeArgs) -> Result<()> {
+    if args.dirs.iter().any(|dir| dir == &args.output_dir) {
+        bail!("The output folder can't be one of the folders being merged");
+    }
+
+    // Each merged file, along with the files going into it. The merged file is compressed
+    // the same way as the first of them.
+    let mut merged: BTreeMap<PathBuf, (Option<String>, Vec<PathBuf>)> = BTreeMap::new();
+    for dir in &args.dirs {
+        let mut files = Vec::new();
+        find_results(dir, dir, &mut files)?;
+        files.sort();
+        for file in files {
+            let (name, compression) = merged_name(&file);
+            merged
+                .entry(name)
+                .or_insert_with(|| (compression.map(str::to_owned), Vec::new()))
+                .1
+                .push(dir.join(file));
+        }
+    }
+
+    for (name, (compression, sources)) in merged {
+        let mut path = args.output_dir.join(&name).into_os_string();
+        if let Some(ext) = compression {
+            path.push(".");
+            path.push(ext);
+        }
+        let path = PathBuf::from(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| anyhow!("Error creating output directory {}", parent.display()))?;
+        }
+
+        // 128-bit hashes make a collision dropping a distinct record vanishingly unlikely.
+        let mut seen = HashSet::new();
+        let (mut records, mut duplicates) = (0u64, 0u64);
+        let mut output = create_output(&path)?;
+        let mut line = String::new();
+        for source in &sources {
+            let mut reader = open_lines(source)
+                .with_context(|| anyhow!("Error opening {}", source.display()))?;
+            while read_record(&mut reader, &mut line)
+                .with_context(|| anyhow!("Error reading {}", source.display()))?
+            {
+                if seen.insert(xxh3_128(line.as_bytes())) {
+                    output.write_all(line.as_bytes())?;
+                    records += 1;
+                } else {
+                    duplicates += 1;
+                }
+            }
+        }
+        output
+            .flush()
+            .with_context(|| anyhow!("Error writing to {}", path.display()))?;
+
+        println!(
+            "Merged {} files into {}, {records} records, dropped {duplicates} duplicates",
+            sources.len(),
+            path.display()
+        );
+    }
+
+    Ok(())
+}
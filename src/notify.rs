@@ -0,0 +1,110 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+
+use crate::report::Summary;
+
+/// How long to wait for the server to take the notification.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How the run ended.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Finished,
+    /// Stopped part way through by Ctrl-C, or from the dashboard.
+    Interrupted,
+    /// Stopped part way through by `--max-runtime`.
+    TimedOut,
+    Failed,
+}
+
+/// Sent when the run ends, as JSON.
+#[derive(Debug, Serialize)]
+pub struct Notification<'a> {
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The totals for the run, if it got far enough to start searching.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<&'a Summary>,
+}
+
+/// Tells something outside that the run has ended, so that long runs don't have to be
+/// watched.
+#[derive(Debug, Default)]
+pub struct Notifier {
+    /// Sent a POST request with the notification as its body.
+    pub url: Option<String>,
+    /// Run by the shell, with the notification on its stdin, and the outcome in
+    /// `YTMS_OUTCOME`.
+    pub command: Option<String>,
+}
+
+impl Notifier {
+    /// Sends the notification. Failing to isn't an error for the run, so any problems are
+    /// printed rather than returned.
+    pub fn notify(&self, notification: &Notification) {
+        if self.url.is_none() && self.command.is_none() {
+            return;
+        }
+        let body = match serde_json::to_string(notification) {
+            Ok(body) => body,
+            Err(e) => {
+                eprintln!("Error encoding notification: {e}");
+                return;
+            }
+        };
+        if let Some(url) = &self.url {
+            if let Err(e) = post(url, &body) {
+                eprintln!("Error sending notification to {url}: {e:#}");
+            }
+        }
+        if let Some(command) = &self.command {
+            if let Err(e) = run(command, notification.outcome, &body) {
+                eprintln!("Error running notification command: {e:#}");
+            }
+        }
+    }
+}
+
+fn post(url: &str, body: &str) -> Result<()> {
+    ureq::post(url)
+        .timeout(TIMEOUT)
+        .set("Content-Type", "application/json")
+        .send_string(body)?;
+    Ok(())
+}
+
+fn run(command: &str, outcome: Outcome, body: &str) -> Result<()> {
+    #[cfg(unix)]
+    let mut shell = Command::new("sh");
+    #[cfg(unix)]
+    shell.arg("-c");
+    #[cfg(not(unix))]
+    let mut shell = Command::new("cmd");
+    #[cfg(not(unix))]
+    shell.arg("/C");
+
+    let outcome = serde_json::to_value(outcome)?;
+    let mut child = shell
+        .arg(command)
+        .env("YTMS_OUTCOME", outcome.as_str().unwrap_or_default())
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| anyhow!("Error starting `{command}`"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The command doesn't have to read it.
+        let _ = stdin.write_all(body.as_bytes());
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("`{command}` exited with {status}");
+    }
+    Ok(())
+}
@@ -0,0 +1,881 @@
+use std::{
+    borrow::Cow,
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    decode::DecodeOptions,
+    record::RecordView,
+    source::{Source, ZstdFile},
+};
+
+/// How matched lines are written to the output files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// The matched lines exactly as they were read.
+    #[default]
+    Raw,
+    /// One JSON object per match, recording the query, source file, and line number along
+    /// with the original record.
+    Jsonl,
+    /// The selected `--fields` of each record, as comma-separated values.
+    Csv,
+    /// The selected `--fields` of each record, as tab-separated values.
+    Tsv,
+    /// Only the `id` of each matched record, one per line, with each ID written once.
+    /// Records without an ID are left out.
+    Ids,
+}
+
+/// A per-query output file, possibly being compressed on the way out.
+pub type OutputWriter = BufWriter<Box<dyn Write + Send>>;
+
+/// What to do with output files that already exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    Append,
+    Overwrite,
+    /// Refuse to write to a file that already has something in it.
+    CreateNew,
+}
+
+/// Settings shared by all of the output files.
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    pub mode: WriteMode,
+    pub compression: Option<Compression>,
+    /// Start a new numbered file once the current one reaches this size.
+    pub max_size: Option<u64>,
+    /// The capacity of each file's write buffer.
+    pub buffer_size: usize,
+}
+
+/// A query's output file, or set of numbered files if rotating by size.
+///
+/// The file being written to has `.partial` added to its name, which is removed once it's
+/// complete, either when the output is finished or when moving on to the next file. That way
+/// a file left behind by an interrupted run can't be mistaken for complete results.
+pub struct OutputFile {
+    base_path: PathBuf,
+    options: OutputOptions,
+    header: Option<String>,
+    /// The current file number, if rotating.
+    part: Option<u32>,
+    /// How many bytes have been written to the current file, after compression.
+    written: Arc<AtomicU64>,
+    writer: OutputWriter,
+    /// The file currently being written to, for syncing it to disk. Stdout isn't synced.
+    file: Option<File>,
+    /// Written before each record, when streaming to stdout.
+    prefix: Option<String>,
+}
+
+impl OutputFile {
+    /// Opens the output file, writing the header if it's empty. When rotating, appending
+    /// continues with the last existing part, and overwriting removes all existing parts.
+    ///
+    /// Appending to a finished file moves it back to being a partial file until this output
+    /// is finished.
+    pub fn open(
+        base_path: PathBuf,
+        options: &OutputOptions,
+        header: Option<String>,
+    ) -> Result<Self> {
+        let part = match options.max_size {
+            None => None,
+            Some(_) => {
+                let mut last = 1;
+                let part_exists = |part| {
+                    let path = part_path(&base_path, part, options.compression);
+                    path.exists() || partial_path(&path).exists()
+                };
+                while part_exists(last + 1) {
+                    last += 1;
+                }
+
+                if options.mode == WriteMode::Overwrite {
+                    for part in 2..=last {
+                        let path = part_path(&base_path, part, options.compression);
+                        remove_if_exists(&path)?;
+                        remove_if_exists(&partial_path(&path))?;
+                    }
+                    Some(1)
+                } else {
+                    Some(last)
+                }
+            }
+        };
+
+        let (writer, written, file) = open_writer(&base_path, part, options, header.as_deref())?;
+        Ok(Self {
+            base_path,
+            options: options.clone(),
+            header,
+            part,
+            written,
+            writer,
+            file: Some(file),
+            prefix: None,
+        })
+    }
+
+    /// Creates an output which streams the query's records to stdout, each prefixed with the
+    /// query name and a tab.
+    pub fn stdout(query: &str) -> Self {
+        Self {
+            base_path: PathBuf::from("<stdout>"),
+            options: OutputOptions {
+                mode: WriteMode::Append,
+                compression: None,
+                max_size: None,
+                buffer_size: 0,
+            },
+            header: None,
+            part: None,
+            written: Arc::default(),
+            // Stdout is shared between the queries, so records are passed straight through
+            // to its own buffer rather than risk a partial record being flushed.
+            writer: BufWriter::with_capacity(0, Box::new(io::stdout())),
+            file: None,
+            prefix: Some(format!("{query}\t")),
+        }
+    }
+
+    /// The path of the file currently being written to.
+    pub fn path(&self) -> PathBuf {
+        let path = self.finished_path();
+        match self.prefix {
+            Some(_) => path,
+            None => partial_path(&path),
+        }
+    }
+
+    /// The path the current file is given once it's complete.
+    fn finished_path(&self) -> PathBuf {
+        current_path(&self.base_path, self.part, self.options.compression)
+    }
+
+    /// Writes a whole record, first moving on to the next file if the current one is full.
+    pub fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        if let (Some(part), Some(max_size)) = (self.part, self.options.max_size) {
+            // Make sure everything's made its way through the encoder before checking.
+            let mut written = self.written.load(Ordering::Relaxed);
+            if written + self.writer.buffer().len() as u64 >= max_size {
+                self.writer.flush()?;
+                written = self.written.load(Ordering::Relaxed);
+            }
+
+            if written >= max_size {
+                let options = OutputOptions {
+                    // The next part may be left over from an earlier run.
+                    mode: WriteMode::Overwrite,
+                    ..self.options.clone()
+                };
+                let (writer, counter, file) = open_writer(
+                    &self.base_path,
+                    Some(part + 1),
+                    &options,
+                    self.header.as_deref(),
+                )
+                .map_err(|e| io::Error::other(format!("{e:#}")))?;
+                // Dropping the old writer flushes it and finishes its compressed stream,
+                // after which the old part is complete.
+                let (partial, finished) = (self.path(), self.finished_path());
+                self.writer.flush()?;
+                self.writer = writer;
+                self.file = Some(file);
+                std::fs::rename(partial, finished)?;
+                self.written = counter;
+                self.part = Some(part + 1);
+            }
+        }
+
+        match &self.prefix {
+            Some(prefix) => self.writer.write_all(&[prefix.as_bytes(), record].concat()),
+            None => self.writer.write_all(record),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Flushes the output, and waits for it to reach the disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        match &self.file {
+            Some(file) => file.sync_data(),
+            None => Ok(()),
+        }
+    }
+
+    /// Stops writing the output, leaving the current file `.partial` for the next run to
+    /// carry on with.
+    pub fn close(mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        // This finishes the compressed stream, if there is one.
+        drop(self.writer);
+        Ok(())
+    }
+
+    /// Finishes writing the output, and removes the `.partial` from the current file's name.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let (partial, finished) = (self.path(), self.finished_path());
+        // This finishes the compressed stream, if there is one.
+        drop(self.writer);
+        if self.prefix.is_none() {
+            std::fs::rename(partial, finished)?;
+        }
+        Ok(())
+    }
+}
+
+/// Opens an output file for reading, decompressing it if it ends in `.zst` or `.gz`.
+pub fn open_lines(path: &Path) -> Result<Box<dyn BufRead + Send>> {
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(File::open(
+            path,
+        )?))))
+    } else {
+        ZstdFile::new(path.to_path_buf()).lines(&DecodeOptions::default())
+    }
+}
+
+/// Creates a file, compressing it if it ends in `.zst` or `.gz`.
+pub fn create_output(path: &Path) -> Result<BufWriter<Box<dyn Write + Send>>> {
+    create_output_as(path, path)
+}
+
+/// Creates a file, compressing it if `name` ends in `.zst` or `.gz`. For writing a file
+/// under a temporary name, before it's moved into place as `name`.
+pub fn create_output_as(path: &Path, name: &Path) -> Result<BufWriter<Box<dyn Write + Send>>> {
+    let file = File::create(path)
+        .with_context(|| anyhow!("Error creating output file {}", path.display()))?;
+    let compression = name
+        .extension()
+        .and_then(|ext| Compression::parse(ext.to_str()?).ok());
+    let writer = match compression {
+        Some(compression) => compression.wrap(file)?,
+        None => Box::new(file),
+    };
+    Ok(BufWriter::new(writer))
+}
+
+/// Reads a line, ending it with a single `\n` whether it ended with CRLF or was the last line
+/// and missing its newline.
+pub fn read_record(reader: &mut impl BufRead, line: &mut String) -> Result<bool> {
+    line.clear();
+    if reader.read_line(line)? == 0 {
+        return Ok(false);
+    }
+    line.truncate(line.trim_end_matches(['\n', '\r']).len());
+    line.push('\n');
+    Ok(true)
+}
+
+pub(crate) fn partial_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".partial");
+    name.into()
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(e).with_context(|| anyhow!("Error removing old output file {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The files a query's output has been written to so far, in order: the one file, or each
+/// of the numbered parts if it was rotated, under whichever compression it was written with.
+/// Each is the finished file if there is one, otherwise the `.partial` file still being
+/// written to.
+pub(crate) fn output_parts(base_path: &Path) -> Vec<PathBuf> {
+    let existing = |path: PathBuf| match path.exists() {
+        true => Some(path),
+        false => Some(partial_path(&path)).filter(|path| path.exists()),
+    };
+    // Only the extension matters here, not the level.
+    for compression in [None, Some(Compression::Zstd(0)), Some(Compression::Gzip(0))] {
+        if let Some(path) = existing(current_path(base_path, None, compression)) {
+            return vec![path];
+        }
+        let parts: Vec<_> = (1..)
+            .map_while(|part| existing(part_path(base_path, part, compression)))
+            .collect();
+        if !parts.is_empty() {
+            return parts;
+        }
+    }
+    Vec::new()
+}
+
+fn part_path(base_path: &Path, part: u32, compression: Option<Compression>) -> PathBuf {
+    current_path(base_path, Some(part), compression)
+}
+
+fn current_path(base_path: &Path, part: Option<u32>, compression: Option<Compression>) -> PathBuf {
+    let mut name = OsString::from(base_path.as_os_str());
+    if let Some(part) = part {
+        name.push(format!(".{part:04}"));
+    }
+    if let Some(compression) = compression {
+        name.push(".");
+        name.push(compression.extension());
+    }
+    name.into()
+}
+
+fn open_writer(
+    base_path: &Path,
+    part: Option<u32>,
+    options: &OutputOptions,
+    header: Option<&str>,
+) -> Result<(OutputWriter, Arc<AtomicU64>, File)> {
+    let finished = current_path(base_path, part, options.compression);
+    let path = partial_path(&finished);
+    match options.mode {
+        WriteMode::CreateNew => {
+            for path in [&finished, &path] {
+                if path.metadata().is_ok_and(|m| m.len() > 0) {
+                    bail!(
+                        "Output file {} already exists, leave out --create-new to overwrite it",
+                        path.display()
+                    );
+                }
+            }
+        }
+        // A partial file is left over from an interrupted run, so carries on from where that
+        // got to, otherwise we pick up the finished file.
+        WriteMode::Append if !path.exists() && finished.exists() => {
+            std::fs::rename(&finished, &path)
+                .with_context(|| anyhow!("Error reopening output file {}", finished.display()))?;
+        }
+        WriteMode::Append => {}
+        WriteMode::Overwrite => remove_if_exists(&finished)?,
+    }
+
+    let existing_len = path.metadata().map_or(0, |m| m.len());
+    let append = options.mode == WriteMode::Append;
+
+    let file = OpenOptions::new()
+        .create(true)
+        .append(append)
+        .write(true)
+        .truncate(!append)
+        .open(&path)
+        .with_context(|| anyhow!("Error creating output file {}", path.display()))?;
+
+    let written = Arc::new(AtomicU64::new(if append { existing_len } else { 0 }));
+    let handle = file
+        .try_clone()
+        .with_context(|| anyhow!("Error opening output file {}", path.display()))?;
+    let file = CountingWriter {
+        inner: file,
+        written: written.clone(),
+    };
+    let file: Box<dyn Write + Send> = match options.compression {
+        Some(compression) => compression
+            .wrap(file)
+            .with_context(|| anyhow!("Error creating output file {}", path.display()))?,
+        None => Box::new(file),
+    };
+
+    let mut writer = BufWriter::with_capacity(options.buffer_size, file);
+    if written.load(Ordering::Relaxed) == 0 {
+        if let Some(header) = header {
+            writer
+                .write_all(header.as_bytes())
+                .with_context(|| anyhow!("Error writing to output file {}", path.display()))?;
+        }
+    }
+
+    Ok((writer, written, handle))
+}
+
+/// Keeps track of how many bytes have made it to the file.
+struct CountingWriter<W> {
+    inner: W,
+    written: Arc<AtomicU64>,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Compression applied to the output files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Zstd(i32),
+    Gzip(u32),
+}
+
+impl Compression {
+    /// Parses `zstd` or `gzip`, optionally followed by `:level`.
+    pub fn parse(value: &str) -> Result<Self> {
+        let (name, level) = match value.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (value, None),
+        };
+        let level = level.map(|l| {
+            l.parse::<i32>()
+                .with_context(|| anyhow!("invalid compression level `{l}`"))
+        });
+
+        match name {
+            "zstd" | "zst" => Ok(Compression::Zstd(level.transpose()?.unwrap_or(3))),
+            "gzip" | "gz" => {
+                let level = level.transpose()?.unwrap_or(6);
+                if !(0..=9).contains(&level) {
+                    bail!("gzip level must be between 0 and 9");
+                }
+                Ok(Compression::Gzip(level as u32))
+            }
+            _ => bail!("unknown compression `{name}`, expected `zstd` or `gzip`"),
+        }
+    }
+
+    /// The extension added to the output file names.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Zstd(_) => "zst",
+            Compression::Gzip(_) => "gz",
+        }
+    }
+
+    /// Wraps the file in an encoder, which finishes the compressed stream when dropped.
+    pub fn wrap(self, file: impl Write + Send + 'static) -> io::Result<Box<dyn Write + Send>> {
+        match self {
+            Compression::Zstd(level) => {
+                Ok(Box::new(zstd::Encoder::new(file, level)?.auto_finish()))
+            }
+            Compression::Gzip(level) => Ok(Box::new(GzEncoder::new(
+                file,
+                flate2::Compression::new(level),
+            ))),
+        }
+    }
+}
+
+/// Where a match came from.
+pub struct Provenance<'a> {
+    pub query: &'a str,
+    pub source: &'a str,
+    /// The 1-based line number within the source. Not known when a file is split into
+    /// chunks which are searched in parallel.
+    pub line: Option<u64>,
+}
+
+/// Renders matched lines in the chosen output format.
+#[derive(Debug, Clone, Default)]
+pub struct Formatter {
+    format: OutputFormat,
+    /// The fields written in the CSV and TSV formats. Nested fields are separated by `.`.
+    fields: Vec<String>,
+}
+
+impl Formatter {
+    pub fn new(format: OutputFormat, fields: Vec<String>) -> Self {
+        Self { format, fields }
+    }
+
+    /// Whether only the records' IDs are written, which only makes sense with each of them
+    /// written once.
+    pub fn writes_ids(&self) -> bool {
+        self.format == OutputFormat::Ids
+    }
+
+    /// The header line written at the start of a new output file, if the format has one.
+    pub fn header(&self) -> Option<String> {
+        let separator = match self.format {
+            OutputFormat::Raw | OutputFormat::Jsonl | OutputFormat::Ids => return None,
+            OutputFormat::Csv => ",",
+            OutputFormat::Tsv => "\t",
+        };
+
+        let mut header = self
+            .fields
+            .iter()
+            .map(|f| self.escape(f))
+            .collect::<Vec<_>>()
+            .join(separator);
+        header.push('\n');
+        Some(header)
+    }
+
+    /// Renders a matched line onto the end of `out`, including its trailing newline.
+    ///
+    /// Returns `false` if the line can't be rendered, which happens when a format that
+    /// selects fields is given a line that isn't valid JSON.
+    pub fn format(&self, provenance: &Provenance, record: &RecordView, out: &mut Vec<u8>) -> bool {
+        let line = record.line();
+        match self.format {
+            OutputFormat::Raw => {
+                // Written with a single `\n` whatever the line ended with, so that CRLF inputs
+                // don't leave stray `\r`s and the last line of a file missing its newline
+                // doesn't run into the next match.
+                out.extend_from_slice(line.trim_end_matches(['\n', '\r']).as_bytes());
+            }
+            OutputFormat::Jsonl => {
+                // Written out by hand, so that the record can be copied in as it was written
+                // without parsing it again.
+                out.extend_from_slice(b"{\"query\":");
+                serde_json::to_writer(&mut *out, provenance.query).expect("strings serialize");
+                out.extend_from_slice(b",\"source\":");
+                serde_json::to_writer(&mut *out, provenance.source).expect("strings serialize");
+                if let Some(line) = provenance.line {
+                    out.extend_from_slice(format!(",\"line\":{line}").as_bytes());
+                }
+                out.extend_from_slice(b",\"record\":");
+                if record.is_json() {
+                    out.extend_from_slice(line.trim_matches([' ', '\t', '\n', '\r']).as_bytes());
+                } else {
+                    // Lines which aren't valid JSON are kept as a string.
+                    let text = line.trim_end_matches(['\n', '\r']);
+                    serde_json::to_writer(&mut *out, text).expect("strings serialize");
+                }
+                out.push(b'}');
+            }
+            OutputFormat::Ids => {
+                let Some(id) = record.id() else {
+                    return false;
+                };
+                out.extend_from_slice(id.as_bytes());
+            }
+            OutputFormat::Csv | OutputFormat::Tsv => {
+                if !record.is_json() {
+                    return false;
+                }
+                let separator = if self.format == OutputFormat::Csv {
+                    b','
+                } else {
+                    b'\t'
+                };
+                for (i, field) in self.fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(separator);
+                    }
+                    out.extend_from_slice(self.escape(&record.field_text(field)).as_bytes());
+                }
+            }
+        }
+
+        out.push(b'\n');
+        true
+    }
+
+    fn escape<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        match self.format {
+            OutputFormat::Csv => csv_escape(value),
+            // TSV has no quoting, so the separators are replaced instead.
+            OutputFormat::Tsv if value.contains(['\t', '\n', '\r']) => {
+                Cow::Owned(value.replace(['\t', '\n', '\r'], " "))
+            }
+            _ => Cow::Borrowed(value),
+        }
+    }
+}
+
+/// Quotes a CSV field, if it needs it.
+pub(crate) fn csv_escape(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Looks up a field, where nested fields are given as a dotted path (e.g. `record.id`).
+pub fn field_value<'a>(record: &'a Value, field: &str) -> Option<&'a Value> {
+    field
+        .split('.')
+        .try_fold(record, |value, key| value.get(key))
+}
+
+/// Cuts a JSON record down to the given top-level keys, in the order given, keeping their
+/// values exactly as written. Keys the record doesn't have are left out.
+/// The projection replaces the contents of `out`, which is reused between records.
+///
+/// Returns `false` if the line isn't a JSON object.
+pub fn project_fields(record: &RecordView, fields: &[String], out: &mut Vec<u8>) -> bool {
+    let Some(record) = record.fields() else {
+        return false;
+    };
+    out.clear();
+    out.push(b'{');
+    for (key, value) in fields.iter().filter_map(|f| record.get_key_value(f)) {
+        if out.len() > 1 {
+            out.push(b',');
+        }
+        serde_json::to_writer(&mut *out, key).expect("strings always serialize");
+        out.push(b':');
+        out.extend_from_slice(value.get().as_bytes());
+    }
+    out.extend_from_slice(b"}\n");
+    true
+}
+
+/// A per-query output template, such as `{id}\t{title}`, where each `{field}` is replaced
+/// by the record's field in the same way as the CSV and TSV formats. Literal braces are
+/// written as `{{` and `}}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Template {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Debug, Clone)]
+enum TemplatePart {
+    Text(String),
+    Field(String),
+}
+
+impl TryFrom<String> for Template {
+    type Error = anyhow::Error;
+
+    fn try_from(template: String) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| anyhow!("unclosed `{{` in template `{template}`"))?;
+                    let field = &rest[..end];
+                    if field.is_empty() || field.contains('{') {
+                        bail!("invalid field `{{{field}}}` in template `{template}`");
+                    }
+                    if !text.is_empty() {
+                        parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(TemplatePart::Field(field.to_owned()));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => bail!("unmatched `}}` in template `{template}`"),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(TemplatePart::Text(text));
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+impl Template {
+    /// Renders a matched line onto the end of `out`, including its trailing newline.
+    ///
+    /// Returns `false` if the line isn't valid JSON.
+    pub fn render(&self, record: &RecordView, out: &mut Vec<u8>) -> bool {
+        if !record.is_json() {
+            return false;
+        }
+        for part in &self.parts {
+            match part {
+                TemplatePart::Text(text) => out.extend_from_slice(text.as_bytes()),
+                TemplatePart::Field(field) => {
+                    out.extend_from_slice(record.field_text(field).as_bytes());
+                }
+            }
+        }
+        out.push(b'\n');
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, line: &str) -> String {
+        let template = Template::try_from(template.to_owned()).unwrap();
+        let mut out = Vec::new();
+        assert!(template.render(&RecordView::new(line), &mut out));
+        String::from_utf8(out).unwrap()
+    }
+
+    fn temp_folder(name: &str) -> PathBuf {
+        let folder =
+            std::env::temp_dir().join(format!("ytms-output-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    fn rotating(compression: Option<Compression>, mode: WriteMode) -> OutputOptions {
+        OutputOptions {
+            mode,
+            compression,
+            max_size: Some(10),
+            buffer_size: 0,
+        }
+    }
+
+    fn file_names(folder: &Path) -> Vec<String> {
+        let mut names: Vec<_> = std::fs::read_dir(folder)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn rotates_into_numbered_parts_each_with_the_header() {
+        let folder = temp_folder("rotate");
+        let base = folder.join("out.csv");
+        let options = rotating(None, WriteMode::Overwrite);
+        let mut output = OutputFile::open(base.clone(), &options, Some("h\n".to_owned())).unwrap();
+        output.write_record(b"0123456789\n").unwrap();
+        output.write_record(b"abcdefghij\n").unwrap();
+        // Only the part being written to is still partial.
+        assert_eq!(
+            file_names(&folder),
+            ["out.csv.0001", "out.csv.0002.partial"]
+        );
+        output.write_record(b"klmnopqrst\n").unwrap();
+        output.finish().unwrap();
+
+        assert_eq!(
+            file_names(&folder),
+            ["out.csv.0001", "out.csv.0002", "out.csv.0003"]
+        );
+        let part = |n| std::fs::read_to_string(folder.join(format!("out.csv.000{n}"))).unwrap();
+        assert_eq!(part(1), "h\n0123456789\n");
+        assert_eq!(part(3), "h\nklmnopqrst\n");
+
+        // Appending carries on with the last part, and overwriting starts again from the first.
+        let options = rotating(None, WriteMode::Append);
+        let output = OutputFile::open(base.clone(), &options, None).unwrap();
+        assert_eq!(output.path(), folder.join("out.csv.0003.partial"));
+        output.close().unwrap();
+        let options = rotating(None, WriteMode::Overwrite);
+        OutputFile::open(base, &options, None)
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert_eq!(file_names(&folder), ["out.csv.0001"]);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn compressed_parts_are_numbered_before_the_extension() {
+        let folder = temp_folder("rotate-compressed");
+        let options = rotating(Some(Compression::Gzip(1)), WriteMode::Overwrite);
+        let mut output = OutputFile::open(folder.join("out.jsonl"), &options, None).unwrap();
+        // Enough that it doesn't compress below the limit.
+        let record: Vec<u8> = (0..64).map(|i| b'a' + (i * 7 % 26) as u8).collect();
+        output.write_record(&record).unwrap();
+        output.write_record(&record).unwrap();
+        output.finish().unwrap();
+
+        assert_eq!(
+            file_names(&folder),
+            ["out.jsonl.0001.gz", "out.jsonl.0002.gz"]
+        );
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn finds_the_parts_written_so_far() {
+        let folder = temp_folder("parts");
+        let base = folder.join("out.jsonl");
+        assert!(output_parts(&base).is_empty());
+
+        for name in [
+            "out.jsonl.0001.zst",
+            "out.jsonl.0002.zst.partial",
+            "out.jsonl.0004.zst",
+        ] {
+            std::fs::write(folder.join(name), "").unwrap();
+        }
+        // The numbering stops at the first part missing.
+        assert_eq!(
+            output_parts(&base),
+            [
+                folder.join("out.jsonl.0001.zst"),
+                folder.join("out.jsonl.0002.zst.partial"),
+            ]
+        );
+
+        std::fs::write(folder.join("out.jsonl.partial"), "").unwrap();
+        assert_eq!(output_parts(&base), [folder.join("out.jsonl.partial")]);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn csv_fields_are_only_quoted_when_they_need_it() {
+        assert!(matches!(
+            csv_escape("plain text"),
+            Cow::Borrowed("plain text")
+        ));
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_escape("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_escape("cr\r"), "\"cr\r\"");
+        assert_eq!(csv_escape(""), "");
+    }
+
+    #[test]
+    fn templates_fill_in_fields_and_unescape_braces() {
+        let line = r#"{"id": "x1", "views": 12, "channel": {"name": "Chan"}, "tags": null}"#;
+        assert_eq!(render("{id}\t{views}", line), "x1\t12\n");
+        assert_eq!(render("{{{id}}} by {channel.name}", line), "{x1} by Chan\n");
+        assert_eq!(render("[{tags}{missing}]", line), "[]\n");
+        assert_eq!(render("no fields", line), "no fields\n");
+    }
+
+    #[test]
+    fn invalid_templates_are_refused() {
+        for template in ["{id", "id}", "{}", "{a{b}", "{id}}"] {
+            assert!(
+                Template::try_from(template.to_owned()).is_err(),
+                "`{template}` was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn templates_skip_lines_which_arent_json() {
+        let template = Template::try_from("{id}".to_owned()).unwrap();
+        let mut out = Vec::new();
+        assert!(!template.render(&RecordView::new("not json"), &mut out));
+        assert!(out.is_empty());
+    }
+}
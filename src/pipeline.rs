@@ -0,0 +1,211 @@
+//! The stages each input goes through on its way from the input folder to the sinks:
+//!
+//! 1. **Enumerate**: the inputs are handed out to be searched, up to `--io-threads` at once.
+//! 2. **Decode**: each input is opened and decompressed.
+//! 3. **Split lines**: the decompressed stream is cut into runs of whole lines.
+//! 4. **Match**: each line is checked against the queries.
+//! 5. **Format**: the lines which matched are rendered as records for each query.
+//! 6. **Sink**: the records are handed to the sinks, in the order they were read.
+//!
+//! By default the stages from decoding onwards run one after the other on the thread that
+//! picked up the input, a batch of lines at a time, which is the cheapest way to go when
+//! there are plenty of inputs to share between the threads. With `--parallel-chunks`, they
+//! are connected by bounded queues instead, so that they all run at once: the input is
+//! decoded and split on their own threads, chunks are matched on the searching threads,
+//! rendered on the `--format-threads` if there are any, and written out in order on the
+//! thread that picked up the input. How much can be waiting between them is set by
+//! `--pipeline-depth`.
+//!
+//! A single stream can only be decompressed on one thread. Inputs made of several zstd
+//! frames can be decoded in parallel with `--split-frames`, each group of frames going
+//! through the rest of the stages on its own.
+
+use std::{
+    io::{self, Read},
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread::Scope,
+};
+
+use memchr::memchr;
+use rayon::iter::{ParallelBridge, ParallelIterator};
+
+use crate::{input::Input, lines::count_lines, pool::BufferPool};
+
+/// The enumerate stage: searches the inputs on the pool's threads, largest first, so that
+/// we don't end up with one thread chewing through a huge file at the end while the rest sit
+/// idle.
+pub(crate) fn enumerate(
+    pool: &rayon::ThreadPool,
+    mut inputs: Vec<Input>,
+    search: impl Fn(&Input) + Sync + Send,
+) {
+    inputs.sort_by_cached_key(|input| std::cmp::Reverse(input.size()));
+
+    // Bridging from a sequential iterator means the files get picked up in the order we've
+    // sorted them in.
+    pool.install(|| inputs.iter().par_bridge().for_each(search));
+}
+
+/// A run of whole lines from the input, as cut by the split lines stage.
+pub(crate) struct Chunk<T> {
+    pub(crate) text: T,
+    /// The line number of the first line in the chunk, counting from 1.
+    pub(crate) first_line: u64,
+    pub(crate) lines: u64,
+}
+
+impl<T: AsRef<[u8]>> Chunk<T> {
+    fn new(text: T, first_line: u64) -> Self {
+        let lines = count_lines(text.as_ref());
+        Self {
+            text,
+            first_line,
+            lines,
+        }
+    }
+}
+
+/// A block of decompressed text, of which the chunk is the part from `start` on. The part
+/// before is the end of a line which went in the previous chunk.
+pub(crate) struct Block {
+    data: Vec<u8>,
+    start: usize,
+}
+
+impl Block {
+    /// Gives back the buffer, so that it can be used again.
+    pub(crate) fn into_buffer(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl AsRef<[u8]> for Block {
+    fn as_ref(&self) -> &[u8] {
+        &self.data[self.start..]
+    }
+}
+
+/// The decode stage: reads the decompressed stream in blocks of `size` bytes, until it runs
+/// out or the receiver goes away.
+pub(crate) fn decode_blocks(
+    mut reader: impl Read,
+    size: u64,
+    buffers: &BufferPool<Vec<u8>>,
+    blocks: SyncSender<io::Result<Vec<u8>>>,
+) {
+    loop {
+        let mut data = buffers.take();
+        match (&mut reader).take(size).read_to_end(&mut data) {
+            Ok(0) => return,
+            Ok(_) => {
+                if blocks.send(Ok(data)).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                let _ = blocks.send(Err(e));
+                return;
+            }
+        }
+    }
+}
+
+/// The split lines stage: cuts the decoded blocks into chunks of whole lines, by moving the
+/// start of each block's first line onto the end of the chunk before it. `next_line` is the
+/// number of lines before the stream's start.
+pub(crate) fn split_blocks(
+    blocks: Receiver<io::Result<Vec<u8>>>,
+    mut next_line: u64,
+    buffers: &BufferPool<Vec<u8>>,
+    chunks: SyncSender<io::Result<Chunk<Block>>>,
+) {
+    let recycle = |mut data: Vec<u8>| {
+        data.clear();
+        buffers.give(data);
+    };
+    // The block which will be sent once the end of its last line has been read.
+    let mut pending: Option<Block> = None;
+    for block in blocks {
+        let data = match block {
+            Ok(data) => data,
+            Err(e) => {
+                let _ = chunks.send(Err(e));
+                return;
+            }
+        };
+
+        let mut start = 0;
+        if let Some(mut block) = pending.take() {
+            match memchr(b'\n', &data) {
+                Some(end) => {
+                    block.data.extend_from_slice(&data[..=end]);
+                    start = end + 1;
+                    let chunk = Chunk::new(block, next_line + 1);
+                    next_line += chunk.lines;
+                    if chunks.send(Ok(chunk)).is_err() {
+                        return;
+                    }
+                }
+                // The line carries on past this block too.
+                None => {
+                    block.data.extend_from_slice(&data);
+                    recycle(data);
+                    pending = Some(block);
+                    continue;
+                }
+            }
+        }
+
+        if start < data.len() {
+            pending = Some(Block { data, start });
+        } else {
+            recycle(data);
+        }
+    }
+
+    if let Some(block) = pending {
+        let _ = chunks.send(Ok(Chunk::new(block, next_line + 1)));
+    }
+}
+
+/// Starts the decode and split lines stages for the stream on their own threads, returning
+/// the chunks of at least `size` bytes they cut it into. Up to `depth` blocks and chunks
+/// can be queued up between the stages.
+pub(crate) fn spawn_reader<'scope>(
+    scope: &'scope Scope<'scope, '_>,
+    reader: impl Read + Send + 'scope,
+    size: u64,
+    next_line: u64,
+    buffers: &'scope BufferPool<Vec<u8>>,
+    depth: usize,
+) -> Receiver<io::Result<Chunk<Block>>> {
+    let (block_sender, blocks) = mpsc::sync_channel(depth);
+    let (chunk_sender, chunks) = mpsc::sync_channel(depth);
+    scope.spawn(move || decode_blocks(reader, size, buffers, block_sender));
+    scope.spawn(move || split_blocks(blocks, next_line, buffers, chunk_sender));
+    chunks
+}
+
+/// The split lines stage for text that's already in memory: splits it into chunks of whole
+/// lines, of at least `size` bytes, without copying it.
+pub(crate) fn split_chunks(
+    mut text: &[u8],
+    size: u64,
+    mut next_line: u64,
+) -> impl Iterator<Item = io::Result<Chunk<&[u8]>>> {
+    let size = usize::try_from(size).unwrap_or(usize::MAX);
+    std::iter::from_fn(move || {
+        if text.is_empty() {
+            return None;
+        }
+        let end = match text.get(size..) {
+            Some(rest) => memchr(b'\n', rest).map_or(text.len(), |i| size + i + 1),
+            None => text.len(),
+        };
+        let (chunk, rest) = text.split_at(end);
+        text = rest;
+        let chunk = Chunk::new(chunk, next_line + 1);
+        next_line += chunk.lines;
+        Some(Ok(chunk))
+    })
+}
@@ -0,0 +1,32 @@
+use std::sync::Mutex;
+
+/// Emptied buffers kept to be used again, so that once a search is under way it isn't
+/// allocating new buffers for each chunk or batch of matches.
+pub struct BufferPool<T> {
+    buffers: Mutex<Vec<T>>,
+    /// The most buffers to keep. Any more are dropped, rather than holding on to the memory
+    /// from a burst that's passed.
+    limit: usize,
+}
+
+impl<T: Default> BufferPool<T> {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::new()),
+            limit,
+        }
+    }
+
+    /// Takes a buffer from the pool, or a new one if it's empty.
+    pub fn take(&self) -> T {
+        self.buffers.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    /// Returns a buffer to the pool. It should already have been cleared.
+    pub fn give(&self, buffer: T) {
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < self.limit {
+            buffers.push(buffer);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
+use memchr::{memchr, memrchr};
+
+use crate::{lines::count_lines, Automaton, Query};
+
+/// Finds the lines which might match one of the queries, so that the rest can be skipped
+/// without running each query's automaton over them.
+///
+/// Nearly every line matches nothing, so rather than going line by line, one automaton
+/// with every query's expressions is run over the whole text, and only the lines it finds
+/// something in are searched properly. Where the expressions only have a few bytes that are
+/// rare in text between them, it skips along with `memchr` looking for those rather than
+/// running the automaton over every byte.
+pub struct Prefilter {
+    /// `None` if every line has to be searched, because some query writes the lines which
+    /// don't match.
+    searcher: Option<AhoCorasick>,
+}
+
+impl Prefilter {
+    pub fn new(queries: &[Query], automaton: Automaton) -> Self {
+        let searcher = (!queries.iter().any(|q| q.invert)).then(|| {
+            AhoCorasickBuilder::new()
+                .ascii_case_insensitive(true)
+                .dfa(automaton == Automaton::Dfa)
+                .prefilter(true)
+                .build(queries.iter().flat_map(|q| &q.expressions))
+        });
+        Self { searcher }
+    }
+
+    /// A prefilter which lets every line through.
+    pub fn every_line() -> Self {
+        Self { searcher: None }
+    }
+
+    /// The lines of `text` which might match, along with their index among its lines.
+    pub fn candidates<'a>(&'a self, text: &'a [u8]) -> Candidates<'a> {
+        Candidates {
+            searcher: self.searcher.as_ref(),
+            text,
+            pos: 0,
+            line: 0,
+        }
+    }
+}
+
+pub struct Candidates<'a> {
+    searcher: Option<&'a AhoCorasick>,
+    text: &'a [u8],
+    /// Where the next line starts.
+    pos: usize,
+    /// The index of the line starting at `pos`.
+    line: u64,
+}
+
+impl<'a> Iterator for Candidates<'a> {
+    type Item = (u64, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.text.get(self.pos..).filter(|rest| !rest.is_empty())?;
+        let start = match self.searcher {
+            Some(searcher) => {
+                let found = match searcher.find(rest) {
+                    Some(found) => found.start(),
+                    None => {
+                        self.pos = self.text.len();
+                        return None;
+                    }
+                };
+                let start = memrchr(b'\n', &rest[..found]).map_or(0, |i| i + 1);
+                self.line += count_lines(&rest[..start]);
+                start
+            }
+            None => 0,
+        };
+        let end = memchr(b'\n', &rest[start..]).map_or(rest.len(), |i| start + i + 1);
+
+        let line = self.line;
+        self.line += 1;
+        self.pos += end;
+        Some((line, &rest[start..end]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use super::*;
+    use crate::lines::{Lines, ReaderLines};
+
+    fn prefilter(queries: &str) -> Prefilter {
+        let queries: Vec<Query> = serde_json::from_str(queries).unwrap();
+        Prefilter::new(&queries, Automaton::Nfa)
+    }
+
+    const TEXT: &[u8] = b"Music video\nnothing here\n\nsong and MUSIC\nplain\nlast song";
+
+    #[test]
+    fn finds_each_line_with_an_expression_once() {
+        let prefilter = prefilter(r#"[{"filename": "a", "expressions": ["music", "song"]}]"#);
+        let found: Vec<_> = prefilter.candidates(TEXT).collect();
+        assert_eq!(
+            found,
+            [
+                (0, &b"Music video\n"[..]),
+                (3, &b"song and MUSIC\n"[..]),
+                (5, &b"last song"[..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn inverted_queries_see_every_line() {
+        let prefilter =
+            prefilter(r#"[{"filename": "a", "expressions": ["music"], "invert": true}]"#);
+        let found: Vec<_> = prefilter.candidates(TEXT).map(|(i, _)| i).collect();
+        assert_eq!(found, [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn line_indices_carry_on_across_batches() {
+        let prefilter = prefilter(r#"[{"filename": "a", "expressions": ["music", "song"]}]"#);
+        let whole: Vec<_> = prefilter
+            .candidates(TEXT)
+            .map(|(i, line)| (i, line.to_vec()))
+            .collect();
+
+        // Small buffers split the text into batches at every line, and make lines longer
+        // than the buffer come through on their own.
+        for capacity in [1, 4, 13, 16, 64] {
+            let mut lines = ReaderLines::new(BufReader::with_capacity(capacity, TEXT));
+            let mut first_line = 0;
+            let mut batched = Vec::new();
+            while let Some(batch) = lines.next_batch().unwrap() {
+                batched.extend(
+                    prefilter
+                        .candidates(batch)
+                        .map(|(i, line)| (first_line + i, line.to_vec())),
+                );
+                first_line += count_lines(batch);
+            }
+            assert_eq!(batched, whole, "with a {capacity} byte buffer");
+        }
+    }
+}
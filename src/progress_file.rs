@@ -0,0 +1,169 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+
+use crate::{
+    display::{self, Snapshot},
+    interrupt::interrupted,
+    management::with_suffix,
+};
+
+/// How far the run has got, as written to the progress file.
+#[derive(Debug, Serialize)]
+struct Progress<'a> {
+    time: String,
+    elapsed_secs: f64,
+    /// Set in the last one written, once the run is over.
+    finished: bool,
+    interrupted: bool,
+    files: u64,
+    files_done: u64,
+    /// The compressed size of the inputs to be searched, and how much of it has been.
+    input_bytes: u64,
+    input_bytes_done: u64,
+    /// Decompressed bytes searched.
+    bytes_searched: u64,
+    lines: u64,
+    /// Since the file was last written.
+    lines_per_sec: f64,
+    bytes_per_sec: f64,
+    eta_secs: Option<f64>,
+    query_matches: BTreeMap<&'a str, u64>,
+    searching: Vec<Searching<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Searching<'a> {
+    file: &'a str,
+    /// Compressed bytes, if the size is known.
+    size: Option<u64>,
+    read: u64,
+}
+
+/// Writes how far the run has got to a JSON file every so often, so that it can be followed
+/// without reading the log. The file is replaced each time, so it's never seen half
+/// written. Dropping this writes it one last time, marked as finished.
+pub struct ProgressFile {
+    thread: Option<JoinHandle<()>>,
+    stop: Arc<(Mutex<bool>, Condvar)>,
+}
+
+/// Starts writing the progress file. The progress is tracked by [`display`], without
+/// drawing the bars if they aren't being shown.
+pub fn start(path: PathBuf, interval: Duration, queries: Vec<String>) -> ProgressFile {
+    display::start_hidden();
+    let stop = Arc::new((Mutex::new(false), Condvar::new()));
+    let thread = {
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            let mut last: Option<Snapshot> = None;
+            let (lock, wake) = &*stop;
+            let mut stopped = lock.lock().unwrap();
+            loop {
+                let finished = *stopped;
+                if let Some(display) = display::get() {
+                    let snapshot = display.snapshot();
+                    if let Err(e) = write(&path, &queries, &snapshot, last.as_ref(), finished) {
+                        eprintln!("{e:#}");
+                    }
+                    last = Some(snapshot);
+                }
+                if finished {
+                    return;
+                }
+                stopped = wake.wait_timeout(stopped, interval).unwrap().0;
+            }
+        })
+    };
+    ProgressFile {
+        thread: Some(thread),
+        stop,
+    }
+}
+
+fn write(
+    path: &Path,
+    queries: &[String],
+    snapshot: &Snapshot,
+    last: Option<&Snapshot>,
+    finished: bool,
+) -> Result<()> {
+    let (lines, bytes, secs) = match last {
+        Some(last) => (
+            snapshot.lines - last.lines,
+            snapshot.bytes - last.bytes,
+            (snapshot.elapsed - last.elapsed).as_secs_f64(),
+        ),
+        None => (
+            snapshot.lines,
+            snapshot.bytes,
+            snapshot.elapsed.as_secs_f64(),
+        ),
+    };
+    let rate = |count: u64| match secs {
+        0.0 => 0.0,
+        secs => count as f64 / secs,
+    };
+    let elapsed = snapshot.elapsed.as_secs_f64();
+    let eta_secs = (snapshot.done_bytes > 0 && !finished).then(|| {
+        let done = snapshot.done_bytes as f64 / snapshot.total_bytes.max(1) as f64;
+        (elapsed / done.min(1.0) - elapsed).max(0.0)
+    });
+
+    let progress = Progress {
+        time: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        elapsed_secs: elapsed,
+        finished,
+        interrupted: interrupted(),
+        files: snapshot.files,
+        files_done: snapshot.files_done,
+        input_bytes: snapshot.total_bytes,
+        input_bytes_done: snapshot.done_bytes,
+        bytes_searched: snapshot.bytes,
+        lines: snapshot.lines,
+        lines_per_sec: rate(lines),
+        bytes_per_sec: rate(bytes),
+        eta_secs,
+        query_matches: queries
+            .iter()
+            .enumerate()
+            .map(|(i, query)| {
+                let matches = snapshot.query_matches.get(i).copied().unwrap_or(0);
+                (query.as_str(), matches)
+            })
+            .collect(),
+        searching: snapshot
+            .searching
+            .iter()
+            .map(|file| Searching {
+                file: &file.name,
+                size: file.size,
+                read: file.read,
+            })
+            .collect(),
+    };
+
+    let temp_path = with_suffix(path, ".tmp");
+    let rendered = serde_json::to_string_pretty(&progress)?;
+    std::fs::write(&temp_path, rendered)
+        .and_then(|_| std::fs::rename(&temp_path, path))
+        .with_context(|| anyhow!("Error writing progress file {}", path.display()))
+}
+
+impl Drop for ProgressFile {
+    fn drop(&mut self) {
+        let (lock, wake) = &*self.stop;
+        *lock.lock().unwrap() = true;
+        wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
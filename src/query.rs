@@ -0,0 +1,173 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+};
+
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
+use anyhow::{anyhow, Context, Result};
+
+use crate::{
+    debug,
+    decode::DecodeOptions,
+    index::index_path,
+    input::{canonical_path, Input, InputSelector},
+    inverted::{field_texts, InvertedIndex},
+    lines::{Lines, ReaderLines, SliceLines},
+    logging::STATUS_TO_STDERR,
+    source::Source,
+    status,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct QueryArgs {
+    /// The expressions to look for. The records with a field containing any of them,
+    /// ignoring ASCII case, are written to stdout.
+    #[clap(required = true)]
+    expressions: Vec<String>,
+    /// Only look in this top-level field of the records, e.g. `title`.
+    #[clap(long = "field", short = 'f')]
+    field: Option<String>,
+    /// Folder containing the input files.
+    #[clap(long = "input-folder", short = 'i', alias = "files-folder")]
+    files_folder: String,
+    /// Glob pattern, relative to the input folder, used to find input files.
+    #[clap(long = "glob", short = 'g', default_value = "**/*.zst")]
+    glob: String,
+    /// Glob pattern, relative to the input folder, of files to skip. Can be repeated.
+    #[clap(long = "exclude", short = 'x')]
+    exclude: Vec<String>,
+    /// The folder the indexes were written to by `index --inverted`. Files without an up to
+    /// date inverted index are read in full.
+    #[clap(long = "index-folder")]
+    index_folder: PathBuf,
+    /// The largest window the zstd decoder will accept, as for searching.
+    #[clap(long = "zstd-window-log", value_parser = clap::value_parser!(u32).range(10..=31))]
+    zstd_window_log: Option<u32>,
+}
+
+/// The lines of the input which might match, counting from 0, or `None` if they all might
+/// because it doesn't have an up to date index.
+fn candidates(args: &QueryArgs, input: &Input, root: &Path) -> Result<Option<BTreeSet<u64>>> {
+    let Some(path) =
+        index_path(&args.index_folder, input, root, ".inv").filter(|path| path.exists())
+    else {
+        status!("{input} has no inverted index, reading all of it");
+        return Ok(None);
+    };
+    let (header, mut index) = InvertedIndex::open(&path)
+        .with_context(|| anyhow!("Error reading index {}", path.display()))?;
+    if input.fingerprint()? != Some(header.fingerprint) {
+        status!("{input} has changed since it was indexed, reading all of it");
+        return Ok(None);
+    }
+
+    let mut candidates = BTreeSet::new();
+    for expression in &args.expressions {
+        match index.candidates(expression, args.field.as_deref()) {
+            Ok(Some(lines)) => candidates.extend(lines),
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(anyhow!("Error reading index {}: {e}", path.display())),
+        }
+    }
+    Ok(Some(candidates))
+}
+
+/// Whether the record has a field containing one of the expressions.
+fn matches(line: &[u8], field: Option<&str>, searcher: &AhoCorasick) -> bool {
+    field_texts(&String::from_utf8_lossy(line))
+        .iter()
+        .filter(|(name, _)| field.is_none_or(|field| field == name))
+        .any(|(_, text)| searcher.is_match(text))
+}
+
+/// Writes out the input's matching records, only reading as far as the last line which
+/// might match. Returns how many were found.
+fn query_input(
+    args: &QueryArgs,
+    input: &Input,
+    candidates: Option<&BTreeSet<u64>>,
+    searcher: &AhoCorasick,
+    options: &DecodeOptions,
+    out: &mut impl Write,
+) -> Result<u64> {
+    let last = match candidates {
+        Some(candidates) => match candidates.last() {
+            Some(&last) => Some(last),
+            None => return Ok(0),
+        },
+        None => None,
+    };
+
+    let mut found = 0;
+    let mut line_number = 0;
+    let mut lines = ReaderLines::new(input.lines(options)?);
+    while let Some(batch) = lines
+        .next_batch()
+        .with_context(|| anyhow!("Error reading {input}"))?
+    {
+        for line in SliceLines::new(batch) {
+            let candidate = candidates.is_none_or(|c| c.contains(&line_number));
+            if candidate && matches(line, args.field.as_deref(), searcher) {
+                out.write_all(line)?;
+                if !line.ends_with(b"\n") {
+                    out.write_all(b"\n")?;
+                }
+                found += 1;
+            }
+            line_number += 1;
+        }
+        if last.is_some_and(|last| line_number > last) {
+            break;
+        }
+    }
+    Ok(found)
+}
+
+/// Writes out the records with a field containing any of the expressions, reading only the
+/// parts of the inputs which their inverted indexes show might match.
+pub fn query(args: &QueryArgs) -> Result<()> {
+    // The records go to stdout.
+    STATUS_TO_STDERR.store(true, Ordering::Relaxed);
+    let selection = InputSelector::new(&args.files_folder, &args.glob, &args.exclude)?.find()?;
+    let root = canonical_path(Path::new(&args.files_folder));
+    let options = DecodeOptions {
+        window_log_max: args.zstd_window_log,
+        ..DecodeOptions::default()
+    };
+    let searcher = AhoCorasickBuilder::new()
+        .ascii_case_insensitive(true)
+        .build(&args.expressions);
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let mut found = 0;
+    let mut skipped = 0;
+    for input in &selection.inputs {
+        let candidates = candidates(args, input, &root)?;
+        match &candidates {
+            Some(candidates) => debug!("{input}: {} candidate lines", candidates.len()),
+            None => debug!("{input}: reading every line"),
+        }
+        if candidates.as_ref().is_some_and(BTreeSet::is_empty) {
+            skipped += 1;
+            continue;
+        }
+        found += query_input(
+            args,
+            input,
+            candidates.as_ref(),
+            &searcher,
+            &options,
+            &mut out,
+        )?;
+    }
+    out.flush()?;
+
+    status!(
+        "Found {found} records in {} files, {skipped} of which were skipped using their index",
+        selection.inputs.len()
+    );
+    Ok(())
+}
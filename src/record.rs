@@ -0,0 +1,148 @@
+use std::{borrow::Cow, cell::OnceCell, collections::HashMap};
+
+use serde_json::{value::RawValue, Value};
+
+use crate::{output::field_value, schema};
+
+/// A line, parsed as a JSON record at most once, however many queries matched it and
+/// whatever each of them needs from it: its fields for the output format or template, the
+/// fields kept by `output_fields`, its ID for deduplicating. Only the top level is parsed
+/// up front, with each field's value kept as it was written until something looks into it.
+pub struct RecordView<'a> {
+    line: &'a str,
+    parsed: OnceCell<Parsed<'a>>,
+}
+
+enum Parsed<'a> {
+    Object(HashMap<String, &'a RawValue>),
+    /// Valid JSON, but not an object, so without any fields.
+    OtherJson,
+    Invalid,
+}
+
+impl<'a> RecordView<'a> {
+    pub fn new(line: &'a str) -> Self {
+        Self {
+            line,
+            parsed: OnceCell::new(),
+        }
+    }
+
+    /// The line, as it was read.
+    pub fn line(&self) -> &'a str {
+        self.line
+    }
+
+    fn parsed(&self) -> &Parsed<'a> {
+        self.parsed.get_or_init(|| {
+            if let Ok(fields) = serde_json::from_str(self.line) {
+                return Parsed::Object(fields);
+            }
+            // Only lines which aren't objects are parsed again, to see whether they're JSON
+            // at all.
+            match serde_json::from_str::<&RawValue>(self.line) {
+                Ok(_) => Parsed::OtherJson,
+                Err(_) => Parsed::Invalid,
+            }
+        })
+    }
+
+    /// Whether the line is valid JSON.
+    pub fn is_json(&self) -> bool {
+        !matches!(self.parsed(), Parsed::Invalid)
+    }
+
+    /// The record's top-level fields, with their values as written, if it's a JSON object.
+    pub fn fields(&self) -> Option<&HashMap<String, &'a RawValue>> {
+        match self.parsed() {
+            Parsed::Object(fields) => Some(fields),
+            Parsed::OtherJson | Parsed::Invalid => None,
+        }
+    }
+
+    /// Looks up a (possibly nested) field, where nested fields are given as a dotted path
+    /// (e.g. `record.id`). Only the top-level field's value is parsed. With a `--schema`,
+    /// records without the field are checked for its aliases, and the value is adapted to
+    /// the schema.
+    pub fn field(&self, field: &str) -> Option<Value> {
+        let Some(schema) = schema::get() else {
+            return self.raw_field(field);
+        };
+        let value = self.raw_field(field).or_else(|| {
+            schema
+                .aliases(field)
+                .iter()
+                .find_map(|alias| self.raw_field(alias))
+        })?;
+        Some(schema.adapt(field, value))
+    }
+
+    /// Looks up a field as it's written, without the schema.
+    fn raw_field(&self, field: &str) -> Option<Value> {
+        let (key, rest) = match field.split_once('.') {
+            Some((key, rest)) => (key, Some(rest)),
+            None => (field, None),
+        };
+        let value: Value = serde_json::from_str(self.fields()?.get(key)?.get()).ok()?;
+        match rest {
+            Some(rest) => field_value(&value, rest).cloned(),
+            None => Some(value),
+        }
+    }
+
+    /// Looks up a field like [`RecordView::field`], rendering it as text. Strings are written
+    /// without quotes, missing fields and nulls are empty, and anything else is written as
+    /// JSON.
+    pub fn field_text(&self, field: &str) -> Cow<'a, str> {
+        // Top-level strings without any escapes can be used as they're written, unless the
+        // schema might change them.
+        let adapted = schema::get().is_some_and(|schema| schema.adapts(field));
+        let raw = (!field.contains('.') && !adapted)
+            .then(|| self.fields()?.get(field))
+            .flatten();
+        if let Some(text) = raw.and_then(|raw| unescaped_string(raw.get())) {
+            return Cow::Borrowed(text);
+        }
+
+        match self.field(field) {
+            None | Some(Value::Null) => Cow::Borrowed(""),
+            Some(Value::String(s)) => Cow::Owned(s),
+            Some(value) => Cow::Owned(value.to_string()),
+        }
+    }
+
+    /// The record itself if the line was written in the `jsonl` output format, where it's
+    /// under `record` alongside where it was found.
+    pub fn inner_record(&self) -> Option<RecordView<'a>> {
+        let fields = self.fields()?;
+        if fields.contains_key("id") {
+            return None;
+        }
+        let raw = fields.get("record")?;
+        Some(RecordView::new(raw.get()))
+    }
+
+    /// The record's `id` field, if it has one and it's a string.
+    pub fn id(&self) -> Option<Cow<'a, str>> {
+        let Some(raw) = self.fields()?.get("id") else {
+            // Only records without an `id` can have it under an alias.
+            return match self.field("id")? {
+                Value::String(id) => Some(Cow::Owned(id)),
+                _ => None,
+            };
+        };
+        if let Some(id) = unescaped_string(raw.get()) {
+            return Some(Cow::Borrowed(id));
+        }
+        serde_json::from_str::<Option<String>>(raw.get())
+            .ok()?
+            .map(Cow::Owned)
+    }
+}
+
+/// The contents of a JSON string as written, if it doesn't need unescaping.
+fn unescaped_string(raw: &str) -> Option<&str> {
+    raw.strip_prefix('"')?
+        .strip_suffix('"')
+        .filter(|text| !text.contains('\\'))
+}
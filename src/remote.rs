@@ -0,0 +1,158 @@
+use std::{
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, OnceLock,
+    },
+    thread,
+};
+
+use anyhow::Result;
+
+/// The size of the blocks downloaded ahead of the search. Each is handed over whole, so
+/// that the searching thread isn't woken up for every packet.
+const BLOCK_SIZE: usize = 1 << 20;
+
+/// How much of each remote input can be downloaded ahead of the search.
+static READ_AHEAD: AtomicU64 = AtomicU64::new(64 << 20);
+static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+
+/// Sets how much of each remote input can be downloaded ahead of the search.
+pub fn set_read_ahead(bytes: u64) {
+    READ_AHEAD.store(bytes, Ordering::Relaxed);
+}
+
+fn agent() -> &'static ureq::Agent {
+    AGENT.get_or_init(ureq::Agent::new)
+}
+
+/// Reads a remote input, which is downloaded on a thread of its own ahead of whoever's
+/// reading it. That way the network reads carry on while the searching thread works through
+/// what's already arrived, rather than it sitting idle waiting on each read in turn.
+pub struct RemoteReader {
+    blocks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    block: Vec<u8>,
+    /// How much of `block` has been read.
+    position: usize,
+}
+
+impl Read for RemoteReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.block.len() {
+            match self.blocks.recv() {
+                Ok(Ok(block)) => {
+                    self.block = block;
+                    self.position = 0;
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(mpsc::RecvError) => return Ok(0),
+            }
+        }
+        let read = (&self.block[self.position..]).read(buf)?;
+        self.position += read;
+        Ok(read)
+    }
+}
+
+/// Starts downloading the URL, returning once the server has responded.
+pub fn open_url(url: &str) -> Result<RemoteReader> {
+    // The agent's errors already say which URL they're about, and error statuses are
+    // returned as errors.
+    let response = agent().get(url).call()?;
+
+    let blocks = READ_AHEAD.load(Ordering::Relaxed) as usize / BLOCK_SIZE;
+    let (sender, receiver) = mpsc::sync_channel(blocks.max(1));
+    let body = response.into_reader();
+    thread::Builder::new()
+        .name("remote-io".to_owned())
+        .spawn(move || download(body, sender))?;
+    Ok(RemoteReader {
+        blocks: receiver,
+        block: Vec::new(),
+        position: 0,
+    })
+}
+
+/// Downloads the response's body in blocks, until it ends or the reader is dropped. The
+/// channel is bounded, so the download waits whenever it gets too far ahead.
+fn download(mut body: impl Read, blocks: mpsc::SyncSender<io::Result<Vec<u8>>>) {
+    loop {
+        let mut block = Vec::with_capacity(BLOCK_SIZE);
+        // What's arrived so far is handed over before any error, so that it's searched.
+        let result = (&mut body).take(BLOCK_SIZE as u64).read_to_end(&mut block);
+        let ended = matches!(result, Ok(read) if read < BLOCK_SIZE);
+        if !block.is_empty() && blocks.send(Ok(block)).is_err() {
+            return;
+        }
+        match result {
+            Ok(_) if ended => return,
+            Ok(_) => {}
+            Err(e) => {
+                let _ = blocks.send(Err(e));
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads its data, then fails.
+    struct Failing(io::Cursor<Vec<u8>>);
+
+    impl Read for Failing {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::Error::other("connection reset")),
+                read => Ok(read),
+            }
+        }
+    }
+
+    fn reader(blocks: mpsc::Receiver<io::Result<Vec<u8>>>) -> RemoteReader {
+        RemoteReader {
+            blocks,
+            block: Vec::new(),
+            position: 0,
+        }
+    }
+
+    #[test]
+    fn hands_over_the_body_in_whole_blocks() {
+        let body: Vec<u8> = (0..BLOCK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        let (sender, receiver) = mpsc::sync_channel(8);
+        download(&body[..], sender);
+
+        let sizes: Vec<_> = receiver.try_iter().map(|b| b.unwrap().len()).collect();
+        assert_eq!(sizes, [BLOCK_SIZE, BLOCK_SIZE, 10]);
+    }
+
+    #[test]
+    fn only_reads_ahead_as_far_as_the_channel_allows() {
+        let body = vec![0; BLOCK_SIZE * 4];
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let download = thread::spawn(move || download(&body[..], sender));
+
+        // The download is held up until the reader takes a block off the channel.
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!download.is_finished());
+        let mut read = Vec::new();
+        reader(receiver).read_to_end(&mut read).unwrap();
+        download.join().unwrap();
+        assert_eq!(read.len(), BLOCK_SIZE * 4);
+    }
+
+    #[test]
+    fn passes_on_what_arrived_before_an_error() {
+        let (sender, receiver) = mpsc::sync_channel(8);
+        download(Failing(io::Cursor::new(b"partial".to_vec())), sender);
+
+        let mut reader = reader(receiver);
+        let mut read = [0; 16];
+        assert_eq!(reader.read(&mut read).unwrap(), 7);
+        assert_eq!(&read[..7], b"partial");
+        assert!(reader.read(&mut read).is_err());
+    }
+}
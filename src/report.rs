@@ -0,0 +1,284 @@
+use std::{collections::BTreeMap, fmt, path::Path, time::Duration};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+
+use crate::{input::Input, search::StreamStats};
+
+/// A report on the run, written to `report.json` in the output folder.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    #[serde(flatten)]
+    summary: Summary,
+    files: Vec<FileReport>,
+}
+
+/// The totals over the whole run, printed at the end of it.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    /// Total matches for each query.
+    queries: BTreeMap<String, u64>,
+    files_searched: u64,
+    /// Files which weren't searched, because they'd already been searched, or were
+    /// duplicates of files that had.
+    files_skipped: u64,
+    files_failed: u64,
+    /// Files left part way through when the run was stopped. What they'd been searched up to
+    /// is counted in the totals.
+    files_partial: u64,
+    lines: u64,
+    /// Decompressed bytes read from the inputs.
+    bytes_read: u64,
+    matches: u64,
+    /// Lines skipped by `--check-records`, as they weren't records.
+    malformed_lines: u64,
+    /// Matching lines skipped as they weren't valid UTF-8.
+    invalid_utf8_lines: u64,
+    /// The fraction of the lines searched, with `--sample`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sample: Option<f64>,
+    /// How many matches searching every line would find, going by the sample.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    estimated_matches: Option<u64>,
+    wall_time_secs: f64,
+    lines_per_sec: f64,
+    bytes_per_sec: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct FileReport {
+    file: String,
+    lines: u64,
+    bytes_read: u64,
+    matches: u64,
+    malformed_lines: u64,
+    invalid_utf8_lines: u64,
+    /// Matches for each query.
+    queries: BTreeMap<String, u64>,
+    secs: f64,
+    /// Whether the run was stopped part way through the file, so these only cover what
+    /// had been searched of it.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    partial: bool,
+}
+
+impl Report {
+    /// Starts the report for the queries, with the fraction of the lines being searched if
+    /// it's only a sample.
+    pub fn new<'a>(queries: impl IntoIterator<Item = &'a str>, sample: Option<f64>) -> Self {
+        Self {
+            summary: Summary {
+                queries: queries.into_iter().map(|q| (q.to_owned(), 0)).collect(),
+                files_searched: 0,
+                files_skipped: 0,
+                files_failed: 0,
+                files_partial: 0,
+                lines: 0,
+                bytes_read: 0,
+                matches: 0,
+                malformed_lines: 0,
+                invalid_utf8_lines: 0,
+                sample,
+                estimated_matches: None,
+                wall_time_secs: 0.0,
+                lines_per_sec: 0.0,
+                bytes_per_sec: 0.0,
+            },
+            files: Vec::new(),
+        }
+    }
+
+    /// Records a completed input. The query names must be in the same order as the stats'
+    /// per-query counts.
+    pub fn add_file<'a>(
+        &mut self,
+        input: &Input,
+        queries: impl IntoIterator<Item = &'a str>,
+        stats: &StreamStats,
+        elapsed: Duration,
+    ) {
+        self.summary.files_searched += 1;
+        self.push_file(input, queries, stats, elapsed, false);
+    }
+
+    /// Records an input the run was stopped part way through, counting what had been
+    /// searched of it so far.
+    pub fn add_partial<'a>(
+        &mut self,
+        input: &Input,
+        queries: impl IntoIterator<Item = &'a str>,
+        stats: &StreamStats,
+        elapsed: Duration,
+    ) {
+        self.summary.files_partial += 1;
+        self.push_file(input, queries, stats, elapsed, true);
+    }
+
+    /// Adds an input's stats to the totals and the report on each file.
+    fn push_file<'a>(
+        &mut self,
+        input: &Input,
+        queries: impl IntoIterator<Item = &'a str>,
+        stats: &StreamStats,
+        elapsed: Duration,
+        partial: bool,
+    ) {
+        let queries = self.summary.add(queries, stats);
+        self.files.push(FileReport {
+            file: input.to_string(),
+            lines: stats.lines,
+            bytes_read: stats.bytes,
+            matches: stats.found,
+            malformed_lines: stats.malformed,
+            invalid_utf8_lines: stats.invalid_utf8,
+            queries,
+            secs: elapsed.as_secs_f64(),
+            partial,
+        });
+    }
+
+    /// Counts an input which wasn't searched.
+    pub fn add_skipped(&mut self) {
+        self.summary.files_skipped += 1;
+    }
+
+    /// Counts an input which couldn't be searched.
+    pub fn add_failed(&mut self) {
+        self.summary.files_failed += 1;
+    }
+
+    /// The totals, with the throughput worked out over the whole run so far.
+    pub fn summary(&mut self, wall_time: Duration) -> &Summary {
+        let summary = &mut self.summary;
+        let secs = wall_time.as_secs_f64();
+        summary.wall_time_secs = secs;
+        summary.estimated_matches = summary
+            .sample
+            .is_some()
+            .then(|| summary.estimate(summary.matches));
+        if secs > 0.0 {
+            summary.lines_per_sec = summary.lines as f64 / secs;
+            summary.bytes_per_sec = summary.bytes_read as f64 / secs;
+        }
+        summary
+    }
+
+    /// Writes the report, with the throughput worked out over the whole run so far.
+    pub fn write(&mut self, output_dir: &Path, wall_time: Duration) -> Result<()> {
+        self.summary(wall_time);
+        let path = output_dir.join("report.json");
+        let rendered = serde_json::to_string_pretty(self)?;
+        std::fs::write(&path, rendered)
+            .with_context(|| anyhow!("Error writing report {}", path.display()))
+    }
+}
+
+impl Summary {
+    /// Adds the stats of an input to the totals, returning its matches for each query.
+    fn add<'a>(
+        &mut self,
+        queries: impl IntoIterator<Item = &'a str>,
+        stats: &StreamStats,
+    ) -> BTreeMap<String, u64> {
+        let queries: BTreeMap<_, _> = queries
+            .into_iter()
+            .zip(&stats.query_matches)
+            .map(|(query, &count)| (query.to_owned(), count))
+            .collect();
+        for (query, count) in &queries {
+            *self.queries.entry(query.clone()).or_default() += count;
+        }
+
+        self.lines += stats.lines;
+        self.bytes_read += stats.bytes;
+        self.matches += stats.found;
+        self.malformed_lines += stats.malformed;
+        self.invalid_utf8_lines += stats.invalid_utf8;
+        queries
+    }
+
+    /// Scales a count from the sample up to all of the lines.
+    fn estimate(&self, count: u64) -> u64 {
+        self.sample
+            .map_or(count, |fraction| (count as f64 / fraction).round() as u64)
+    }
+
+    pub fn matches(&self) -> u64 {
+        self.matches
+    }
+
+    pub fn files_failed(&self) -> u64 {
+        self.files_failed
+    }
+
+    /// Writes just the totals, without the report on each file.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let rendered = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, rendered)
+            .with_context(|| anyhow!("Error writing summary {}", path.display()))
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Searched {} files ({} skipped, {} failed): {} lines, {:.1} MB decompressed, in {:.1}s",
+            self.files_searched,
+            self.files_skipped,
+            self.files_failed,
+            self.lines,
+            self.bytes_read as f64 / 1_000_000.0,
+            self.wall_time_secs,
+        )?;
+        writeln!(
+            f,
+            "Averaged {:.0} lines/sec, {:.1} MB/sec",
+            self.lines_per_sec,
+            self.bytes_per_sec / 1_000_000.0,
+        )?;
+        if self.files_partial > 0 {
+            writeln!(
+                f,
+                "Stopped part way through {} files, counted up to where they'd got to",
+                self.files_partial
+            )?;
+        }
+        if self.malformed_lines > 0 {
+            writeln!(
+                f,
+                "Skipped {} malformed lines, written to malformed.jsonl",
+                self.malformed_lines
+            )?;
+        }
+        if self.invalid_utf8_lines > 0 {
+            writeln!(
+                f,
+                "Skipped {} matching lines which weren't valid UTF-8",
+                self.invalid_utf8_lines
+            )?;
+        }
+        let Some(fraction) = self.sample else {
+            write!(f, "Found {} matches", self.matches)?;
+            for (query, matches) in &self.queries {
+                write!(f, "\n  {query}: {matches}")?;
+            }
+            return Ok(());
+        };
+        write!(
+            f,
+            "Found {} matches in a {}% sample of the lines, about {} in all",
+            self.matches,
+            fraction * 100.0,
+            self.estimate(self.matches)
+        )?;
+        for (query, matches) in &self.queries {
+            write!(
+                f,
+                "\n  {query}: {matches}, about {}",
+                self.estimate(*matches)
+            )?;
+        }
+        Ok(())
+    }
+}
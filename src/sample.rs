@@ -0,0 +1,102 @@
+use anyhow::{bail, Context, Result};
+use xxhash_rust::xxh3::xxh3_64_with_seed;
+
+/// A random fraction of the lines, for `--sample`. Whether a line is in the sample goes by
+/// a hash of its text, so the same lines are picked however the inputs are split between
+/// threads, and a run can be resumed.
+#[derive(Debug, Clone, Copy)]
+pub struct LineSample {
+    fraction: f64,
+    seed: u64,
+    /// Lines whose hash is at most this are in the sample.
+    threshold: u64,
+}
+
+impl LineSample {
+    pub fn new(fraction: f64, seed: u64) -> Self {
+        Self {
+            fraction,
+            seed,
+            threshold: (fraction * u64::MAX as f64) as u64,
+        }
+    }
+
+    pub fn fraction(&self) -> f64 {
+        self.fraction
+    }
+
+    /// Whether the line, without its line ending, is in the sample.
+    pub fn contains(&self, line: &[u8]) -> bool {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        xxh3_64_with_seed(line, self.seed) <= self.threshold
+    }
+
+    /// Sets the sample apart from other samples, and from searching every line, when checking
+    /// whether a run can carry on from an earlier one.
+    pub fn describe(&self) -> String {
+        format!("sample {} seed {}", self.fraction, self.seed)
+    }
+}
+
+/// Parses a fraction of the lines, between 0 and 1.
+pub fn parse_fraction(value: &str) -> Result<f64> {
+    let fraction: f64 = value
+        .trim()
+        .parse()
+        .with_context(|| format!("expected a fraction such as `0.01`, not `{value}`"))?;
+    if !(fraction > 0.0 && fraction <= 1.0) {
+        bail!("the fraction has to be more than 0, and at most 1");
+    }
+    Ok(fraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines() -> impl Iterator<Item = String> {
+        (0..10_000).map(|i| format!("{{\"id\": \"video{i}\"}}"))
+    }
+
+    #[test]
+    fn picks_about_the_fraction_asked_for() {
+        let sample = LineSample::new(0.1, 0);
+        let picked = lines()
+            .filter(|line| sample.contains(line.as_bytes()))
+            .count();
+        assert!((800..1200).contains(&picked), "{picked} lines picked");
+
+        let everything = LineSample::new(1.0, 0);
+        assert!(lines().all(|line| everything.contains(line.as_bytes())));
+    }
+
+    #[test]
+    fn picks_lines_by_their_text_and_seed() {
+        let sample = LineSample::new(0.5, 7);
+        for line in lines().take(100) {
+            // The line ending doesn't matter, so the last line of a file is treated the same.
+            let ended = format!("{line}\n");
+            assert_eq!(
+                sample.contains(line.as_bytes()),
+                sample.contains(ended.as_bytes())
+            );
+        }
+
+        let other = LineSample::new(0.5, 8);
+        assert!(lines()
+            .take(100)
+            .any(|line| sample.contains(line.as_bytes()) != other.contains(line.as_bytes())));
+    }
+
+    #[test]
+    fn fractions_are_between_0_and_1() {
+        assert_eq!(parse_fraction("0.01").unwrap(), 0.01);
+        assert_eq!(parse_fraction(" 1 ").unwrap(), 1.0);
+        for fraction in ["0", "1.5", "-0.1", "NaN", "half"] {
+            assert!(
+                parse_fraction(fraction).is_err(),
+                "`{fraction}` was accepted"
+            );
+        }
+    }
+}
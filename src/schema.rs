@@ -0,0 +1,148 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::OnceLock,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+static SCHEMA: OnceLock<Schema> = OnceLock::new();
+
+/// How records from different generations of scrapers map onto the fields that queries,
+/// filters and output formats look up, as given by `--schema`:
+///
+/// ```json
+/// {
+///     "aliases": {"view_count": ["viewCount", "statistics.viewCount"]},
+///     "dates": ["upload_date"],
+///     "numbers": ["view_count"]
+/// }
+/// ```
+///
+/// Only the fields looked up by name are adapted; raw records are written as they were
+/// found.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schema {
+    /// For each field, the names other generations of records give it, tried in order when
+    /// a record doesn't have the field itself.
+    #[serde(default)]
+    aliases: HashMap<String, Vec<String>>,
+    /// Fields holding dates, which are given as `YYYYMMDD` whichever of the other formats
+    /// scrapers have used they're written in: ISO 8601 dates (with or without a time), or
+    /// Unix timestamps.
+    #[serde(default)]
+    dates: HashSet<String>,
+    /// Fields holding numbers, which are given as numbers even where they're written as
+    /// strings.
+    #[serde(default)]
+    numbers: HashSet<String>,
+}
+
+/// Reads the schema file, and uses it for every record from here on.
+pub fn load(path: &Path) -> Result<()> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| anyhow!("Error reading schema {}", path.display()))?;
+    let schema: Schema = serde_json::from_str(&text)
+        .with_context(|| anyhow!("Error parsing schema {}", path.display()))?;
+    let _ = SCHEMA.set(schema);
+    Ok(())
+}
+
+/// The schema given by `--schema`, if there is one.
+pub fn get() -> Option<&'static Schema> {
+    SCHEMA.get()
+}
+
+impl Schema {
+    /// The other names the field can be found under.
+    pub fn aliases(&self, field: &str) -> &[String] {
+        self.aliases.get(field).map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether the field's value can be changed from what's written by [`Schema::adapt`].
+    pub fn adapts(&self, field: &str) -> bool {
+        self.dates.contains(field) || self.numbers.contains(field)
+    }
+
+    /// Brings a field's value into the form the schema gives it in.
+    pub fn adapt(&self, field: &str, value: Value) -> Value {
+        if self.dates.contains(field) {
+            if let Some(date) = normalize_date(&value) {
+                return Value::String(date);
+            }
+        }
+        if self.numbers.contains(field) {
+            if let Some(number) = value.as_str().and_then(|s| s.trim().parse().ok()) {
+                return Value::Number(number);
+            }
+        }
+        value
+    }
+}
+
+/// Converts a date to `YYYYMMDD`, if it's in one of the formats we know.
+pub fn normalize_date(value: &Value) -> Option<String> {
+    let timestamp = match value {
+        // Dates written as numbers, rather than timestamps from the first few months of 1970.
+        Value::Number(n)
+            if n.as_u64()
+                .is_some_and(|n| (10_000_000..100_000_000).contains(&n)) =>
+        {
+            return Some(n.to_string());
+        }
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) if s.len() > 8 && s.bytes().all(|b| b.is_ascii_digit()) => s.parse().ok(),
+        Value::String(s) => {
+            // `20130415`, `2013-04-15`, `2013/04/15` and `2013-04-15T10:00:00Z` alike.
+            let date = s.get(..10).unwrap_or(s);
+            let digits: String = date.chars().filter(char::is_ascii_digit).collect();
+            return (digits.len() == 8).then_some(digits);
+        }
+        _ => None,
+    }?;
+    let time = UNIX_EPOCH + Duration::from_secs(timestamp);
+    let formatted = humantime::format_rfc3339_seconds(time).to_string();
+    Some(formatted[..10].replace('-', ""))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn normalizes_dates_in_each_known_format() {
+        for date in [
+            json!(20130415),
+            json!("20130415"),
+            json!("2013-04-15"),
+            json!("2013/04/15"),
+            json!("2013-04-15T10:00:00Z"),
+            // Unix timestamps, as numbers or strings.
+            json!(1366020000),
+            json!("1366020000"),
+        ] {
+            assert_eq!(normalize_date(&date).as_deref(), Some("20130415"), "{date}");
+        }
+    }
+
+    #[test]
+    fn leaves_unknown_dates_alone() {
+        for date in [
+            json!("April 2013"),
+            json!("2013-4-15"),
+            json!(""),
+            json!(null),
+            json!(-5),
+            json!(2013.5),
+            json!(["20130415"]),
+        ] {
+            assert_eq!(normalize_date(&date), None, "{date}");
+        }
+    }
+}
@@ -0,0 +1,1997 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{self, BufRead, BufReader, Read},
+    ops::Range,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use aho_corasick::AhoCorasick;
+use anyhow::{anyhow, Context, Result};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    build_searchers,
+    channels::ChannelList,
+    debug,
+    decode::DecodeOptions,
+    dedup::{record_id_hash, SeenIds},
+    display::{self, FileProgress},
+    error_log::ErrorLog,
+    estimate,
+    frames::{frame_ranges, group_frames, ChunkReader},
+    index::load_index,
+    input::{Input, InputSelector, Shard, OTHER_SHARD},
+    interrupt::{self, interrupted},
+    lines::{count_lines, Lines, ReaderLines, SliceLines},
+    load_queries,
+    logging::{self, Event, LogLevel, STATUS_TO_STDERR},
+    malformed::MalformedLog,
+    management::{
+        lock_management, run_saver, Change, FileStats, Management, Progress, ResumePoint,
+        SaveRequest,
+    },
+    notify::{Notification, Notifier, Outcome},
+    output::{project_fields, Compression, Formatter, OutputFormat, OutputOptions, Provenance},
+    parse_size,
+    pipeline::{self, Block, Chunk},
+    pool::BufferPool,
+    prefilter::Prefilter,
+    progress_file,
+    record::RecordView,
+    remote,
+    report::Report,
+    sample::{self, LineSample},
+    search_line,
+    sink::{QueryFiles, Sink},
+    source::Source,
+    status, thread_pool, tui,
+    writer::{Buffered, MatchMemory, Records, WriterShared},
+    Automaton, Query,
+};
+
+mod checkpoint;
+mod outputs;
+mod reporting;
+
+use checkpoint::{checkpoint, flush_outputs, open_management, peek_management, resume_point};
+use outputs::{
+    close_sinks, open_output_files, open_sinks, open_split_output, write_matches, write_mode,
+};
+use reporting::{list_files, report_outcome};
+
+#[derive(Debug, clap::Args)]
+pub(crate) struct SearchArgs {
+    #[clap(long = "output-dir", env = "YTMS_OUTPUT_DIR", short = 'o')]
+    output_dir: PathBuf,
+    #[clap(long = "query-json", env = "YTMS_QUERY_JSON", short = 'q')]
+    query_json: PathBuf,
+    /// Folder to search for input files, or `-` to read a single stream from stdin.
+    #[clap(
+        long = "input-folder",
+        env = "YTMS_INPUT_FOLDER",
+        short = 'i',
+        alias = "files-folder",
+        required_unless_present = "input-urls"
+    )]
+    files_folder: Option<String>,
+    /// URL of a compressed file to stream and search. Can be repeated.
+    #[clap(long = "input-url", env = "YTMS_INPUT_URL", short = 'u')]
+    input_urls: Vec<String>,
+    /// How much of each `--input-url` to download ahead of the search, e.g. `64M`, so that
+    /// downloading carries on while the searching catches up.
+    #[clap(long = "read-ahead", env = "YTMS_READ_AHEAD", default_value = "64M", value_parser = parse_size)]
+    read_ahead: u64,
+    /// Glob pattern, relative to the input folder, used to find input files. Files split into
+    /// numbered parts (`file.zst.001`, `file.zst.002`, ...) are found by the whole file's name.
+    #[clap(
+        long = "glob",
+        env = "YTMS_GLOB",
+        short = 'g',
+        default_value = "**/*.zst"
+    )]
+    glob: String,
+    /// Glob pattern, relative to the input folder, of files to skip. Can be repeated.
+    #[clap(long = "exclude", env = "YTMS_EXCLUDE", short = 'x')]
+    exclude: Vec<String>,
+    /// The management file, recording which files have been searched so that the search
+    /// can carry on from there. With a `.sqlite`, `.sqlite3` or `.db` extension it's kept
+    /// in SQLite, where each file's row is updated as it changes rather than the whole file
+    /// being rewritten.
+    #[clap(
+        long = "search-management-file",
+        env = "YTMS_SEARCH_MANAGEMENT_FILE",
+        short = 'm'
+    )]
+    management_file: PathBuf,
+    /// Wait for another run using the same management file to finish, instead of exiting.
+    #[clap(long = "wait-for-lock", env = "YTMS_WAIT_FOR_LOCK")]
+    wait_for_lock: bool,
+    /// Write out the management file after this many files have been completed.
+    #[clap(long = "save-every", env = "YTMS_SAVE_EVERY", default_value_t = 1)]
+    save_every: usize,
+    /// The longest time, in seconds, a completed file can go without being recorded in the
+    /// management file.
+    #[clap(
+        long = "save-interval",
+        env = "YTMS_SAVE_INTERVAL",
+        default_value_t = 60
+    )]
+    save_interval: u64,
+    /// Record progress by appending each change to `<management file>.journal`, instead of
+    /// rewriting the whole management file each time it's saved. The journal is folded into
+    /// the management file at the end of the run. Much faster when searching a very large
+    /// number of files. Not needed with a SQLite management file, so it's ignored.
+    #[clap(long = "management-journal", env = "YTMS_MANAGEMENT_JOURNAL")]
+    management_journal: bool,
+    /// Only search files modified after this time. Either a timestamp (e.g. `2022-09-01` or
+    /// `2022-09-01 12:00:00`), or a duration before now (e.g. `3days`, `12h`).
+    #[clap(long = "newer-than", env = "YTMS_NEWER_THAN", value_parser = parse_time)]
+    newer_than: Option<SystemTime>,
+    /// Only search files of at least this size, e.g. `1`, `500K`, `2G`.
+    #[clap(long = "min-size", env = "YTMS_MIN_SIZE", value_parser = parse_size)]
+    min_size: Option<u64>,
+    /// Only search files of at most this size, e.g. `500M`, `100G`.
+    #[clap(long = "max-size", env = "YTMS_MAX_SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+    /// Only search the `K`th of `N` slices of the inputs, e.g. `2/4`. Each input is assigned
+    /// to a slice by a hash of its path within the input folder (or its URL), so that `N`
+    /// machines can each search their own slice, with their own management files, without
+    /// coordinating.
+    #[clap(long = "shard", env = "YTMS_SHARD", value_parser = Shard::parse)]
+    shard: Option<Shard>,
+    /// How matched lines are written to the output files.
+    #[clap(
+        long = "output-format",
+        env = "YTMS_OUTPUT_FORMAT",
+        value_enum,
+        default_value = "raw"
+    )]
+    output_format: OutputFormat,
+    /// Comma-separated list of the fields written by the `csv` and `tsv` output formats,
+    /// e.g. `id,title,uploader`.
+    #[clap(
+        long = "fields",
+        env = "YTMS_FIELDS",
+        use_value_delimiter = true,
+        required_if_eq_any = &[("output-format", "csv"), ("output-format", "tsv")]
+    )]
+    fields: Vec<String>,
+    /// Compress the output files, with either `zstd` or `gzip`, optionally followed by the
+    /// compression level (e.g. `zstd:9`). The matching extension is added to the file names.
+    #[clap(long = "compress-output", env = "YTMS_COMPRESS_OUTPUT", value_parser = Compression::parse)]
+    compress_output: Option<Compression>,
+    /// Start a new numbered output file (`name.0001`, `name.0002`, ...) whenever the current
+    /// one reaches this size, e.g. `2G`.
+    #[clap(long = "max-output-size", env = "YTMS_MAX_OUTPUT_SIZE", value_parser = parse_size)]
+    max_output_size: Option<u64>,
+    /// Write each input's matches to separate files, named
+    /// `<output-dir>/<query>/<input>.<ext>`, instead of one file per query.
+    #[clap(long = "split-output", env = "YTMS_SPLIT_OUTPUT")]
+    split_output: bool,
+    /// Stream the matches to stdout instead of writing output files, each line prefixed
+    /// with the query's filename and a tab. Status messages are written to stderr.
+    #[clap(
+        long = "stdout",
+        env = "YTMS_STDOUT",
+        conflicts_with_all = &["split-output", "compress-output", "max-output-size"]
+    )]
+    stdout: bool,
+    /// The size of each output file's write buffer, e.g. `1M`.
+    #[clap(long = "write-buffer-size", env = "YTMS_WRITE_BUFFER_SIZE", default_value = "8K", value_parser = parse_size)]
+    write_buffer_size: u64,
+    /// How many matches each search thread collects before writing them to the output files.
+    #[clap(
+        long = "flush-every-matches",
+        env = "YTMS_FLUSH_EVERY_MATCHES",
+        alias = "flush-every",
+        default_value_t = 1000
+    )]
+    flush_every_matches: usize,
+    /// Also write out the matches collected so far at least this often, in seconds, so that
+    /// the few matches of a rare query turn up in the output files during a long run.
+    #[clap(long = "flush-every-secs", env = "YTMS_FLUSH_EVERY_SECS")]
+    flush_every_secs: Option<u64>,
+    /// Sync the output files to disk this often, in seconds, so that what's been written
+    /// survives a crash or power loss.
+    #[clap(long = "fsync-every-secs", env = "YTMS_FSYNC_EVERY_SECS")]
+    fsync_every_secs: Option<u64>,
+    /// The most memory the matches waiting to be written can take up between them, e.g.
+    /// `512M`. Past this, the searching threads write out their matches early, and wait
+    /// for the output files to catch up before reading any more.
+    #[clap(long = "max-match-memory", env = "YTMS_MAX_MATCH_MEMORY", default_value = "1G", value_parser = parse_size)]
+    max_match_memory: u64,
+    /// How often, in seconds, to record how far through each file the search has got, so
+    /// that an interrupted run can carry on part way through a file. Matches found after the
+    /// last checkpoint may be written again when resuming. Set to 0 to disable.
+    #[clap(
+        long = "checkpoint-interval",
+        env = "YTMS_CHECKPOINT_INTERVAL",
+        default_value_t = 60
+    )]
+    checkpoint_interval: u64,
+    /// Also send the matches to this Elasticsearch or OpenSearch server using bulk index
+    /// requests, e.g. `http://localhost:9200`. Each query's matches go to an index named
+    /// after its filename.
+    #[clap(long = "elasticsearch-url", env = "YTMS_ELASTICSEARCH_URL")]
+    elasticsearch_url: Option<String>,
+    /// Prefix added to the names of the Elasticsearch indices.
+    #[clap(
+        long = "elasticsearch-index-prefix",
+        env = "YTMS_ELASTICSEARCH_INDEX_PREFIX",
+        default_value = ""
+    )]
+    elasticsearch_index_prefix: String,
+    /// The number of matches sent in each Elasticsearch bulk request.
+    #[clap(
+        long = "elasticsearch-batch-size",
+        env = "YTMS_ELASTICSEARCH_BATCH_SIZE",
+        default_value_t = 1000
+    )]
+    elasticsearch_batch_size: usize,
+    /// How many times to retry a failed Elasticsearch request before giving up on the file.
+    #[clap(
+        long = "elasticsearch-retries",
+        env = "YTMS_ELASTICSEARCH_RETRIES",
+        default_value_t = 5
+    )]
+    elasticsearch_retries: u32,
+    /// Also total up each query's matches by channel as the search goes, writing the number
+    /// of records, total views and first and last upload dates of each channel to
+    /// `<output-dir>/<query filename>.channels.csv`. The totals are taken from the records
+    /// after the query's `output_fields` are applied, so these need to keep `channel_id`,
+    /// `view_count` and `upload_date`.
+    #[clap(long = "aggregate", env = "YTMS_AGGREGATE")]
+    aggregate: bool,
+    /// Append to existing output files. This is the default when resuming from a management
+    /// file.
+    #[clap(long = "append", env = "YTMS_APPEND", conflicts_with = "overwrite")]
+    append: bool,
+    /// Truncate existing output files, and ignore the progress recorded in the management
+    /// file so that everything is searched again.
+    #[clap(long = "overwrite", env = "YTMS_OVERWRITE")]
+    overwrite: bool,
+    /// Refuse to start a new run if any of the output files already has something in it,
+    /// rather than overwriting it.
+    #[clap(
+        long = "create-new",
+        env = "YTMS_CREATE_NEW",
+        conflicts_with_all = &["append", "overwrite"]
+    )]
+    create_new: bool,
+    /// Carry on from the management file even though the queries have changed since it was
+    /// written.
+    #[clap(
+        long = "force-resume",
+        env = "YTMS_FORCE_RESUME",
+        conflicts_with = "restart"
+    )]
+    force_resume: bool,
+    /// Start over, as with `--overwrite`, if the queries have changed since the management
+    /// file was written.
+    #[clap(long = "restart", env = "YTMS_RESTART")]
+    restart: bool,
+    /// Only search the files which failed in earlier runs, as recorded in the management
+    /// file.
+    #[clap(long = "retry-failed", env = "YTMS_RETRY_FAILED", conflicts_with_all = &["overwrite", "restart"])]
+    retry_failed: bool,
+    /// Skip files whose contents match a file that has already been searched, based on a
+    /// fingerprint of their size and first and last blocks.
+    #[clap(long = "dedup-inputs", env = "YTMS_DEDUP_INPUTS")]
+    dedup_inputs: bool,
+    /// Identify completed files by the fingerprint of their contents, as well as their path,
+    /// so that files which have changed since they were searched are searched again. Matches
+    /// from the earlier search of a changed file are left in the output. Implies
+    /// `--dedup-inputs`, so renamed copies of completed files are skipped.
+    #[clap(long = "match-by-content", env = "YTMS_MATCH_BY_CONTENT")]
+    match_by_content: bool,
+    /// Write the lines which match none of each query's expressions, instead of those which
+    /// match. Queries can also set `"invert": true` individually.
+    #[clap(long = "invert", env = "YTMS_INVERT")]
+    invert: bool,
+    /// Only consider the records from the channels in this file, for the queries which don't
+    /// give their own `channel_list`: a text file with a channel ID on each line, or a CSV
+    /// file with a `channel_id` column.
+    #[clap(long = "channel-list", env = "YTMS_CHANNEL_LIST")]
+    channel_list: Option<PathBuf>,
+    /// Keep running after the initial search, and search new files as they appear in the
+    /// input folder. The output files keep their `.partial` names while watching, until it's
+    /// stopped with Ctrl-C.
+    #[clap(long = "watch", env = "YTMS_WATCH", requires = "files-folder")]
+    watch: bool,
+    /// How often, in seconds, to check the input folder for new files in `--watch` mode.
+    #[clap(
+        long = "watch-interval",
+        env = "YTMS_WATCH_INTERVAL",
+        default_value_t = 30
+    )]
+    watch_interval: u64,
+    /// Skip over corrupt zstd frames instead of abandoning the rest of the file. Each frame is
+    /// held in memory until its checksum has been checked, so that nothing from a corrupt
+    /// frame is searched.
+    #[clap(long = "skip-corrupt-frames", env = "YTMS_SKIP_CORRUPT_FRAMES")]
+    skip_corrupt_frames: bool,
+    /// Dictionary to use when decompressing the inputs.
+    #[clap(long = "zstd-dict", env = "YTMS_ZSTD_DICT")]
+    zstd_dict: Option<PathBuf>,
+    /// The largest window the zstd decoder will accept, as a power of two. Files compressed
+    /// with `--long` need this raising to match, up to 31, which lets each input being
+    /// decoded use as much memory as the window size (2 GiB at 31). [default: 27]
+    #[clap(long = "zstd-window-log", env = "YTMS_ZSTD_WINDOW_LOG", value_parser = clap::value_parser!(u32).range(10..=31))]
+    zstd_window_log: Option<u32>,
+    /// Split multi-frame (e.g. seekable) zstd files into chunks which are searched in parallel.
+    #[clap(long = "split-frames", env = "YTMS_SPLIT_FRAMES")]
+    split_frames: bool,
+    /// The minimum compressed size of each chunk when using `--split-frames`.
+    #[clap(long = "frame-chunk-size", env = "YTMS_FRAME_CHUNK_SIZE", default_value = "256M", value_parser = parse_size)]
+    frame_chunk_size: u64,
+    /// Search the inputs as a pipeline: each input being read (see `--io-threads`) is
+    /// decompressed and split into chunks of whole lines on their own threads, which are
+    /// queued up for the searching threads. Decompressing and searching then overlap, and a
+    /// single large input can keep all of the threads busy. See also `--format-threads` and
+    /// `--pipeline-depth`.
+    #[clap(long = "parallel-chunks", env = "YTMS_PARALLEL_CHUNKS")]
+    parallel_chunks: bool,
+    /// The decompressed size of each chunk when using `--parallel-chunks`.
+    #[clap(long = "parallel-chunk-size", env = "YTMS_PARALLEL_CHUNK_SIZE", default_value = "16M", value_parser = parse_size)]
+    parallel_chunk_size: u64,
+    /// How the queries' expressions are matched. `bench` can be used to compare them.
+    #[clap(
+        long = "automaton",
+        env = "YTMS_AUTOMATON",
+        value_enum,
+        default_value = "nfa"
+    )]
+    automaton: Automaton,
+    /// How many threads to search with. Defaults to one for each CPU core.
+    #[clap(long = "threads", env = "YTMS_THREADS", short = 'j')]
+    threads: Option<usize>,
+    /// How many inputs to read at once. Defaults to one for each searching thread. Setting
+    /// it lower avoids swamping a slow disk or network share, while the searching threads
+    /// can still share the work of large inputs with `--parallel-chunks` or `--split-frames`.
+    #[clap(long = "io-threads", env = "YTMS_IO_THREADS")]
+    io_threads: Option<usize>,
+    /// How many threads render the matches found with `--parallel-chunks` as records, apart
+    /// from the searching threads. By default each chunk's matches are rendered by the
+    /// thread which searched it, which is usually best unless the output format or
+    /// `output_fields` make rendering the matches the slow part.
+    #[clap(long = "format-threads", env = "YTMS_FORMAT_THREADS")]
+    format_threads: Option<usize>,
+    /// How many chunks can be queued up between each of the stages with `--parallel-chunks`,
+    /// waiting for the next stage to get to them. Defaults to one for each searching thread.
+    #[clap(long = "pipeline-depth", env = "YTMS_PIPELINE_DEPTH")]
+    pipeline_depth: Option<usize>,
+    /// Memory-map uncompressed input files and search them in place, rather than reading
+    /// them through a buffer. Compressed files are read as usual. The files mustn't be
+    /// changed while they're being searched.
+    #[clap(long = "mmap", env = "YTMS_MMAP")]
+    mmap: bool,
+    /// Skip the input files whose indexes in this folder, built by the `index` subcommand,
+    /// show that they can't contain any of the expressions. Files without an up to date
+    /// index are searched as usual.
+    #[clap(
+        long = "index-folder",
+        env = "YTMS_INDEX_FOLDER",
+        requires = "files-folder"
+    )]
+    index_folder: Option<PathBuf>,
+    /// Check that every line is a JSON object with the `--required-fields`, skipping those
+    /// which aren't rather than searching them, and writing them to `malformed.jsonl` in the
+    /// output folder with the file and line they came from. Every line has to be parsed, so
+    /// this is a lot slower, and files aren't skipped using their index.
+    #[clap(long = "check-records", env = "YTMS_CHECK_RECORDS")]
+    check_records: bool,
+    /// Only search this fraction of the lines, picked at random, e.g. `0.01`, and estimate
+    /// from what's found how many matches searching every line would find. Every line is
+    /// still read, but it's quicker to get through them. A sampled run can only be resumed
+    /// with the same sample.
+    #[clap(long = "sample", env = "YTMS_SAMPLE", value_parser = sample::parse_fraction)]
+    sample: Option<f64>,
+    /// The seed picking the lines for `--sample`. Different seeds pick different lines.
+    #[clap(
+        long = "sample-seed",
+        env = "YTMS_SAMPLE_SEED",
+        default_value_t = 0,
+        requires = "sample"
+    )]
+    sample_seed: u64,
+    /// Comma-separated list of the fields every record has to have for `--check-records`.
+    #[clap(
+        long = "required-fields",
+        env = "YTMS_REQUIRED_FIELDS",
+        use_value_delimiter = true,
+        default_value = "id"
+    )]
+    required_fields: Vec<String>,
+    /// Print a line as each file is started and finished, instead of showing progress bars.
+    /// The bars are only shown when stderr is a terminal.
+    #[clap(long = "no-progress", env = "YTMS_NO_PROGRESS")]
+    no_progress: bool,
+    /// Also write the totals printed at the end of the run to this file, as JSON. They're
+    /// in `report.json` in the output folder too, alongside the figures for each file.
+    #[clap(long = "summary-file", env = "YTMS_SUMMARY_FILE")]
+    summary_file: Option<PathBuf>,
+    /// Read options from this TOML file, keyed by their long names, as in
+    /// `output-dir = "out"`. Lists give options which can be repeated, and `true` gives a
+    /// flag. Options on the command line, or in `YTMS_` environment variables, take
+    /// precedence over the file.
+    #[clap(long = "config", env = "YTMS_CONFIG", value_parser)]
+    config: Option<PathBuf>,
+    /// List each input file found, with its size and whether it will be searched, skipped as
+    /// already completed, retried after failing, or was excluded, then exit without
+    /// searching.
+    #[clap(long = "list-files", env = "YTMS_LIST_FILES")]
+    list_files: bool,
+    /// Search the start of a few of the input files, and estimate from them how long
+    /// searching the rest would take and how big the outputs would be, then exit.
+    #[clap(
+        long = "estimate",
+        env = "YTMS_ESTIMATE",
+        conflicts_with = "list-files"
+    )]
+    estimate: bool,
+    /// How many input files to sample for `--estimate`, spread from the smallest to the
+    /// largest.
+    #[clap(
+        long = "estimate-samples",
+        env = "YTMS_ESTIMATE_SAMPLES",
+        default_value_t = 3
+    )]
+    estimate_samples: usize,
+    /// How much of each sample to decompress and search for `--estimate`.
+    #[clap(
+        long = "estimate-sample-size",
+        env = "YTMS_ESTIMATE_SAMPLE_SIZE",
+        default_value = "256M",
+        value_parser = parse_size
+    )]
+    estimate_sample_size: u64,
+    /// Show a full screen dashboard of the search on stderr instead of the progress bars,
+    /// with the files being searched, the matches for each query, the throughput and the
+    /// latest matches. The search can be paused from it, and files skipped, to carry on
+    /// from where they got to next time. Files whose progress can't be recorded, like those
+    /// read from stdin, are searched to the end regardless.
+    #[clap(long = "tui", env = "YTMS_TUI", conflicts_with = "no-progress")]
+    tui: bool,
+    /// Write how far the run has got to this file as JSON every `--progress-interval`
+    /// seconds, for monitors to follow: the files and bytes done, the current throughput,
+    /// the matches for each query, and the files being searched. It's written one last time
+    /// at the end, marked as finished.
+    #[clap(long = "progress-file", env = "YTMS_PROGRESS_FILE")]
+    progress_file: Option<PathBuf>,
+    #[clap(
+        long = "progress-interval",
+        env = "YTMS_PROGRESS_INTERVAL",
+        default_value_t = 10
+    )]
+    progress_interval: u64,
+    /// Exit as grep does: with 0 if this run found any matches, 1 if it found none, and 2
+    /// if there was an error, including any input failing to be searched.
+    #[clap(long = "grep-exit-codes", env = "YTMS_GREP_EXIT_CODES")]
+    grep_exit_codes: bool,
+    /// Stop after this long, e.g. `6h`, as if interrupted: no more files are started, the
+    /// ones being searched have their progress recorded, and the outputs and management file
+    /// are saved, so the search can carry on from there next time. Exits with 75 when the
+    /// limit is reached.
+    #[clap(long = "max-runtime", env = "YTMS_MAX_RUNTIME", value_parser = humantime::parse_duration)]
+    max_runtime: Option<Duration>,
+    /// When the run ends, whether it finished, was stopped or failed, POST a JSON
+    /// notification to this URL, with the outcome and the run's totals.
+    #[clap(long = "notify-url", env = "YTMS_NOTIFY_URL")]
+    notify_url: Option<String>,
+    /// When the run ends, run this shell command with the JSON notification on its stdin,
+    /// and the outcome in `YTMS_OUTCOME`.
+    #[clap(long = "notify-cmd", env = "YTMS_NOTIFY_CMD")]
+    notify_cmd: Option<String>,
+    /// Stop the whole run at the first input which can't be searched, rather than carrying
+    /// on with the rest. The files being searched have their progress recorded, as with
+    /// Ctrl-C, and it exits with an error.
+    #[clap(long = "fail-fast", env = "YTMS_FAIL_FAST")]
+    fail_fast: bool,
+}
+
+/// The exit code for an error with `--grep-exit-codes`.
+const GREP_ERROR: i32 = 2;
+/// The exit code for stopping at `--max-runtime`, EX_TEMPFAIL from `sysexits.h`, as the
+/// search can be carried on later.
+const TIMED_OUT: i32 = 75;
+
+fn parse_time(value: &str) -> Result<SystemTime> {
+    if let Ok(duration) = humantime::parse_duration(value) {
+        return SystemTime::now()
+            .checked_sub(duration)
+            .ok_or_else(|| anyhow!("duration too long"));
+    }
+
+    humantime::parse_rfc3339_weak(value)
+        .or_else(|_| humantime::parse_rfc3339_weak(&format!("{value} 00:00:00")))
+        .with_context(|| anyhow!("expected a timestamp or duration"))
+}
+
+impl Query {
+    /// The header line for this query's output files, which templated output doesn't have.
+    fn header(&self, formatter: &Formatter) -> Option<String> {
+        match self.template {
+            Some(_) => None,
+            None => formatter.header(),
+        }
+    }
+
+    /// Whether only the first record seen with each `id` is written: when the query asks
+    /// for it, or when all that's written of each record is its ID.
+    fn deduplicated(&self, formatter: &Formatter) -> bool {
+        self.dedup || (self.template.is_none() && formatter.writes_ids())
+    }
+}
+
+/// Everything shared between the threads searching files.
+struct SearchContext {
+    /// The management state from when the run started, used to skip completed files.
+    management: Management,
+    decode_options: DecodeOptions,
+    dedup_inputs: bool,
+    /// Search completed files again if their fingerprint has changed.
+    match_by_content: bool,
+    /// How many matches to collect before writing them out.
+    flush_every: usize,
+    /// Also write out the matches at least this often.
+    flush_interval: Option<Duration>,
+    /// The size of the chunks to split inputs into with `--parallel-chunks`.
+    parallel_chunk_size: Option<u64>,
+    /// Memory-map uncompressed input files.
+    mmap: bool,
+    /// Where the input files' indexes are kept, if they're to be used.
+    index_folder: Option<PathBuf>,
+    /// How often to record how far through each file we've got.
+    checkpoint_interval: Option<Duration>,
+    formatter: Formatter,
+    output_dir: PathBuf,
+    output_options: OutputOptions,
+    /// Write each input's matches to separate files, instead of the shared `files`.
+    split_output: bool,
+    /// The folder the inputs were found in, used to name the per-input output files.
+    input_root: Option<PathBuf>,
+    /// The canonicalized input folder, which the paths in the management file are relative to.
+    management_root: Option<PathBuf>,
+    queries: Vec<Query>,
+    searchers: Vec<AhoCorasick>,
+    prefilter: Prefilter,
+    /// The threads used for searching.
+    pool: rayon::ThreadPool,
+    /// The threads used for rendering the matches with `--parallel-chunks`, if they're not
+    /// rendered by the threads which found them.
+    format_pool: Option<rayon::ThreadPool>,
+    /// How many chunks can be queued up between each of the stages with `--parallel-chunks`.
+    pipeline_depth: usize,
+    /// Shared with the output files' writers.
+    writers: Arc<WriterShared>,
+    /// Buffers for the chunks read with `--parallel-chunks`, kept once they're searched.
+    chunk_buffers: BufferPool<Vec<u8>>,
+    /// Where the matches go: the output files, unless they're split per input, and anything
+    /// else they're sent to.
+    sinks: Vec<Box<dyn Sink>>,
+    /// Whether any of the sinks want the matched lines, as well as the rendered records.
+    keep_lines: bool,
+    /// The IDs written so far for each query with `dedup` enabled.
+    seen_ids: Vec<Option<Mutex<SeenIds>>>,
+    progress: Arc<Mutex<Progress>>,
+    /// Where the inputs which couldn't be searched are recorded.
+    errors: ErrorLog,
+    /// Where the lines which aren't records are recorded, with `--check-records`.
+    malformed: Option<MalformedLog>,
+    /// The lines to search, with `--sample`.
+    sample: Option<LineSample>,
+    /// Whether to stop everything when an input fails.
+    fail_fast: bool,
+    /// Set when an input is left unfinished because the run was stopped, in which case the
+    /// outputs are left to be carried on with rather than finished.
+    stopped_early: AtomicBool,
+    save_requests: Sender<SaveRequest>,
+    report: Mutex<Report>,
+}
+
+/// A query's rendered matches waiting to be written out.
+#[derive(Default)]
+struct QueryMatches {
+    records: Records,
+    /// Hash of each record's ID, if the query is deduplicating.
+    id_hashes: Vec<Option<u64>>,
+    /// The lines the records were rendered from, if a sink wants them.
+    lines: Records,
+    /// Where the fields kept by `output_fields` are written, reused for each match.
+    projected: Vec<u8>,
+}
+
+impl QueryMatches {
+    /// Roughly how much memory the matches take up.
+    fn size(&self) -> u64 {
+        let id_hashes = self.id_hashes.len() * std::mem::size_of::<Option<u64>>();
+        self.records.size() + self.lines.size() + id_hashes as u64
+    }
+}
+
+/// How much memory the matches in each of the queries' buffers take up.
+fn matches_size(matches: &[QueryMatches]) -> u64 {
+    matches.iter().map(QueryMatches::size).sum()
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StreamStats {
+    pub(crate) lines: u64,
+    pub(crate) found: u64,
+    /// Decompressed bytes read.
+    pub(crate) bytes: u64,
+    /// The number of matches for each query.
+    pub(crate) query_matches: Vec<u64>,
+    /// Lines skipped by `--check-records`.
+    pub(crate) malformed: u64,
+    /// Matching lines skipped as they weren't valid UTF-8.
+    pub(crate) invalid_utf8: u64,
+    /// Time spent handing the matches to the output files, and waiting for them to catch up.
+    writing: Duration,
+}
+
+impl std::ops::Add for StreamStats {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        let mut query_matches = self.query_matches;
+        query_matches.resize(rhs.query_matches.len().max(query_matches.len()), 0);
+        for (total, count) in query_matches.iter_mut().zip(rhs.query_matches) {
+            *total += count;
+        }
+
+        Self {
+            lines: self.lines + rhs.lines,
+            found: self.found + rhs.found,
+            bytes: self.bytes + rhs.bytes,
+            query_matches,
+            malformed: self.malformed + rhs.malformed,
+            invalid_utf8: self.invalid_utf8 + rhs.invalid_utf8,
+            writing: self.writing + rhs.writing,
+        }
+    }
+}
+
+fn search_file(ctx: &SearchContext, input: &Input) {
+    // Don't start on any more files once we've been told to stop.
+    if interrupted() {
+        ctx.stopped_early.store(true, Ordering::Relaxed);
+        return;
+    }
+
+    let file_path = input.management_path(ctx.management_root.as_deref());
+    let completed = file_path
+        .as_ref()
+        .is_some_and(|path| ctx.management.c_files.contains(path));
+    if completed && !ctx.match_by_content {
+        status!("Skipping file {input} (completed)");
+        skip_input(ctx, input);
+        return;
+    }
+
+    let fingerprinting = Instant::now();
+    let fingerprint = match ctx.dedup_inputs.then(|| input.fingerprint()) {
+        None | Some(Ok(None)) => None,
+        Some(Ok(Some(fingerprint))) => Some(fingerprint),
+        Some(Err(e)) => {
+            display::skip(input);
+            record_failure(
+                ctx,
+                input,
+                file_path,
+                format!("Error fingerprinting {input}: {e}"),
+                None,
+            );
+            return;
+        }
+    };
+
+    let fingerprinted = fingerprinting.elapsed();
+
+    if completed {
+        // Files completed without recording a fingerprint can't be told apart from changed
+        // ones, so are assumed to be the same.
+        let recorded = file_path
+            .as_ref()
+            .and_then(|path| ctx.management.c_stats.get(path))
+            .and_then(|stats| stats.fingerprint.as_ref());
+        if fingerprint.is_none() || recorded.is_none() || recorded == fingerprint.as_ref() {
+            status!("Skipping file {input} (completed)");
+            skip_input(ctx, input);
+            return;
+        }
+        status!("{input} has changed since it was searched");
+    }
+    if let Some(fingerprint) = &fingerprint {
+        if !claim_fingerprint(ctx, input, fingerprint) {
+            skip_input(ctx, input);
+            return;
+        }
+    }
+
+    let searching = Instant::now();
+    let stats = search_input(ctx, input);
+
+    let mut lock = ctx.progress.lock().unwrap();
+    if let Some(fingerprint) = &fingerprint {
+        lock.in_progress_hashes.remove(fingerprint);
+    }
+
+    let (stats, elapsed) = match stats {
+        Ok(stats) => stats,
+        Err(SearchError::Interrupted(stats)) => {
+            ctx.stopped_early.store(true, Ordering::Relaxed);
+            ctx.report.lock().unwrap().add_partial(
+                input,
+                ctx.queries.iter().map(|q| q.filename.as_str()),
+                &stats,
+                searching.elapsed(),
+            );
+            status!("Stopped searching {input}, it will carry on from here next time");
+            return;
+        }
+        Err(SearchError::Failed(error, position)) => {
+            drop(lock);
+            // Return here, so that it doesn't get marked as complete.
+            record_failure(ctx, input, file_path, error, position);
+            return;
+        }
+    };
+    let StreamStats {
+        lines: line_count,
+        found: found_count,
+        ..
+    } = stats;
+
+    ctx.report.lock().unwrap().add_file(
+        input,
+        ctx.queries.iter().map(|q| q.filename.as_str()),
+        &stats,
+        elapsed,
+    );
+
+    // We've now finished searching this file, update the management.
+    let file_stats = FileStats {
+        lines: stats.lines,
+        size: input.size(),
+        secs: elapsed.as_secs_f64(),
+        fingerprint: fingerprint.clone(),
+        matches: ctx
+            .queries
+            .iter()
+            .zip(&stats.query_matches)
+            .map(|(query, &count)| (query.filename.clone(), count))
+            .collect(),
+    };
+    lock.record(Change::Completed {
+        stats: file_path.is_some().then_some(file_stats),
+        file: file_path,
+        fingerprint,
+        lines: line_count,
+    });
+
+    logging::log(Event::FileFinished {
+        file: &input.to_string(),
+        lines: line_count,
+        bytes: stats.bytes,
+        found: found_count,
+        query_matches: ctx
+            .queries
+            .iter()
+            .map(|query| query.filename.as_str())
+            .zip(stats.query_matches.iter().copied())
+            .collect(),
+        elapsed,
+        writing: stats.writing,
+    });
+    if stats.invalid_utf8 > 0 {
+        status!(
+            "Skipped {} matching lines of {input} which weren't valid UTF-8",
+            stats.invalid_utf8
+        );
+    }
+    let secs = elapsed.as_secs_f64();
+    let per_query: Vec<_> = ctx
+        .queries
+        .iter()
+        .zip(&stats.query_matches)
+        .map(|(query, count)| format!("{} {count}", query.filename))
+        .collect();
+    let fingerprinting = if ctx.dedup_inputs {
+        format!("fingerprinting took {fingerprinted:?}, ")
+    } else {
+        String::new()
+    };
+    debug!(
+        "{input}: {fingerprinting}searching took {elapsed:?} ({:.0} lines/sec, {:.1} MB/sec), \
+        {:?} of which was spent writing out matches. Matches per query: {}",
+        stats.lines as f64 / secs,
+        stats.bytes as f64 / secs / 1_000_000.0,
+        stats.writing,
+        per_query.join(", "),
+    );
+
+    // Leave writing out the management to the saver thread.
+    let _ = ctx.save_requests.send(SaveRequest::Completed);
+}
+
+/// Checks the input's index, if it has one, returning the stats of searching it if the
+/// index shows that none of its lines can match. Inputs whose index can't be read are
+/// searched as usual.
+fn ruled_out_by_index(ctx: &SearchContext, input: &Input) -> Option<StreamStats> {
+    let folder = ctx
+        .index_folder
+        .as_deref()
+        .filter(|_| ctx.malformed.is_none())?;
+    let (header, index) = match load_index(folder, input, ctx.management_root.as_deref()?) {
+        Ok(Some(index)) => index,
+        Ok(None) => {
+            debug!("{input} has no up to date index");
+            return None;
+        }
+        Err(e) => {
+            status!("{e:#}, searching {input} in full");
+            return None;
+        }
+    };
+    if index.may_match(&ctx.queries) {
+        debug!("The index of {input} shows it might match");
+        return None;
+    }
+    Some(StreamStats {
+        lines: header.lines,
+        found: 0,
+        bytes: header.bytes,
+        query_matches: vec![0; ctx.queries.len()],
+        malformed: 0,
+        invalid_utf8: 0,
+        writing: Duration::ZERO,
+    })
+}
+
+/// Reports that an input couldn't be searched, and records it so that it can be retried
+/// with `--retry-failed`.
+fn record_failure(
+    ctx: &SearchContext,
+    input: &Input,
+    file_path: Option<PathBuf>,
+    error: String,
+    position: Option<ResumePoint>,
+) {
+    let name = input.to_string();
+    logging::log(Event::FileFailed {
+        file: &name,
+        error: &error,
+    });
+    ctx.errors.record(&name, position, &error);
+    if ctx.fail_fast {
+        interrupt::stop_on_failure();
+    }
+    ctx.report.lock().unwrap().add_failed();
+    let Some(file_path) = file_path else {
+        return;
+    };
+    let change = Change::Failed {
+        file: file_path,
+        error,
+    };
+    ctx.progress.lock().unwrap().record(change);
+    let _ = ctx.save_requests.send(SaveRequest::Now);
+}
+
+/// Counts an input which isn't going to be searched.
+fn skip_input(ctx: &SearchContext, input: &Input) {
+    display::skip(input);
+    ctx.report.lock().unwrap().add_skipped();
+}
+
+/// Marks the fingerprint as in progress, unless a file with the same contents has already
+/// been searched or is being searched. Returns `false` if the input is a duplicate.
+fn claim_fingerprint(ctx: &SearchContext, input: &Input, fingerprint: &str) -> bool {
+    let mut lock = ctx.progress.lock().unwrap();
+    if lock.management.c_hashes.contains(fingerprint) {
+        status!("Skipping file {input} (duplicate of a completed file)");
+        return false;
+    }
+    if !lock.in_progress_hashes.insert(fingerprint.to_owned()) {
+        status!("Skipping file {input} (duplicate of a file being searched)");
+        return false;
+    }
+
+    true
+}
+
+/// Why the search of an input didn't complete.
+enum SearchError {
+    /// The input couldn't be searched, for the given reason. Where it's known, how far
+    /// through the input the search had got.
+    Failed(String, Option<ResumePoint>),
+    /// The search was stopped part way through by Ctrl-C, with its progress recorded. The
+    /// stats cover the search up to that point.
+    Interrupted(StreamStats),
+}
+
+impl From<String> for SearchError {
+    fn from(error: String) -> Self {
+        SearchError::Failed(error, None)
+    }
+}
+
+/// Searches an input, returning a description of the problem if it couldn't be searched.
+fn search_input(
+    ctx: &SearchContext,
+    input: &Input,
+) -> Result<(StreamStats, Duration), SearchError> {
+    let now = Instant::now();
+    if let Some(stats) = ruled_out_by_index(ctx, input) {
+        status!("Skipping file {input} (its index shows it can't match)");
+        display::skip(input);
+        return Ok((stats, now.elapsed()));
+    }
+    // The file's bar stands in for the message when the progress bars are shown.
+    let progress = display::get().map(|display| display.start_file(input));
+    logging::log(Event::FileStarted {
+        file: &input.to_string(),
+    });
+
+    let split_files = if ctx.split_output {
+        match open_split_output(ctx, input) {
+            Ok(files) => Some(QueryFiles::new(files, ctx.writers.clone(), true)),
+            Err(e) => return Err(format!("{e:#}").into()),
+        }
+    } else {
+        None
+    };
+    let sinks: Vec<&dyn Sink> = ctx
+        .sinks
+        .iter()
+        .map(|sink| sink.as_ref())
+        .chain(split_files.as_ref().map(|files| files as &dyn Sink))
+        .collect();
+    let sinks = sinks.as_slice();
+
+    let chunks = match input {
+        Input::File(path) if ctx.decode_options.split_frames => match frame_ranges(path) {
+            Ok(frames) => group_frames(&frames, ctx.decode_options.frame_chunk_size),
+            Err(e) => return Err(format!("Error reading frames of {input}: {e}").into()),
+        },
+        _ => Vec::new(),
+    };
+
+    let stats = if chunks.len() > 1 {
+        let Input::File(path) = input else {
+            unreachable!()
+        };
+        ctx.pool.install(|| {
+            chunks
+                .into_par_iter()
+                .map(|chunk| {
+                    let reader = match ChunkReader::new(path, chunk, &ctx.decode_options) {
+                        Ok(r) => BufReader::new(r),
+                        Err(e) => return Err(format!("Error opening {input}: {e}").into()),
+                    };
+                    search_stream(ctx, ReaderLines::new(reader), input, sinks, None)
+                })
+                .try_reduce(StreamStats::default, |a, b| Ok(a + b))
+        })
+    } else {
+        search_whole(ctx, input, sinks, progress.as_ref())
+    };
+
+    if let (Some(files), Ok(_)) = (split_files, &stats) {
+        Box::new(files).finalize()?;
+    }
+
+    stats.map(|stats| (stats, now.elapsed()))
+}
+
+/// Whether the line is one of those searched with `--sample`.
+fn sampled(ctx: &SearchContext, line: &[u8]) -> bool {
+    ctx.sample.is_none_or(|sample| sample.contains(line))
+}
+
+/// Checks the line is a record with `--check-records`, returning whether it should be
+/// searched.
+fn check_line(ctx: &SearchContext, source: &str, line_number: Option<u64>, line: &[u8]) -> bool {
+    ctx.malformed
+        .as_ref()
+        .is_none_or(|log| log.check(source, line_number, line))
+}
+
+/// The match stage: checks a line against every query, setting `does_match` for each of
+/// them. Returns whether the line is a match for any of them, once they're inverted.
+fn match_line(ctx: &SearchContext, line_buf: &[u8], does_match: &mut [bool]) -> bool {
+    does_match.fill(false);
+    search_line(line_buf, &ctx.searchers, does_match);
+    does_match
+        .iter()
+        .zip(&ctx.queries)
+        .any(|(m, q)| q.may_match(*m))
+}
+
+/// The format stage: adds the rendered line to `matches` for each query it matched, going
+/// by `does_match` from [`match_line`]. Returns the number of queries it matched, and the
+/// number of matches added, or `None` if the line was skipped as it isn't valid UTF-8.
+fn format_line(
+    ctx: &SearchContext,
+    line_buf: &[u8],
+    source: &str,
+    line_number: Option<u64>,
+    does_match: &[bool],
+    matches: &mut [QueryMatches],
+    query_matches: &mut [u64],
+) -> Option<(u64, usize)> {
+    // Most lines don't match anything, so they're only checked to be valid UTF-8 once
+    // they're needed as text. A stray invalid byte shouldn't cost the rest of the file.
+    // Each is only mentioned with `--verbose`, as they're counted for the file as a whole.
+    let Ok(line_buf) = std::str::from_utf8(line_buf) else {
+        match line_number {
+            Some(n) => debug!("Skipping line {n} of {source}: not valid UTF-8"),
+            None => debug!("Skipping a line of {source}: not valid UTF-8"),
+        }
+        return None;
+    };
+
+    // Parsed once for all of the queries, when one of them first needs to.
+    let record = RecordView::new(line_buf);
+    let mut found = 0;
+    let mut rendered = 0;
+    let query_results = does_match.iter().zip(matches).zip(query_matches);
+    let queries = ctx.queries.iter().zip(&ctx.searchers);
+    for (((does_match, match_list), query_count), (query, searcher)) in query_results.zip(queries) {
+        if query.matches(*does_match, &record, searcher) {
+            let provenance = Provenance {
+                query: &query.filename,
+                source,
+                line: line_number,
+            };
+            found += 1;
+            *query_count += 1;
+            let projected = &mut match_list.projected;
+            let projection;
+            let view = if !query.output_fields.is_empty()
+                && project_fields(&record, &query.output_fields, projected)
+            {
+                let text = std::str::from_utf8(projected).expect("projected from valid UTF-8");
+                projection = RecordView::new(text);
+                &projection
+            } else {
+                &record
+            };
+            let written = match_list.records.push_with(|out| match &query.template {
+                Some(template) => template.render(view, out),
+                None => ctx.formatter.format(&provenance, view, out),
+            });
+            if written {
+                let id_hash = query
+                    .deduplicated(&ctx.formatter)
+                    .then(|| record_id_hash(&record))
+                    .flatten();
+                match_list.id_hashes.push(id_hash);
+                if ctx.keep_lines {
+                    match_list.lines.push(view.line().as_bytes());
+                }
+                rendered += 1;
+            }
+        }
+    }
+
+    Some((found, rendered))
+}
+
+/// Searches every line, writing out the matches as it goes.
+///
+/// `start` is where the stream starts in the input, or `None` if the stream is only a part
+/// of it, in which case the line numbers are meaningless.
+fn search_stream(
+    ctx: &SearchContext,
+    mut lines: impl Lines,
+    input: &Input,
+    sinks: &[&dyn Sink],
+    start: Option<ResumePoint>,
+) -> Result<StreamStats, SearchError> {
+    let queries = &ctx.queries;
+    let source = input.to_string();
+    // Progress can only be recorded when we're searching the input from start to end, and
+    // the matches are going to the shared output files.
+    let checkpoint_path = input
+        .management_path(ctx.management_root.as_deref())
+        .filter(|_| start.is_some() && !ctx.split_output && ctx.checkpoint_interval.is_some());
+    let mut last_checkpoint = Instant::now();
+    let start_bytes = start.map_or(0, |s| s.bytes);
+    let mut line_count = start.map_or(0, |s| s.lines);
+    let mut found_count = 0;
+    let mut byte_count = 0;
+    let mut query_matches = vec![0; queries.len()];
+    let mut malformed = 0;
+    let mut invalid_utf8 = 0;
+    // We'll be doing the line search a lot, and we don't know at compile-time how many
+    // queries we'll have, so instead of allocating a new vector for each line we'll
+    // pass one in and reset it for each line read.
+    // Note that the order of these should match the order of `queries`.
+    let mut does_match = vec![false; queries.len()];
+    let mut matches: Vec<QueryMatches> = queries.iter().map(|_| QueryMatches::default()).collect();
+    let mut match_count = 0;
+    let mut buffered = ctx.writers.memory.buffered();
+    let mut last_write = Instant::now();
+    let mut writing = Duration::ZERO;
+    // The lines are read a batch at a time, and everything besides the matching itself is
+    // only done once per batch.
+    loop {
+        tui::wait_while_paused();
+        let batch = match lines.next_batch() {
+            Ok(Some(batch)) => batch,
+            Ok(None) => break,
+            Err(e) => {
+                let position = ResumePoint {
+                    lines: line_count,
+                    bytes: start_bytes + byte_count,
+                };
+                let error = format!("Error reading {input}: {e}");
+                return Err(SearchError::Failed(error, Some(position)));
+            }
+        };
+        byte_count += batch.len() as u64;
+
+        for (i, line_buf) in ctx.prefilter.candidates(batch) {
+            if !sampled(ctx, line_buf) {
+                continue;
+            }
+            let line_number = start.is_some().then_some(line_count + i + 1);
+            if !check_line(ctx, &source, line_number, line_buf) {
+                malformed += 1;
+                continue;
+            }
+            if !match_line(ctx, line_buf, &mut does_match) {
+                continue;
+            }
+            let Some((found, rendered)) = format_line(
+                ctx,
+                line_buf,
+                &source,
+                line_number,
+                &does_match,
+                &mut matches,
+                &mut query_matches,
+            ) else {
+                invalid_utf8 += 1;
+                continue;
+            };
+            found_count += found;
+            match_count += rendered;
+        }
+        let batch_lines = count_lines(batch);
+        line_count += batch_lines;
+        display::add_searched(batch_lines, batch.len() as u64);
+
+        buffered.set(matches_size(&matches));
+        let due = ctx
+            .flush_interval
+            .is_some_and(|interval| match_count > 0 && last_write.elapsed() >= interval);
+        if match_count >= ctx.flush_every || due || ctx.writers.memory.over_budget() {
+            let started = Instant::now();
+            found_count -= write_matches(ctx, &mut matches, sinks, &mut query_matches)?;
+            buffered.set(0);
+            match_count = 0;
+            ctx.writers.memory.wait_for_room();
+            last_write = Instant::now();
+            writing += last_write - started;
+        }
+
+        if let (Some(path), Some(interval)) = (&checkpoint_path, ctx.checkpoint_interval) {
+            // When interrupted or skipped, record how far we've got and stop. Inputs which
+            // can't be checkpointed are searched to the end instead.
+            let stopping = interrupted() || tui::skip_requested(&source);
+            if stopping || last_checkpoint.elapsed() >= interval {
+                let started = Instant::now();
+                found_count -= write_matches(ctx, &mut matches, sinks, &mut query_matches)?;
+                buffered.set(0);
+                match_count = 0;
+
+                let point = ResumePoint {
+                    lines: line_count,
+                    bytes: start_bytes + byte_count,
+                };
+                checkpoint(ctx, path, point)?;
+                last_checkpoint = Instant::now();
+                writing += last_checkpoint - started;
+                if stopping {
+                    return Err(SearchError::Interrupted(StreamStats {
+                        lines: line_count,
+                        found: found_count,
+                        bytes: byte_count,
+                        query_matches,
+                        malformed,
+                        invalid_utf8,
+                        writing,
+                    }));
+                }
+            }
+        }
+    }
+
+    if match_count > 0 {
+        let started = Instant::now();
+        found_count -= write_matches(ctx, &mut matches, sinks, &mut query_matches)?;
+        writing += started.elapsed();
+    }
+
+    Ok(StreamStats {
+        lines: line_count,
+        found: found_count,
+        bytes: byte_count,
+        query_matches,
+        malformed,
+        invalid_utf8,
+        writing,
+    })
+}
+
+/// The matches found in a chunk.
+struct ChunkMatches<'a> {
+    matches: Vec<QueryMatches>,
+    found: u64,
+    query_matches: Vec<u64>,
+    malformed: u64,
+    invalid_utf8: u64,
+    /// Counts the matches against the memory budget until they're written.
+    buffered: Buffered<'a>,
+}
+
+/// The lines of a chunk which matched any of the queries, found by the match stage for the
+/// format stage to render.
+#[derive(Default)]
+struct ChunkHits {
+    /// Where each line is in the chunk, and its line number.
+    lines: Vec<(Range<usize>, u64)>,
+    /// Which of the queries each line matched, one after the other.
+    does_match: Vec<bool>,
+    /// Lines skipped by `--check-records`.
+    malformed: u64,
+}
+
+/// The match stage for a chunk.
+fn match_chunk(ctx: &SearchContext, chunk: &Chunk<impl AsRef<[u8]>>, source: &str) -> ChunkHits {
+    let text = chunk.text.as_ref();
+    let mut does_match = vec![false; ctx.queries.len()];
+    let mut hits = ChunkHits::default();
+    for (i, line) in ctx.prefilter.candidates(text) {
+        if !sampled(ctx, line) {
+            continue;
+        }
+        if !check_line(ctx, source, Some(chunk.first_line + i), line) {
+            hits.malformed += 1;
+        } else if match_line(ctx, line, &mut does_match) {
+            let start = line.as_ptr() as usize - text.as_ptr() as usize;
+            hits.lines
+                .push((start..start + line.len(), chunk.first_line + i));
+            hits.does_match.extend_from_slice(&does_match);
+        }
+    }
+    hits
+}
+
+/// The format stage for a chunk, rendering the lines the match stage found.
+fn format_chunk<'a>(
+    ctx: &'a SearchContext,
+    chunk: &Chunk<impl AsRef<[u8]>>,
+    hits: &ChunkHits,
+    source: &str,
+) -> ChunkMatches<'a> {
+    let queries = ctx.queries.len();
+    let text = chunk.text.as_ref();
+    let mut result = ChunkMatches {
+        matches: ctx
+            .queries
+            .iter()
+            .map(|_| QueryMatches::default())
+            .collect(),
+        found: 0,
+        query_matches: vec![0; queries],
+        malformed: hits.malformed,
+        invalid_utf8: 0,
+        buffered: ctx.writers.memory.buffered(),
+    };
+    let does_match = hits.does_match.chunks(queries.max(1));
+    for ((range, line_number), does_match) in hits.lines.iter().zip(does_match) {
+        match format_line(
+            ctx,
+            &text[range.clone()],
+            source,
+            Some(*line_number),
+            does_match,
+            &mut result.matches,
+            &mut result.query_matches,
+        ) {
+            Some((found, _)) => result.found += found,
+            None => result.invalid_utf8 += 1,
+        }
+    }
+    result.buffered.set(matches_size(&result.matches));
+    result
+}
+
+/// Searches the decoded stream like [`search_chunks`], decoding it and splitting it into
+/// chunks on their own threads.
+fn search_stream_parallel(
+    ctx: &SearchContext,
+    reader: impl BufRead + Send,
+    input: &Input,
+    sinks: &[&dyn Sink],
+    start: ResumePoint,
+    chunk_size: u64,
+) -> Result<StreamStats, SearchError> {
+    std::thread::scope(|scope| {
+        let buffers = &ctx.chunk_buffers;
+        let chunks = pipeline::spawn_reader(
+            scope,
+            reader,
+            chunk_size,
+            start.lines,
+            buffers,
+            ctx.pipeline_depth,
+        );
+        let recycle = |block: Block| {
+            let mut text = block.into_buffer();
+            text.clear();
+            buffers.give(text);
+        };
+        search_chunks(ctx, chunks.into_iter(), recycle, input, sinks, start)
+    })
+}
+
+/// Searches the input like [`search_stream`], but with the stages connected by queues so
+/// that they run at once: the chunks of whole lines are handed out to the searching threads
+/// as soon as they've been read, their matches are rendered on the format threads if there
+/// are any, and written out in order as they finish. That way a single large input can keep
+/// all of the threads busy, and reading the input carries on while they're searching.
+///
+/// Once each chunk has been rendered its text is handed to `recycle`, so that its buffer
+/// can be used again.
+fn search_chunks<T: AsRef<[u8]> + Send>(
+    ctx: &SearchContext,
+    chunks: impl Iterator<Item = io::Result<Chunk<T>>>,
+    recycle: impl Fn(T) + Sync,
+    input: &Input,
+    sinks: &[&dyn Sink],
+    start: ResumePoint,
+) -> Result<StreamStats, SearchError> {
+    let source = input.to_string();
+    let stages = ChunkStages {
+        ctx,
+        recycle: &recycle,
+        source: &source,
+    };
+    match &ctx.format_pool {
+        Some(pool) => pool.in_place_scope(|format_scope| {
+            stages.run(chunks, input, sinks, start, Some(format_scope))
+        }),
+        None => stages.run(chunks, input, sinks, start, None),
+    }
+}
+
+/// What the stages of [`search_chunks`] share, which has to outlive the scope the format
+/// stage runs in.
+struct ChunkStages<'a, R> {
+    ctx: &'a SearchContext,
+    recycle: &'a R,
+    source: &'a str,
+}
+
+impl<'a, R> ChunkStages<'a, R> {
+    fn run<T: AsRef<[u8]> + Send + 'a>(
+        &self,
+        mut chunks: impl Iterator<Item = io::Result<Chunk<T>>>,
+        input: &Input,
+        sinks: &[&dyn Sink],
+        start: ResumePoint,
+        format_scope: Option<&rayon::Scope<'a>>,
+    ) -> Result<StreamStats, SearchError>
+    where
+        R: Fn(T) + Sync,
+    {
+        let Self {
+            ctx,
+            recycle,
+            source,
+        } = *self;
+        let checkpoint_path = input
+            .management_path(ctx.management_root.as_deref())
+            .filter(|_| !ctx.split_output && ctx.checkpoint_interval.is_some());
+        let mut last_checkpoint = Instant::now();
+        let mut stats = StreamStats {
+            lines: start.lines,
+            query_matches: vec![0; ctx.queries.len()],
+            ..StreamStats::default()
+        };
+        // Enough chunks to keep every thread busy, with more waiting for them.
+        let max_in_flight = (ctx.pool.current_num_threads() + ctx.pipeline_depth) as u64;
+
+        ctx.pool.in_place_scope(|scope| {
+            let (sender, results) = mpsc::channel();
+            // Chunks which have been searched, waiting on the ones before them to be written.
+            let mut searched = BTreeMap::new();
+            let mut next_read = 0;
+            let mut next_write = 0;
+            let mut reading = true;
+
+            loop {
+                tui::wait_while_paused();
+                while reading && next_read - next_write < max_in_flight {
+                    // Hold off on reading more while the matches are over the memory budget,
+                    // unless there's nothing else to wait on.
+                    if ctx.writers.memory.over_budget() {
+                        if next_read > next_write {
+                            break;
+                        }
+                        let started = Instant::now();
+                        ctx.writers.memory.wait_for_room();
+                        stats.writing += started.elapsed();
+                    }
+                    match chunks.next() {
+                        Some(Ok(chunk)) => {
+                            let (sender, index) = (sender.clone(), next_read);
+                            scope.spawn(move |_| {
+                                let hits = match_chunk(ctx, &chunk, source);
+                                let format = move || {
+                                    let result = format_chunk(ctx, &chunk, &hits, source);
+                                    let size = chunk.text.as_ref().len() as u64;
+                                    recycle(chunk.text);
+                                    let _ = sender.send((index, chunk.lines, size, result));
+                                };
+                                match format_scope {
+                                    Some(format_scope) => format_scope.spawn(move |_| format()),
+                                    None => format(),
+                                }
+                            });
+                            next_read += 1;
+                        }
+                        Some(Err(e)) => {
+                            // The chunks still being searched come after this.
+                            let position = ResumePoint {
+                                lines: stats.lines,
+                                bytes: start.bytes + stats.bytes,
+                            };
+                            let error = format!("Error reading {input}: {e}");
+                            return Err(SearchError::Failed(error, Some(position)));
+                        }
+                        None => reading = false,
+                    }
+                }
+                if next_write == next_read {
+                    break;
+                }
+
+                let (index, lines, size, result) = receive_searched(ctx, &results);
+                searched.insert(index, (lines, size, result));
+
+                // The chunks are written out in order, so the output is the same as searching
+                // the lines one at a time.
+                while let Some((lines, size, mut result)) = searched.remove(&next_write) {
+                    next_write += 1;
+                    if result.found > 0 {
+                        let started = Instant::now();
+                        result.found -= write_matches(
+                            ctx,
+                            &mut result.matches,
+                            sinks,
+                            &mut result.query_matches,
+                        )?;
+                        stats.writing += started.elapsed();
+                    }
+                    stats.lines += lines;
+                    stats.bytes += size;
+                    display::add_searched(lines, size);
+                    stats.found += result.found;
+                    stats.malformed += result.malformed;
+                    stats.invalid_utf8 += result.invalid_utf8;
+                    for (total, count) in stats.query_matches.iter_mut().zip(result.query_matches) {
+                        *total += count;
+                    }
+                }
+
+                if let (Some(path), Some(interval)) = (&checkpoint_path, ctx.checkpoint_interval) {
+                    // Chunks still being searched are searched again when resuming.
+                    let stopping = interrupted() || tui::skip_requested(source);
+                    if stopping || last_checkpoint.elapsed() >= interval {
+                        let started = Instant::now();
+                        let point = ResumePoint {
+                            lines: stats.lines,
+                            bytes: start.bytes + stats.bytes,
+                        };
+                        checkpoint(ctx, path, point)?;
+                        last_checkpoint = Instant::now();
+                        stats.writing += last_checkpoint - started;
+                        if stopping {
+                            return Err(SearchError::Interrupted(stats));
+                        }
+                    }
+                }
+            }
+
+            Ok(stats)
+        })
+    }
+}
+
+/// Waits for a chunk to finish being searched. If we're on one of the searching threads,
+/// it helps with the searching while it waits, rather than holding up the chunks.
+fn receive_searched<T>(ctx: &SearchContext, results: &mpsc::Receiver<T>) -> T {
+    const EXPECT: &str = "a sender is kept while waiting for chunks";
+    if ctx.pool.current_thread_index().is_none() {
+        return results.recv().expect(EXPECT);
+    }
+
+    loop {
+        match results.try_recv() {
+            Ok(result) => return result,
+            Err(mpsc::TryRecvError::Empty) => {}
+            Err(mpsc::TryRecvError::Disconnected) => panic!("{EXPECT}"),
+        }
+        if rayon::yield_now() != Some(rayon::Yield::Executed) {
+            // Everything left is already being worked on.
+            if let Ok(result) = results.recv_timeout(Duration::from_millis(1)) {
+                return result;
+            }
+        }
+    }
+}
+
+/// Searches the whole input as a single stream, carrying on from where an earlier run got
+/// to.
+fn search_whole(
+    ctx: &SearchContext,
+    input: &Input,
+    sinks: &[&dyn Sink],
+    progress: Option<&FileProgress>,
+) -> Result<StreamStats, SearchError> {
+    let map = match ctx.mmap.then(|| input.map()).transpose() {
+        Ok(map) => map.flatten(),
+        Err(e) => return Err(format!("Error mapping {input}: {e:#}").into()),
+    };
+    let too_short = || format!("Error resuming {input}: file is shorter than the resume point");
+
+    let start = resume_point(ctx, input).unwrap_or_default();
+    if start.bytes > 0 {
+        status!("Resuming {input} from line {}", start.lines);
+    }
+
+    if let Some(map) = map {
+        let text = usize::try_from(start.bytes)
+            .ok()
+            .and_then(|start| map.get(start..))
+            .ok_or_else(too_short)?;
+        return match ctx.parallel_chunk_size {
+            Some(size) => search_chunks(
+                ctx,
+                pipeline::split_chunks(text, size, start.lines),
+                drop,
+                input,
+                sinks,
+                start,
+            ),
+            None => search_stream(ctx, SliceLines::new(text), input, sinks, Some(start)),
+        };
+    }
+
+    let opened = input.open().and_then(|raw| {
+        let raw = match progress {
+            Some(progress) => progress.counted(raw),
+            None => raw,
+        };
+        input.decode(raw, &ctx.decode_options)
+    });
+    let mut reader = match opened {
+        Ok(reader) => reader,
+        Err(e) => return Err(format!("Error opening {input}: {e:#}").into()),
+    };
+    if start.bytes > 0 {
+        match io::copy(&mut (&mut reader).take(start.bytes), &mut io::sink()) {
+            Ok(skipped) if skipped == start.bytes => {}
+            Ok(_) => return Err(too_short().into()),
+            Err(e) => return Err(format!("Error reading {input}: {e}").into()),
+        }
+    }
+    match ctx.parallel_chunk_size {
+        Some(size) => search_stream_parallel(ctx, reader, input, sinks, start, size),
+        None => search_stream(ctx, ReaderLines::new(reader), input, sinks, Some(start)),
+    }
+}
+
+/// Runs the `search` command, sending the notification if it fails before it gets as far as
+/// sending one itself.
+pub(crate) fn search(args: SearchArgs) -> Result<()> {
+    let grep_exit_codes = args.grep_exit_codes;
+    let notifier = Notifier {
+        url: args.notify_url.clone(),
+        command: args.notify_cmd.clone(),
+    };
+    let result = run(args, &notifier);
+    if let Err(e) = &result {
+        notifier.notify(&Notification {
+            outcome: Outcome::Failed,
+            error: Some(format!("{e:#}")),
+            summary: None,
+        });
+    }
+    if let (Err(e), true) = (&result, grep_exit_codes) {
+        eprintln!("Error: {e:?}");
+        std::process::exit(GREP_ERROR);
+    }
+    result
+}
+
+fn run(args: SearchArgs, notifier: &Notifier) -> Result<()> {
+    let started = Instant::now();
+    STATUS_TO_STDERR.store(args.stdout, Ordering::Relaxed);
+    interrupt::install_handler();
+    if let Some(limit) = args.max_runtime {
+        interrupt::stop_after(limit.saturating_sub(started.elapsed()));
+    }
+    let searching = !args.list_files && !args.estimate;
+    if searching
+        && !args.tui
+        && !args.no_progress
+        && logging::enabled(LogLevel::Info)
+        && !logging::json()
+    {
+        display::start();
+    }
+    let mut inputs = Vec::new();
+    let mut excluded = Vec::new();
+    let selector = match args.files_folder.as_deref() {
+        Some("-") => {
+            inputs.push(Input::Stdin);
+            None
+        }
+        Some(files_folder) => {
+            let selector = InputSelector::new(files_folder, &args.glob, &args.exclude)?
+                .newer_than(args.newer_than)
+                .size_range(args.min_size, args.max_size)
+                .shard(args.shard);
+            let selection = selector.find()?;
+            if !args.list_files {
+                // Most of the files are usually in other shards, which isn't worth a line each.
+                let mut other_shards = 0;
+                for (path, reason) in &selection.excluded {
+                    if *reason == OTHER_SHARD {
+                        other_shards += 1;
+                    } else {
+                        status!("Skipping file {} ({reason})", path.display());
+                    }
+                }
+                if other_shards > 0 {
+                    status!("Skipping {other_shards} files in other shards");
+                }
+            }
+            if selection.inputs.is_empty() {
+                eprintln!(
+                    "No files matching `{}` found in `{files_folder}`",
+                    args.glob
+                );
+            }
+            inputs.extend(selection.inputs);
+            excluded = selection.excluded;
+            Some(selector)
+        }
+        None => None,
+    };
+    remote::set_read_ahead(args.read_ahead);
+    inputs.extend(
+        args.input_urls
+            .iter()
+            .filter(|url| args.shard.is_none_or(|shard| shard.contains(url)))
+            .cloned()
+            .map(Input::Url),
+    );
+    if args.list_files {
+        return list_files(&args, &inputs, &excluded);
+    }
+
+    if inputs.is_empty() && !args.watch {
+        eprintln!("No input files found");
+        notifier.notify(&Notification {
+            outcome: Outcome::Failed,
+            error: Some("No input files found".to_owned()),
+            summary: None,
+        });
+        if args.grep_exit_codes {
+            std::process::exit(GREP_ERROR);
+        }
+        return Ok(());
+    }
+
+    let (query_file, mut queries) = load_queries(&args.query_json)?;
+    if args.invert {
+        queries.iter_mut().for_each(|q| q.invert = true);
+    }
+    if let Some(path) = &args.channel_list {
+        let channels = Arc::new(ChannelList::load(path)?);
+        status!("Limiting the search to {} channels", channels.len());
+        for query in queries.iter_mut().filter(|q| q.channels.is_none()) {
+            query.channels = Some(channels.clone());
+        }
+    }
+    let searchers = build_searchers(&queries, args.automaton);
+    // Every line has to be looked at to check it's a record.
+    let prefilter = if args.check_records {
+        Prefilter::every_line()
+    } else {
+        Prefilter::new(&queries, args.automaton)
+    };
+
+    let dictionary = match &args.zstd_dict {
+        Some(path) => {
+            Some(std::fs::read(path).with_context(|| anyhow!("Error reading zstd dictionary"))?)
+        }
+        None => None,
+    };
+    let decode_options = DecodeOptions {
+        skip_corrupt_frames: args.skip_corrupt_frames,
+        split_frames: args.split_frames,
+        frame_chunk_size: args.frame_chunk_size,
+        dictionary,
+        window_log_max: args.zstd_window_log,
+    };
+
+    if args.estimate {
+        // Nothing is written while estimating, so it can be run alongside the search it's
+        // estimating.
+        let (management_root, management) = peek_management(&args)?;
+        inputs.retain(|input| {
+            !input
+                .management_path(management_root.as_deref())
+                .is_some_and(|path| management.c_files.contains(&path))
+        });
+        // Files are searched one to a thread, on the I/O threads if there are any.
+        let threads = args
+            .io_threads
+            .or(args.threads)
+            .unwrap_or_else(rayon::current_num_threads);
+        return estimate::estimate(
+            &inputs,
+            &queries,
+            &searchers,
+            &prefilter,
+            &decode_options,
+            threads,
+            args.estimate_samples,
+            args.estimate_sample_size,
+        );
+    }
+
+    let dashboard = args.tui.then(|| tui::start(&queries)).transpose()?;
+    let progress_file = args.progress_file.clone().map(|path| {
+        progress_file::start(
+            path,
+            Duration::from_secs(args.progress_interval.max(1)),
+            queries.iter().map(|q| q.filename.clone()).collect(),
+        )
+    });
+    logging::log(Event::RunStarted {
+        inputs: inputs.len(),
+        queries: queries.len(),
+    });
+
+    std::fs::create_dir_all(&args.output_dir)
+        .with_context(|| anyhow!("Error creating output directory"))?;
+
+    // Ensure the folder exists if the management path has a parent.
+    if let Some(parent) = args.management_file.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| anyhow!("Error creating parent directory for management file"))?;
+    }
+    let _lock = lock_management(&args.management_file, args.wait_for_lock)?;
+
+    let sample = args
+        .sample
+        .map(|fraction| LineSample::new(fraction, args.sample_seed));
+    let (management_root, management, overwrite) =
+        open_management(&args, &query_file, sample.as_ref())?;
+
+    if args.retry_failed {
+        inputs.retain(|input| {
+            input
+                .management_path(management_root.as_deref())
+                .is_some_and(|path| management.failed_files.contains_key(&path))
+        });
+        status!("Retrying {} failed files", inputs.len());
+    }
+    let resuming = args.management_file.exists() && !overwrite;
+
+    let mode = write_mode(&args, overwrite, resuming);
+    let formatter = Formatter::new(args.output_format, args.fields.clone());
+    let output_options = OutputOptions {
+        mode,
+        compression: args.compress_output,
+        max_size: args.max_output_size,
+        buffer_size: args.write_buffer_size as usize,
+    };
+
+    let (output_files, seen_ids) = open_output_files(&args, &queries, &formatter, &output_options)?;
+
+    let progress = Arc::new(Mutex::new(Progress::new(
+        management.clone(),
+        args.management_file.clone(),
+        args.management_journal,
+    )?));
+    let (save_requests, receiver) = mpsc::channel();
+    let saver = {
+        let progress = progress.clone();
+        let every = args.save_every.max(1);
+        let interval = Duration::from_secs(args.save_interval);
+        std::thread::spawn(move || run_saver(&progress, receiver, every, interval))
+    };
+
+    let pool = thread_pool(args.threads)?;
+    let writers = Arc::new(WriterShared {
+        threads: pool.current_num_threads(),
+        memory: MatchMemory::new(args.max_match_memory),
+        // Enough for each thread to have a batch of matches for each query being written,
+        // while it fills another.
+        buffers: BufferPool::new(pool.current_num_threads() * queries.len() * 2),
+        flush_interval: args.flush_every_secs.map(Duration::from_secs),
+        sync_interval: args.fsync_every_secs.map(Duration::from_secs),
+    });
+    let io_pool = args.io_threads.map(|n| thread_pool(Some(n))).transpose()?;
+    let format_pool = args
+        .format_threads
+        .filter(|_| args.parallel_chunks)
+        .map(|n| thread_pool(Some(n.max(1))))
+        .transpose()?;
+    let pipeline_depth = args
+        .pipeline_depth
+        .unwrap_or_else(|| pool.current_num_threads())
+        .max(1);
+    let sinks = open_sinks(&args, &queries, output_files, &writers, mode)?;
+    let ctx = SearchContext {
+        keep_lines: sinks.iter().any(|sink| sink.wants_lines()),
+        sinks,
+        // As many as there can be chunks in flight, and queued up behind them.
+        chunk_buffers: BufferPool::new(pool.current_num_threads() + pipeline_depth * 2),
+        pool,
+        format_pool,
+        pipeline_depth,
+        writers,
+        seen_ids,
+        progress: progress.clone(),
+        save_requests: save_requests.clone(),
+        report: Mutex::new(Report::new(
+            queries.iter().map(|q| q.filename.as_str()),
+            sample.map(|s| s.fraction()),
+        )),
+        errors: ErrorLog::new(args.output_dir.join("errors.log")),
+        sample,
+        malformed: args.check_records.then(|| {
+            MalformedLog::new(
+                args.output_dir.join("malformed.jsonl"),
+                args.required_fields,
+            )
+        }),
+        fail_fast: args.fail_fast,
+        stopped_early: AtomicBool::new(false),
+        management,
+        decode_options,
+        dedup_inputs: args.dedup_inputs || args.match_by_content,
+        match_by_content: args.match_by_content,
+        flush_every: args.flush_every_matches.max(1),
+        flush_interval: args.flush_every_secs.map(Duration::from_secs),
+        parallel_chunk_size: args
+            .parallel_chunks
+            .then_some(args.parallel_chunk_size.max(1)),
+        mmap: args.mmap,
+        index_folder: args.index_folder,
+        checkpoint_interval: (args.checkpoint_interval > 0)
+            .then(|| Duration::from_secs(args.checkpoint_interval)),
+        formatter,
+        output_dir: args.output_dir,
+        output_options,
+        split_output: args.split_output,
+        input_root: args.files_folder.as_ref().map(PathBuf::from),
+        management_root,
+        queries,
+        searchers,
+        prefilter,
+    };
+
+    let search_all = |inputs: Vec<Input>| {
+        if let Some(display) = display::get() {
+            display.add_inputs(&inputs);
+        }
+        let pool = io_pool.as_ref().unwrap_or(&ctx.pool);
+        pipeline::enumerate(pool, inputs, |input| search_file(&ctx, input));
+
+        // In watch mode we won't be exiting to flush the outputs, so do it after each batch.
+        for e in flush_outputs(&ctx) {
+            eprintln!("{e}");
+        }
+
+        let mut report = ctx.report.lock().unwrap();
+        if let Err(e) = report.write(&ctx.output_dir, started.elapsed()) {
+            eprintln!("{e:#}");
+        }
+        let _ = ctx.save_requests.send(SaveRequest::Now);
+    };
+
+    let mut seen: HashSet<PathBuf> = inputs
+        .iter()
+        .filter_map(|input| input.management_path(ctx.management_root.as_deref()))
+        .collect();
+    search_all(inputs);
+
+    if let Some(selector) = selector.filter(|_| args.watch) {
+        status!("Watching for new files...");
+        // Files are only searched once their size has stopped changing between polls, so
+        // that we don't pick up a file while it's still being written.
+        let mut last_sizes: HashMap<PathBuf, Option<u64>> = HashMap::new();
+        while !interrupted() {
+            interrupt::sleep(Duration::from_secs(args.watch_interval));
+            if interrupted() {
+                break;
+            }
+
+            // Inputs which failed are searched again once they're ready, in case whatever was
+            // wrong with them has since been put right.
+            {
+                let progress = ctx.progress.lock().unwrap();
+                seen.retain(|key| !progress.management.failed_files.contains_key(key));
+            }
+
+            let selection = match selector.find() {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("Error finding input files: {e:#}");
+                    continue;
+                }
+            };
+
+            let mut ready = Vec::new();
+            for input in selection.inputs {
+                let Some(key) = input.management_path(ctx.management_root.as_deref()) else {
+                    continue;
+                };
+                if seen.contains(&key) {
+                    continue;
+                }
+
+                let size = input.source_size();
+                if last_sizes.insert(key.clone(), size) == Some(size) {
+                    last_sizes.remove(&key);
+                    seen.insert(key);
+                    ready.push(input);
+                }
+            }
+
+            if !ready.is_empty() {
+                search_all(ready);
+            }
+        }
+    }
+
+    // The outputs are only finished once everything has been searched without a failure
+    // stopping the run. Otherwise they're left as they are, to be carried on with by the next run.
+    let finished = !ctx.stopped_early.load(Ordering::Relaxed) && !interrupt::failed();
+    close_sinks(ctx.sinks, finished);
+    let _ = save_requests.send(SaveRequest::Finish);
+    let _ = saver.join();
+    drop(dashboard);
+    drop(progress_file);
+    if let Some(display) = display::get() {
+        display.finish();
+    }
+    report_outcome(
+        &ctx.report,
+        started,
+        finished,
+        args.summary_file.as_deref(),
+        args.grep_exit_codes,
+        notifier,
+    );
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use super::{SearchArgs, SearchContext};
+use crate::{
+    input::{canonical_path, Input},
+    logging::{self, Event},
+    management::{query_set_hash, Change, Management, ResumePoint, SaveRequest},
+    sample::LineSample,
+};
+
+/// Where to carry on searching the input from, if an earlier run was interrupted part way
+/// through.
+pub(super) fn resume_point(ctx: &SearchContext, input: &Input) -> Option<ResumePoint> {
+    // Split outputs are always started from scratch.
+    if ctx.split_output {
+        return None;
+    }
+    let path = input.management_path(ctx.management_root.as_deref())?;
+    ctx.management.partial.get(&path).copied()
+}
+
+/// Records how far through the input we've got, once everything written before this point
+/// has made it to the output files.
+pub(super) fn checkpoint(
+    ctx: &SearchContext,
+    path: &Path,
+    point: ResumePoint,
+) -> Result<(), String> {
+    if let Some(e) = flush_outputs(ctx).into_iter().next() {
+        return Err(e);
+    }
+
+    let mut progress = ctx.progress.lock().unwrap();
+    progress.record(Change::Checkpoint {
+        file: path.to_path_buf(),
+        point,
+    });
+    let _ = ctx.save_requests.send(SaveRequest::Now);
+    logging::log(Event::Checkpoint {
+        file: &path.display().to_string(),
+        lines: point.lines,
+        bytes: point.bytes,
+    });
+    Ok(())
+}
+
+/// Waits for everything written so far to be flushed to the shared outputs, returning any
+/// errors. The IDs of the records written are then saved for `dedup`, so that they're only
+/// recorded once their records are safely in the outputs.
+pub(super) fn flush_outputs(ctx: &SearchContext) -> Vec<String> {
+    // Taken before flushing, as records are handed to the sinks while their IDs are held.
+    let pending: Vec<_> = ctx
+        .seen_ids
+        .iter()
+        .map(|seen_ids| seen_ids.as_ref().map(|s| s.lock().unwrap().take_pending()))
+        .collect();
+    let mut errors = Vec::new();
+    for sink in &ctx.sinks {
+        if let Err(e) = sink.flush() {
+            errors.push(e);
+        }
+    }
+    let flushed = errors.is_empty();
+    let seen_ids = ctx.queries.iter().zip(&ctx.seen_ids).zip(pending);
+    for ((query, seen_ids), pending) in seen_ids {
+        let (Some(seen_ids), Some(pending)) = (seen_ids, pending) else {
+            continue;
+        };
+        let mut seen_ids = seen_ids.lock().unwrap();
+        if !flushed {
+            seen_ids.restore_pending(pending);
+        } else if let Err(e) = seen_ids.save(&pending) {
+            errors.push(format!("Error recording IDs for {}: {e}", query.filename));
+        }
+    }
+    logging::log(Event::Flushed {
+        outputs: ctx.sinks.len(),
+        errors: errors.len(),
+    });
+    errors
+}
+
+/// The folder the management file's paths are relative to, if the inputs are in one.
+fn management_root(args: &SearchArgs) -> Option<PathBuf> {
+    args.files_folder
+        .as_deref()
+        .filter(|folder| *folder != "-")
+        .map(|folder| canonical_path(Path::new(folder)))
+}
+
+/// Loads the management file for the search to carry on from, folding in its journal. It
+/// has to be locked first. Returns it along with the root its paths are relative to, and
+/// whether the run is starting over rather than carrying on from an earlier one.
+pub(super) fn open_management(
+    args: &SearchArgs,
+    query_file: &str,
+    sample: Option<&LineSample>,
+) -> Result<(Option<PathBuf>, Management, bool)> {
+    let management_root = management_root(args);
+    let mut overwrite = args.overwrite;
+    let mut management = if args.management_file.exists() && !overwrite {
+        Management::load_for_update(&args.management_file, management_root.as_deref())?
+    } else {
+        Management::default()
+    };
+
+    // A sampled run searches different lines to a full one, or one with another sample.
+    let query_hash = match sample {
+        Some(sample) => format!("{} ({})", query_set_hash(query_file)?, sample.describe()),
+        None => query_set_hash(query_file)?,
+    };
+    if management
+        .query_hash
+        .as_ref()
+        .is_some_and(|h| *h != query_hash)
+    {
+        if args.force_resume {
+            eprintln!(
+                "WARNING: The queries have changed since the last run. Files searched by \
+                earlier runs won't be searched again with the new queries."
+            );
+        } else if args.restart {
+            eprintln!("The queries have changed since the last run, starting over");
+            overwrite = true;
+            management = Management::default();
+        } else {
+            bail!(
+                "The queries in {} (or the --sample) have changed since the last run, so files \
+                searched by earlier runs were searched with different queries. Pass \
+                --force-resume to carry on anyway, or --restart to start over",
+                args.query_json.display()
+            );
+        }
+    }
+    management.query_hash = Some(query_hash);
+    Ok((management_root, management, overwrite))
+}
+
+/// Loads the management file without locking it, for looking at what a search would do
+/// without starting one. Returns it along with the root its paths are relative to.
+///
+/// A search may be running on it, so the journal is only read, not folded in.
+pub(super) fn peek_management(args: &SearchArgs) -> Result<(Option<PathBuf>, Management)> {
+    let management_root = management_root(args);
+    let management = if args.management_file.exists() && !args.overwrite {
+        Management::load(&args.management_file, management_root.as_deref())?
+    } else {
+        Management::default()
+    };
+    Ok((management_root, management))
+}
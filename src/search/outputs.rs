@@ -0,0 +1,236 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context, Result};
+
+use super::{QueryMatches, SearchArgs, SearchContext};
+use crate::{
+    aggregate::ChannelTotalsSink,
+    dedup::SeenIds,
+    display,
+    elastic::BulkIndexer,
+    input::Input,
+    output::{Formatter, OutputFile, OutputOptions, WriteMode},
+    sink::{QueryFiles, Sink},
+    tui,
+    writer::WriterShared,
+    Query,
+};
+
+/// How the shared output files are opened.
+pub(super) fn write_mode(args: &SearchArgs, overwrite: bool, resuming: bool) -> WriteMode {
+    // Appending is the default when resuming, so the results from files searched in
+    // earlier runs are kept. A new run replaces whatever is already there, unless told not
+    // to touch it.
+    if overwrite {
+        WriteMode::Overwrite
+    } else if args.append || resuming {
+        WriteMode::Append
+    } else if args.create_new {
+        WriteMode::CreateNew
+    } else {
+        WriteMode::Overwrite
+    }
+}
+
+/// The IDs already written for each query, for those being deduplicated.
+type SeenIdsByQuery = Vec<Option<Mutex<SeenIds>>>;
+
+/// Opens the shared output file for each query, unless the output is split per input or
+/// going to stdout, along with the IDs already written to it for those being deduplicated.
+pub(super) fn open_output_files(
+    args: &SearchArgs,
+    queries: &[Query],
+    formatter: &Formatter,
+    output_options: &OutputOptions,
+) -> Result<(Vec<OutputFile>, SeenIdsByQuery)> {
+    let mut output_files = Vec::new();
+    let mut seen_ids = Vec::new();
+    for query in queries {
+        let path = args.output_dir.join(&query.filename);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| anyhow!("Error creating output directory {}", parent.display()))?;
+        }
+        if query.deduplicated(formatter) {
+            let mut seen_path = path.clone().into_os_string();
+            seen_path.push(".seen-ids");
+            seen_ids.push(Some(Mutex::new(SeenIds::open(
+                seen_path.as_ref(),
+                output_options.mode != WriteMode::Append,
+            )?)));
+        } else {
+            seen_ids.push(None);
+        }
+        if args.stdout {
+            output_files.push(OutputFile::stdout(&query.filename));
+        } else if !args.split_output {
+            output_files.push(OutputFile::open(
+                path,
+                output_options,
+                query.header(formatter),
+            )?);
+        }
+    }
+    Ok((output_files, seen_ids))
+}
+
+/// Sets up where the matches go: the shared output files, and any others asked for.
+pub(super) fn open_sinks(
+    args: &SearchArgs,
+    queries: &[Query],
+    output_files: Vec<OutputFile>,
+    writers: &Arc<WriterShared>,
+    mode: WriteMode,
+) -> Result<Vec<Box<dyn Sink>>> {
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+    if !output_files.is_empty() {
+        sinks.push(Box::new(QueryFiles::new(
+            output_files,
+            writers.clone(),
+            false,
+        )));
+    }
+    if let Some(url) = &args.elasticsearch_url {
+        sinks.push(Box::new(BulkIndexer::new(
+            url,
+            &args.elasticsearch_index_prefix,
+            queries.iter().map(|q| q.filename.as_str()),
+            args.elasticsearch_batch_size,
+            args.elasticsearch_retries,
+        )));
+    }
+    if args.aggregate {
+        sinks.push(Box::new(ChannelTotalsSink::new(
+            queries.iter().map(|q| args.output_dir.join(&q.filename)),
+            mode == WriteMode::Append,
+        )?));
+    }
+    Ok(sinks)
+}
+
+/// Opens the output files for an input when splitting the output per input. These are
+/// named `<query>/<input>.<ext>`, mirroring the layout of the input folder.
+pub(super) fn open_split_output(ctx: &SearchContext, input: &Input) -> Result<Vec<OutputFile>> {
+    let input_path = input
+        .source_path()
+        .unwrap_or_else(|| PathBuf::from("stdin"));
+    let relative = match &ctx.input_root {
+        Some(root) => input_path.strip_prefix(root).ok().map(Path::to_path_buf),
+        None => None,
+    };
+    let mut relative =
+        relative.unwrap_or_else(|| input_path.file_name().unwrap_or_default().into());
+
+    // Strip the compression and format extensions, e.g. `foo.jsonl.zst` becomes `foo`.
+    while let Some("zst" | "gz" | "zip" | "jsonl" | "json" | "ndjson") =
+        relative.extension().and_then(|e| e.to_str())
+    {
+        relative.set_extension("");
+    }
+
+    // A previous attempt at searching this input will have been incomplete, so we always
+    // start its output from scratch.
+    let options = OutputOptions {
+        mode: WriteMode::Overwrite,
+        ..ctx.output_options.clone()
+    };
+
+    ctx.queries
+        .iter()
+        .map(|query| {
+            let query_path = Path::new(&query.filename);
+            let mut path = ctx
+                .output_dir
+                .join(query_path.with_extension(""))
+                .join(&relative);
+            if let Some(ext) = query_path.extension() {
+                let mut name = path.into_os_string();
+                name.push(".");
+                name.push(ext);
+                path = name.into();
+            }
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).with_context(|| {
+                    anyhow!("Error creating output directory {}", parent.display())
+                })?;
+            }
+            OutputFile::open(path, &options, query.header(&ctx.formatter))
+        })
+        .collect()
+}
+
+/// Hands the matches over to the sinks, leaving `matches` empty. Records left out as
+/// duplicates by `dedup` are taken off their query's count in `query_matches`, returning
+/// how many were left out in all.
+pub(super) fn write_matches(
+    ctx: &SearchContext,
+    matches: &mut [QueryMatches],
+    sinks: &[&dyn Sink],
+    query_matches: &mut [u64],
+) -> Result<u64, String> {
+    let mut duplicates = 0;
+    for (i, matches) in matches.iter_mut().enumerate() {
+        if matches.records.is_empty() {
+            continue;
+        }
+
+        let mut kept = None;
+        // Held until the records are with the sinks, so that their IDs aren't saved by a
+        // checkpoint before they're flushed along with the rest.
+        let mut seen_ids = ctx.seen_ids[i].as_ref().map(|s| s.lock().unwrap());
+        let (records, lines) = match &mut seen_ids {
+            // Nothing's being left out, so the whole buffer can be handed over as it is.
+            None => (&matches.records, matches.lines.iter().collect()),
+            Some(seen_ids) => {
+                let mut records = ctx.writers.buffers.take();
+                let mut lines = Vec::new();
+                let mut all_lines = matches.lines.iter();
+                for (record, id_hash) in matches.records.iter().zip(&matches.id_hashes) {
+                    let line = all_lines.next();
+                    if id_hash.is_some_and(|id_hash| !seen_ids.insert(id_hash)) {
+                        continue;
+                    }
+                    records.push(record);
+                    lines.extend(line);
+                }
+                (&*kept.insert(records), lines)
+            }
+        };
+        let left_out = (matches.records.len() - records.len()) as u64;
+        query_matches[i] -= left_out;
+        duplicates += left_out;
+        display::add_matches(i, records.len() as u64);
+        tui::add_recent(i, records);
+        for sink in sinks {
+            sink.write_matches(i, records, &lines)?;
+        }
+        drop(seen_ids);
+
+        if let Some(mut records) = kept {
+            records.clear();
+            ctx.writers.buffers.give(records);
+        }
+        matches.records.clear();
+        matches.id_hashes.clear();
+        matches.lines.clear();
+    }
+    Ok(duplicates)
+}
+
+/// Finishes off the sinks once the run is over. Unless everything was searched, they're
+/// only flushed and closed, leaving the output files to be carried on with by the next run.
+pub(super) fn close_sinks(sinks: Vec<Box<dyn Sink>>, finished: bool) {
+    for sink in sinks {
+        let result = if finished {
+            sink.finalize()
+        } else {
+            sink.close()
+        };
+        if let Err(e) = result {
+            eprintln!("{e}");
+        }
+    }
+}
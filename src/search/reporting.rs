@@ -0,0 +1,135 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Instant,
+};
+
+use anyhow::Result;
+
+use super::{checkpoint::peek_management, SearchArgs, GREP_ERROR, TIMED_OUT};
+use crate::{
+    input::Input,
+    interrupt::{self, interrupted},
+    logging::{self, Event},
+    notify::{Notification, Notifier, Outcome},
+    report::Report,
+    status,
+};
+
+/// Prints what a search with these arguments would do with each input, going by the
+/// management file as it stands.
+pub(super) fn list_files(
+    args: &SearchArgs,
+    inputs: &[Input],
+    excluded: &[(PathBuf, &str)],
+) -> Result<()> {
+    let (management_root, management) = peek_management(args)?;
+
+    let size = |size: Option<u64>| match size {
+        Some(size) => format!("{:.1} MB", size as f64 / 1e6),
+        None => "unknown".to_string(),
+    };
+    let mut counts: BTreeMap<&str, (usize, u64)> = BTreeMap::new();
+    for input in inputs {
+        let status = match input.management_path(management_root.as_deref()) {
+            Some(ref path) if management.c_files.contains(path) => "completed",
+            Some(ref path) if management.failed_files.contains_key(path) => "retry failed",
+            _ if args.retry_failed => "not retried",
+            Some(ref path) if management.partial.contains_key(path) => "resume",
+            _ => "search",
+        };
+        let source_size = input.source_size();
+        println!("{status:<12} {:>12}  {input}", size(source_size));
+        let count = counts.entry(status).or_default();
+        count.0 += 1;
+        count.1 += source_size.unwrap_or(0);
+    }
+    for (path, reason) in excluded {
+        let file_size = std::fs::metadata(path).ok().map(|m| m.len());
+        println!(
+            "{:<12} {:>12}  {} ({reason})",
+            "excluded",
+            size(file_size),
+            path.display()
+        );
+        let count = counts.entry("excluded").or_default();
+        count.0 += 1;
+        count.1 += file_size.unwrap_or(0);
+    }
+
+    if counts.is_empty() {
+        eprintln!("No input files found");
+    }
+    for (status, (files, bytes)) in counts {
+        println!("{status}: {files} files, {}", size(Some(bytes)));
+    }
+    Ok(())
+}
+
+/// Prints the summary once the run is over and sends the notification, exiting with the
+/// status for how the run ended unless it's a plain success.
+pub(super) fn report_outcome(
+    report: &Mutex<Report>,
+    started: Instant,
+    finished: bool,
+    summary_file: Option<&Path>,
+    grep_exit_codes: bool,
+    notifier: &Notifier,
+) {
+    let mut report = report.lock().unwrap();
+    let summary = report.summary(started.elapsed());
+    logging::log(Event::Summary(summary));
+    if let Some(path) = summary_file {
+        if let Err(e) = summary.write(path) {
+            eprintln!("{e:#}");
+        }
+    }
+    // A time limit reached once everything had been searched didn't cut anything short.
+    let timed_out = interrupt::timed_out() && !finished;
+    let stopped = interrupted() && !(interrupt::timed_out() && finished);
+    logging::log(Event::RunFinished {
+        elapsed: started.elapsed(),
+        interrupted: stopped,
+    });
+    let outcome = if interrupt::failed() {
+        Outcome::Failed
+    } else if timed_out {
+        Outcome::TimedOut
+    } else if stopped {
+        Outcome::Interrupted
+    } else {
+        Outcome::Finished
+    };
+    notifier.notify(&Notification {
+        outcome,
+        error: interrupt::failed().then(|| "An input couldn't be searched".to_owned()),
+        summary: Some(summary),
+    });
+
+    if interrupt::failed() {
+        eprintln!(
+            "Stopped at the first input which couldn't be searched (--fail-fast), with the \
+            outputs left as .partial files"
+        );
+        std::process::exit(if grep_exit_codes { GREP_ERROR } else { 1 });
+    }
+    if timed_out {
+        status!(
+            "Reached the --max-runtime, the search will carry on from here next time, with the \
+            outputs left as .partial files until then"
+        );
+        std::process::exit(TIMED_OUT);
+    }
+    if stopped {
+        status!("Interrupted, the search will carry on from here next time");
+        std::process::exit(130);
+    }
+    if grep_exit_codes {
+        if summary.files_failed() > 0 {
+            std::process::exit(GREP_ERROR);
+        } else if summary.matches() == 0 {
+            std::process::exit(1);
+        }
+    }
+}
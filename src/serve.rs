@@ -0,0 +1,444 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ffi::OsString,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{output::output_parts, parse_queries, status};
+
+/// The largest query set that can be submitted.
+const MAX_BODY: u64 = 16 << 20;
+/// How often a running job is checked on, to see whether it's finished or been stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, clap::Args)]
+pub struct ServeArgs {
+    /// Address to listen for requests on.
+    #[clap(long = "listen", short = 'l', default_value = "127.0.0.1:8080")]
+    listen: String,
+    /// Folder of input files the submitted queries are searched.
+    #[clap(long = "input-folder", short = 'i', alias = "files-folder")]
+    files_folder: String,
+    /// Folder to keep each job's query file, outputs, progress and log in, in a folder named
+    /// after the job.
+    #[clap(long = "jobs-dir", short = 'o')]
+    jobs_dir: PathBuf,
+    /// How many jobs to run at once. The rest are queued until there's room.
+    #[clap(long = "max-jobs", default_value_t = 1)]
+    max_jobs: usize,
+    /// Options given to each job's search, after a `--`, e.g. `-- --threads 8 --glob
+    /// '**/*.jsonl.zst'`.
+    #[clap(last = true)]
+    search_args: Vec<OsString>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Queued,
+    Running,
+    Finished,
+    /// Stopped by a `DELETE`, with its progress saved if it had started.
+    Stopped,
+    Failed,
+}
+
+impl State {
+    fn name(self) -> &'static str {
+        match self {
+            State::Queued => "queued",
+            State::Running => "running",
+            State::Finished => "finished",
+            State::Stopped => "stopped",
+            State::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Job {
+    id: u64,
+    /// The queries' output files, which are the only files of the job that can be fetched.
+    filenames: Vec<String>,
+    state: State,
+    stop: bool,
+    submitted: SystemTime,
+    started: Option<SystemTime>,
+    ended: Option<SystemTime>,
+    exit_code: Option<i32>,
+}
+
+/// What's shared between the threads handling requests and those running the jobs.
+struct Jobs {
+    args: ServeArgs,
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    queue: Mutex<VecDeque<u64>>,
+    queued: Condvar,
+}
+
+impl Jobs {
+    fn dir(&self, id: u64) -> PathBuf {
+        self.args.jobs_dir.join(id.to_string())
+    }
+
+    /// The job as it's described in responses.
+    fn describe(&self, job: &Job) -> Value {
+        let time = |time: Option<SystemTime>| {
+            time.map(|time| humantime::format_rfc3339_seconds(time).to_string())
+        };
+        let dir = self.dir(job.id);
+        // Written by the search as it goes, and one last time at the end.
+        let progress = std::fs::read(dir.join("progress.json"))
+            .ok()
+            .and_then(|progress| serde_json::from_slice::<Value>(&progress).ok());
+        let results: Vec<Value> = job
+            .filenames
+            .iter()
+            .map(|filename| {
+                let files = result_files(&dir.join("output"), filename);
+                // Nothing's been written for it yet.
+                let size = (!files.is_empty()).then(|| files.iter().map(|f| f.size).sum::<u64>());
+                let files: Vec<Value> = files
+                    .iter()
+                    .map(|file| {
+                        json!({
+                            "filename": file.name,
+                            "url": format!("/jobs/{}/results/{}", job.id, file.name),
+                            "size": file.size,
+                        })
+                    })
+                    .collect();
+                json!({
+                    "filename": filename,
+                    "url": format!("/jobs/{}/results/{filename}", job.id),
+                    "size": size,
+                    "files": files,
+                })
+            })
+            .collect();
+        json!({
+            "id": job.id,
+            "state": job.state.name(),
+            "submitted": time(Some(job.submitted)),
+            "started": time(job.started),
+            "ended": time(job.ended),
+            "exit_code": job.exit_code,
+            "progress": progress,
+            "results": results,
+        })
+    }
+
+    /// Queues a search for the query set.
+    fn submit(&self, query_file: &str) -> Result<Value> {
+        let queries = parse_queries(query_file)?;
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.keys().next_back().map_or(1, |id| id + 1);
+        let id = (id..)
+            .find(|id| !self.dir(*id).exists())
+            .expect("there's always a free ID");
+        let dir = self.dir(id);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| anyhow!("Error creating job folder {}", dir.display()))?;
+        std::fs::write(dir.join("queries.json"), query_file)
+            .with_context(|| anyhow!("Error writing query file for job {id}"))?;
+
+        let job = Job {
+            id,
+            filenames: queries.into_iter().map(|query| query.filename).collect(),
+            state: State::Queued,
+            stop: false,
+            submitted: SystemTime::now(),
+            started: None,
+            ended: None,
+            exit_code: None,
+        };
+        let description = self.describe(&job);
+        jobs.insert(id, job);
+        self.queue.lock().unwrap().push_back(id);
+        self.queued.notify_one();
+        status!("Queued job {id}");
+        Ok(description)
+    }
+
+    /// Stops the job: taking it out of the queue if it hasn't started, or interrupting its
+    /// search so that it stops with its progress saved.
+    fn stop(&self, id: u64) -> Option<Value> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(&id)?;
+        match job.state {
+            State::Queued => {
+                self.queue.lock().unwrap().retain(|queued| *queued != id);
+                job.state = State::Stopped;
+                job.ended = Some(SystemTime::now());
+            }
+            State::Running => job.stop = true,
+            State::Finished | State::Stopped | State::Failed => {}
+        }
+        Some(self.describe(job))
+    }
+
+    /// Runs the queued jobs one after another, until the server stops.
+    fn run_queue(&self) {
+        loop {
+            let id = {
+                let queue = self.queue.lock().unwrap();
+                let mut queue = self
+                    .queued
+                    .wait_while(queue, |queue| queue.is_empty())
+                    .unwrap();
+                queue
+                    .pop_front()
+                    .expect("waited for the queue to have a job")
+            };
+            {
+                let mut jobs = self.jobs.lock().unwrap();
+                let job = jobs.get_mut(&id).expect("queued jobs are kept");
+                job.state = State::Running;
+                job.started = Some(SystemTime::now());
+            }
+            status!("Starting job {id}");
+
+            let (state, exit_code) = match self.run(id) {
+                Ok(Some(0)) => (State::Finished, Some(0)),
+                Ok(code) if self.jobs.lock().unwrap()[&id].stop => (State::Stopped, code),
+                Ok(code) => (State::Failed, code),
+                Err(e) => {
+                    eprintln!("Error running job {id}: {e:#}");
+                    (State::Failed, None)
+                }
+            };
+            let mut jobs = self.jobs.lock().unwrap();
+            let job = jobs.get_mut(&id).expect("queued jobs are kept");
+            job.state = state;
+            job.exit_code = exit_code;
+            job.ended = Some(SystemTime::now());
+            status!("Job {id} {}", state.name());
+        }
+    }
+
+    /// Runs the job's search in its own process, so that jobs can't get in each other's way,
+    /// returning its exit code.
+    fn run(&self, id: u64) -> Result<Option<i32>> {
+        let dir = self.dir(id);
+        let log = File::create(dir.join("log.txt"))?;
+        let mut child = Command::new(std::env::current_exe()?)
+            .arg("search")
+            .arg("--query-json")
+            .arg(dir.join("queries.json"))
+            .arg("--input-folder")
+            .arg(&self.args.files_folder)
+            .arg("--output-dir")
+            .arg(dir.join("output"))
+            .arg("--search-management-file")
+            .arg(dir.join("management.json"))
+            .arg("--progress-file")
+            .arg(dir.join("progress.json"))
+            .arg("--no-progress")
+            .args(&self.args.search_args)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .spawn()
+            .with_context(|| anyhow!("Error starting search"))?;
+
+        let mut stopping = false;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status.code());
+            }
+            if !stopping && self.jobs.lock().unwrap()[&id].stop {
+                stopping = true;
+                interrupt(&mut child)?;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Asks the search to stop as Ctrl-C would, so that it saves its progress.
+#[cfg(unix)]
+fn interrupt(child: &mut Child) -> Result<()> {
+    // SAFETY: Sending a signal to our own child process, which hasn't been waited on yet.
+    if unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn interrupt(child: &mut Child) -> Result<()> {
+    Ok(child.kill()?)
+}
+
+/// One of the files a query's output has been written to.
+struct ResultFile {
+    /// Its name in the output folder once it's finished, which it's fetched by.
+    name: String,
+    /// Where it is for now, which is the `.partial` file until it's finished.
+    path: PathBuf,
+    size: u64,
+}
+
+/// The files written for a query's output so far, going by the same naming as the search's
+/// output writer: the output may have been compressed, and split into numbered parts.
+fn result_files(output_dir: &Path, filename: &str) -> Vec<ResultFile> {
+    output_parts(&output_dir.join(filename))
+        .into_iter()
+        .filter_map(|path| {
+            let size = std::fs::metadata(&path).ok()?.len();
+            let name = path.strip_prefix(output_dir).ok()?.to_str()?;
+            let name = name.strip_suffix(".partial").unwrap_or(name);
+            Some(ResultFile {
+                name: name.to_owned(),
+                path,
+                size,
+            })
+        })
+        .collect()
+}
+
+/// Opens the files to be streamed one after another. Each is only read as far as it had got
+/// when listed, so the response matches its length even if the search is still writing.
+fn open_result(files: &[ResultFile]) -> std::io::Result<Box<dyn Read + Send>> {
+    let mut reader: Box<dyn Read + Send> = Box::new(std::io::empty());
+    for file in files {
+        // The search may have finished the file since it was listed.
+        let finished = file
+            .path
+            .with_file_name(Path::new(&file.name).file_name().unwrap());
+        let opened = File::open(&file.path).or_else(|_| File::open(finished))?;
+        reader = Box::new(reader.chain(opened.take(file.size)));
+    }
+    Ok(reader)
+}
+
+fn json_response(status: u16, body: &Value) -> Response<std::io::Cursor<Vec<u8>>> {
+    let header = Header::from_bytes("Content-Type", "application/json").expect("valid header");
+    Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(header)
+}
+
+fn error_response(status: u16, error: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    json_response(status, &json!({ "error": error }))
+}
+
+fn handle(jobs: &Jobs, mut request: Request) -> std::io::Result<()> {
+    let url = request.url().to_owned();
+    let path: Vec<&str> = url
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect();
+    let id = path.get(1).and_then(|id| id.parse::<u64>().ok());
+
+    let response = match (request.method(), path.as_slice(), id) {
+        (Method::Get, [] | ["jobs"], _) => {
+            let jobs_list = jobs.jobs.lock().unwrap();
+            let list: Vec<Value> = jobs_list.values().map(|job| jobs.describe(job)).collect();
+            json_response(200, &Value::from(list))
+        }
+        (Method::Post, ["jobs"], _) => {
+            let mut body = String::new();
+            if let Err(e) = request.as_reader().take(MAX_BODY).read_to_string(&mut body) {
+                return request.respond(error_response(400, &e.to_string()));
+            }
+            match jobs.submit(&body) {
+                Ok(job) => json_response(201, &job),
+                Err(e) => error_response(400, &format!("{e:#}")),
+            }
+        }
+        (Method::Get, ["jobs", _], Some(id)) => match jobs.jobs.lock().unwrap().get(&id) {
+            Some(job) => json_response(200, &jobs.describe(job)),
+            None => error_response(404, "No such job"),
+        },
+        (Method::Delete, ["jobs", _], Some(id)) => match jobs.stop(id) {
+            Some(job) => json_response(200, &job),
+            None => error_response(404, "No such job"),
+        },
+        (Method::Get, ["jobs", _, "results", filename @ ..], Some(id)) => {
+            let filename = filename.join("/");
+            let filenames = jobs
+                .jobs
+                .lock()
+                .unwrap()
+                .get(&id)
+                .map(|job| job.filenames.clone())
+                .unwrap_or_default();
+            // Only the queries' outputs can be fetched, not whatever else is in the folder.
+            // That's the whole of a query's output, or one of the files it was split into.
+            let output_dir = jobs.dir(id).join("output");
+            let files = match filenames.contains(&filename) {
+                true => result_files(&output_dir, &filename),
+                false => filenames
+                    .iter()
+                    .flat_map(|name| result_files(&output_dir, name))
+                    .filter(|file| file.name == filename)
+                    .collect(),
+            };
+            let size = files.iter().map(|file| file.size).sum::<u64>();
+            match open_result(&files) {
+                _ if files.is_empty() => error_response(404, "No such result"),
+                // Streamed from the files, rather than read into memory first.
+                Ok(reader) => {
+                    let response =
+                        Response::new(200.into(), Vec::new(), reader, Some(size as usize), None);
+                    return request.respond(response);
+                }
+                Err(e) => error_response(500, &e.to_string()),
+            }
+        }
+        _ => error_response(404, "Not found"),
+    };
+    request.respond(response)
+}
+
+/// Serves the HTTP API, which queues up searches of the input folder for the query sets
+/// submitted to it, so that several people can share one machine without getting in each
+/// other's way:
+///
+/// - `POST /jobs` with a query file as the body queues a search for it.
+/// - `GET /jobs` lists the jobs, and `GET /jobs/<id>` describes one, with its progress.
+/// - `GET /jobs/<id>/results/<filename>` fetches one of its query's outputs, as far as it's
+///   got. An output split into numbered parts is fetched as the parts one after another, and
+///   each part can be fetched on its own by the name it's listed under in the job's `files`.
+/// - `DELETE /jobs/<id>` stops it.
+pub fn serve(args: ServeArgs) -> Result<()> {
+    std::fs::create_dir_all(&args.jobs_dir)
+        .with_context(|| anyhow!("Error creating {}", args.jobs_dir.display()))?;
+    let server = Server::http(&args.listen)
+        .map_err(|e| anyhow!("Error listening on {}: {e}", args.listen))?;
+    status!("Listening on {}", args.listen);
+
+    let runners = args.max_jobs.max(1);
+    let jobs = Arc::new(Jobs {
+        args,
+        jobs: Mutex::new(BTreeMap::new()),
+        queue: Mutex::new(VecDeque::new()),
+        queued: Condvar::new(),
+    });
+    for _ in 0..runners {
+        let jobs = jobs.clone();
+        std::thread::spawn(move || jobs.run_queue());
+    }
+
+    for request in server.incoming_requests() {
+        let jobs = jobs.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle(&jobs, request) {
+                eprintln!("Error responding to request: {e}");
+            }
+        });
+    }
+    Ok(())
+}
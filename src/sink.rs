@@ -0,0 +1,156 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{
+    output::OutputFile,
+    writer::{MatchWriter, Records, WriterShared},
+};
+
+/// Somewhere the queries' matches go. The searching threads hand their matches over in
+/// batches as they go, so a sink is shared between them.
+pub trait Sink: Send + Sync {
+    /// Whether the sink needs the line each match was rendered from, as well as the record.
+    /// They're only kept if some sink does.
+    fn wants_lines(&self) -> bool {
+        false
+    }
+
+    /// Writes one of a query's matches, by the query's index. `record` is the match as
+    /// rendered in the `--output-format` or the query's template, with its line ending, and
+    /// `line` is the JSON it was rendered from, after the query's `output_fields` are
+    /// applied, if the sink wants lines.
+    fn write_match(&self, query: usize, record: &[u8], line: Option<&[u8]>) -> Result<(), String>;
+
+    /// Writes a batch of a query's matches, with their lines if the sink wants them. Sinks
+    /// which can do better than a match at a time override this.
+    fn write_matches(
+        &self,
+        query: usize,
+        records: &Records,
+        lines: &[&[u8]],
+    ) -> Result<(), String> {
+        for (i, record) in records.iter().enumerate() {
+            self.write_match(query, record, lines.get(i).copied())?;
+        }
+        Ok(())
+    }
+
+    /// Makes sure everything written so far has made it out, as progress is about to be
+    /// recorded on the basis that it has.
+    fn flush(&self) -> Result<(), String>;
+
+    /// Finishes the output once everything has been written to it.
+    fn finalize(self: Box<Self>) -> Result<(), String>;
+
+    /// Stops writing when the run was stopped before everything was searched, leaving the
+    /// output as it is to be carried on with by the next run.
+    fn close(self: Box<Self>) -> Result<(), String> {
+        self.flush()
+    }
+}
+
+/// An output file for each query, each written on its own thread.
+pub struct QueryFiles {
+    writers: Vec<MatchWriter>,
+    shared: Arc<WriterShared>,
+    /// Whether anything has been written to each file.
+    written: Vec<AtomicBool>,
+    /// Delete the files which nothing was written to when finishing, rather than leaving
+    /// them empty.
+    remove_empty: bool,
+}
+
+impl QueryFiles {
+    pub fn new(files: Vec<OutputFile>, shared: Arc<WriterShared>, remove_empty: bool) -> Self {
+        Self {
+            written: files.iter().map(|_| AtomicBool::new(false)).collect(),
+            writers: files
+                .into_iter()
+                .map(|file| MatchWriter::spawn(file, shared.clone()))
+                .collect(),
+            shared,
+            remove_empty,
+        }
+    }
+}
+
+/// Combines the errors from each of the files into one.
+fn collect_errors(errors: Vec<String>) -> Result<(), String> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+impl Sink for QueryFiles {
+    fn write_match(&self, query: usize, record: &[u8], _line: Option<&[u8]>) -> Result<(), String> {
+        let mut batch = self.shared.buffers.take();
+        batch.push(record);
+        self.written[query].store(true, Ordering::Relaxed);
+        self.writers[query].write(batch)
+    }
+
+    fn write_matches(
+        &self,
+        query: usize,
+        records: &Records,
+        _lines: &[&[u8]],
+    ) -> Result<(), String> {
+        // The writer hands the batch back to the pool once it's written, so the searching
+        // thread can carry on with its own.
+        let mut batch = self.shared.buffers.take();
+        batch.extend(records);
+        self.written[query].store(true, Ordering::Relaxed);
+        self.writers[query].write(batch)
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        let errors = self
+            .writers
+            .iter()
+            .filter_map(|w| w.flush().err())
+            .collect();
+        collect_errors(errors)
+    }
+
+    fn close(self: Box<Self>) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for writer in self.writers {
+            match writer.close() {
+                Ok(file) => {
+                    let path = file.path();
+                    if let Err(e) = file.close() {
+                        errors.push(format!("Error writing to {}: {e}", path.display()));
+                    }
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        collect_errors(errors)
+    }
+
+    fn finalize(self: Box<Self>) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for (writer, written) in self.writers.into_iter().zip(&self.written) {
+            let file = match writer.close() {
+                Ok(file) => file,
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            };
+            let path = file.path();
+            // Don't leave behind a pile of empty files for queries with no matches.
+            if self.remove_empty && !written.load(Ordering::Relaxed) {
+                drop(file);
+                let _ = std::fs::remove_file(path);
+            } else if let Err(e) = file.finish() {
+                errors.push(format!("Error writing to {}: {e}", path.display()));
+            }
+        }
+        collect_errors(errors)
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+
+use crate::{
+    output::{create_output_as, open_lines, partial_path, read_record, OutputWriter},
+    parse_size,
+    record::RecordView,
+};
+
+/// The most runs merged at once. Any more are merged in several passes, to avoid running out
+/// of file handles.
+const MAX_MERGE_WIDTH: usize = 256;
+
+/// Rough per-line overhead of a buffered line, on top of its text.
+const LINE_OVERHEAD: usize = 64;
+
+#[derive(Debug, clap::Args)]
+pub struct SortArgs {
+    /// The output file to sort. Files ending in `.zst` or `.gz` are decompressed.
+    file: PathBuf,
+    /// Field to sort the records by, e.g. `upload_date`, `view_count` or `id`. Nested fields
+    /// are given as a dotted path, such as `record.upload_date` for `jsonl` output.
+    #[clap(long = "by", short = 'b')]
+    by: String,
+    /// Where to write the sorted file. Defaults to replacing the input file.
+    #[clap(long = "output", short = 'o')]
+    output: Option<PathBuf>,
+    /// Sort in descending order.
+    #[clap(long = "reverse", short = 'r')]
+    reverse: bool,
+    /// How much of the file to sort in memory at once, e.g. `512M`. Larger files are sorted
+    /// in runs written to temporary files, which are then merged.
+    #[clap(long = "memory", default_value = "1G", value_parser = parse_size)]
+    memory: u64,
+    /// Folder for the temporary run files. Defaults to the output file's folder.
+    #[clap(long = "temp-dir")]
+    temp_dir: Option<PathBuf>,
+}
+
+/// The value a record is sorted by.
+///
+/// Numbers sort before strings, and records missing the field (or which aren't JSON) sort
+/// last (whichever direction is being sorted in), so that the order is total even for mixed
+/// data.
+#[derive(Debug)]
+enum SortKey {
+    Number(f64),
+    Text(String),
+    Missing,
+}
+
+impl SortKey {
+    fn of(line: &str, field: &str) -> Self {
+        match RecordView::new(line).field(field) {
+            Some(Value::Number(n)) => n.as_f64().map_or(SortKey::Missing, SortKey::Number),
+            Some(Value::String(s)) => SortKey::Text(s),
+            None | Some(Value::Null) => SortKey::Missing,
+            Some(value) => SortKey::Text(value.to_string()),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            SortKey::Number(_) => 0,
+            SortKey::Text(_) => 1,
+            SortKey::Missing => 2,
+        }
+    }
+
+    fn compare(&self, other: &Self, reverse: bool) -> Ordering {
+        let ordering = match (self, other) {
+            (SortKey::Number(a), SortKey::Number(b)) => a.total_cmp(b),
+            (SortKey::Text(a), SortKey::Text(b)) => a.cmp(b),
+            _ => return self.rank().cmp(&other.rank()),
+        };
+        if reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+/// A temporary file holding a sorted run of records, deleted once it's been merged.
+struct Run {
+    path: PathBuf,
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+struct RunNamer {
+    dir: PathBuf,
+    prefix: String,
+    next: usize,
+}
+
+impl RunNamer {
+    fn create(&mut self) -> Result<(Run, BufWriter<File>)> {
+        let path = self
+            .dir
+            .join(format!("{}.run{:04}", self.prefix, self.next));
+        self.next += 1;
+        let file = File::create(&path)
+            .with_context(|| anyhow!("Error creating temporary file {}", path.display()))?;
+        Ok((Run { path }, BufWriter::new(file)))
+    }
+}
+
+/// The next record from one of the runs being merged.
+///
+/// Ties are broken by the run index, so that records with equal keys keep their order in the
+/// original file.
+struct HeapEntry {
+    key: SortKey,
+    run: usize,
+    line: String,
+    reverse: bool,
+}
+
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // `BinaryHeap` is a max-heap, so this is flipped to pop the smallest entry first.
+        self.key
+            .compare(&other.key, self.reverse)
+            .then(self.run.cmp(&other.run))
+            .reverse()
+    }
+}
+
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapEntry {}
+
+fn merge_runs(runs: &[Run], args: &SortArgs, output: &mut impl Write) -> Result<()> {
+    let mut readers = runs
+        .iter()
+        .map(|run| Ok(BufReader::new(File::open(&run.path)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut heap = BinaryHeap::with_capacity(readers.len());
+    for (run, reader) in readers.iter_mut().enumerate() {
+        let mut line = String::new();
+        if read_record(reader, &mut line)? {
+            heap.push(HeapEntry {
+                key: SortKey::of(&line, &args.by),
+                run,
+                line,
+                reverse: args.reverse,
+            });
+        }
+    }
+
+    while let Some(mut entry) = heap.pop() {
+        output.write_all(entry.line.as_bytes())?;
+        if read_record(&mut readers[entry.run], &mut entry.line)? {
+            entry.key = SortKey::of(&entry.line, &args.by);
+            heap.push(entry);
+        }
+    }
+
+    Ok(())
+}
+
+fn write_sorted(
+    records: &mut Vec<(SortKey, String)>,
+    reverse: bool,
+    output: &mut impl Write,
+) -> Result<()> {
+    // A stable sort, so records with equal keys stay in file order.
+    records.sort_by(|(a, _), (b, _)| a.compare(b, reverse));
+    for (_, line) in records.drain(..) {
+        output.write_all(line.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Writes the output to `<output>.partial`, which only replaces the output once it's been
+/// written in full. That way a failure part way through sorting a file in place leaves the
+/// original as it was.
+fn write_output(path: &Path, write: impl FnOnce(&mut OutputWriter) -> Result<()>) -> Result<()> {
+    let partial = partial_path(path);
+    let mut output = create_output_as(&partial, path)?;
+    let result = write(&mut output).and_then(|_| Ok(output.flush()?));
+    drop(output);
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+    std::fs::rename(&partial, path).with_context(|| {
+        anyhow!(
+            "Error moving the sorted output into place as {}",
+            path.display()
+        )
+    })
+}
+
+/// Sorts the records of an output file by a field, using an external merge sort so that
+/// files larger than memory can be sorted.
+pub fn sort_output(args: &SortArgs) -> Result<()> {
+    let output_path = args.output.as_ref().unwrap_or(&args.file);
+    let mut reader =
+        open_lines(&args.file).with_context(|| anyhow!("Error opening {}", args.file.display()))?;
+
+    let temp_dir = match &args.temp_dir {
+        Some(dir) => dir.clone(),
+        None => match output_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        },
+    };
+    let file_name = output_path
+        .file_name()
+        .ok_or_else(|| anyhow!("Output path must be a file"))?;
+    let mut namer = RunNamer {
+        dir: temp_dir,
+        prefix: format!(".{}.{}", file_name.to_string_lossy(), std::process::id()),
+        next: 0,
+    };
+
+    let mut records = Vec::new();
+    let mut buffered = 0;
+    let mut runs = Vec::new();
+    let mut total = 0u64;
+    let mut line = String::new();
+    while read_record(&mut reader, &mut line)? {
+        total += 1;
+        buffered += line.len() + LINE_OVERHEAD;
+        records.push((SortKey::of(&line, &args.by), std::mem::take(&mut line)));
+
+        if buffered as u64 >= args.memory {
+            let (run, mut writer) = namer.create()?;
+            write_sorted(&mut records, args.reverse, &mut writer)?;
+            writer.flush()?;
+            runs.push(run);
+            buffered = 0;
+        }
+    }
+    drop(reader);
+
+    if runs.is_empty() {
+        write_output(output_path, |output| {
+            write_sorted(&mut records, args.reverse, output)
+        })?;
+    } else {
+        if !records.is_empty() {
+            let (run, mut writer) = namer.create()?;
+            write_sorted(&mut records, args.reverse, &mut writer)?;
+            writer.flush()?;
+            runs.push(run);
+        }
+
+        // Merging consecutive runs keeps equal keys in file order across passes.
+        while runs.len() > MAX_MERGE_WIDTH {
+            let mut merged = Vec::new();
+            for group in runs.chunks(MAX_MERGE_WIDTH) {
+                let (run, mut writer) = namer.create()?;
+                merge_runs(group, args, &mut writer)?;
+                writer.flush()?;
+                merged.push(run);
+            }
+            runs = merged;
+        }
+
+        write_output(output_path, |output| merge_runs(&runs, args, output))?;
+    }
+
+    println!(
+        "Sorted {total} records from {} into {}",
+        args.file.display(),
+        output_path.display()
+    );
+    Ok(())
+}
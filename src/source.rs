@@ -0,0 +1,116 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use zstd::Decoder;
+
+use crate::decode::{DecodeOptions, ExplainWindowErrors, RecoveringDecoder, ZSTD_MAGIC};
+
+/// Somewhere to read a stream of lines from, which might need decompressing on the way.
+///
+/// Opening the stream as it's stored and decoding it are kept apart, so that how far
+/// through the stored stream the search has got can be followed while its lines are read.
+/// Each way of storing the inputs, or compressing them, is a source.
+pub trait Source: fmt::Display + Send + Sync {
+    /// Opens the stream as it's stored, still compressed.
+    fn open(&self) -> Result<Box<dyn Read + Send>>;
+
+    /// Wraps the stream opened with [`Source::open`] in a reader of its lines. By default,
+    /// it's decompressed if it starts with a zstd frame, and read as it is otherwise.
+    fn decode(
+        &self,
+        raw: Box<dyn Read + Send>,
+        options: &DecodeOptions,
+    ) -> Result<Box<dyn BufRead + Send>> {
+        decode_zstd(raw, &self.to_string(), options)
+    }
+
+    /// Opens the stream and decodes its lines.
+    fn lines(&self, options: &DecodeOptions) -> Result<Box<dyn BufRead + Send>> {
+        self.decode(self.open()?, options)
+    }
+}
+
+/// Decompresses the stream, if it starts with a zstd frame. `name` identifies it in the
+/// messages about frames skipped with `skip_corrupt_frames`.
+pub fn decode_zstd(
+    raw: Box<dyn Read + Send>,
+    name: &str,
+    options: &DecodeOptions,
+) -> Result<Box<dyn BufRead + Send>> {
+    let mut reader = BufReader::new(raw);
+    if !reader.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+        return Ok(Box::new(reader));
+    }
+
+    if options.skip_corrupt_frames {
+        let decoder = RecoveringDecoder::new(reader, name.to_owned(), options)?;
+        Ok(Box::new(BufReader::new(decoder)))
+    } else {
+        let mut decoder = match &options.dictionary {
+            Some(dictionary) => Decoder::with_dictionary(reader, dictionary)?,
+            None => Decoder::with_buffer(reader)?,
+        };
+        if let Some(log) = options.window_log_max {
+            decoder.window_log_max(log)?;
+        }
+        Ok(Box::new(BufReader::new(ExplainWindowErrors(decoder))))
+    }
+}
+
+/// A local file, compressed with zstd or not compressed at all.
+#[derive(Debug, Clone)]
+pub struct ZstdFile {
+    pub path: PathBuf,
+}
+
+impl ZstdFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl fmt::Display for ZstdFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.path.display().fmt(f)
+    }
+}
+
+impl Source for ZstdFile {
+    fn open(&self) -> Result<Box<dyn Read + Send>> {
+        Ok(Box::new(File::open(&self.path)?))
+    }
+}
+
+/// Lines held in memory, compressed with zstd or not, under a name for the messages about
+/// them. Handy for searching what's already been read, or for fixtures.
+#[derive(Debug, Clone)]
+pub struct InMemory {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl InMemory {
+    pub fn new(name: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            name: name.into(),
+            data: data.into(),
+        }
+    }
+}
+
+impl fmt::Display for InMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.name.fmt(f)
+    }
+}
+
+impl Source for InMemory {
+    fn open(&self) -> Result<Box<dyn Read + Send>> {
+        Ok(Box::new(std::io::Cursor::new(self.data.clone())))
+    }
+}
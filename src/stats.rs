@@ -0,0 +1,95 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::management::Management;
+
+#[derive(Debug, clap::Args)]
+pub struct StatsArgs {
+    #[clap(long = "search-management-file", short = 'm')]
+    management_file: PathBuf,
+    /// How many of the files with the most matches to list.
+    #[clap(long = "top", default_value_t = 10)]
+    top: usize,
+    /// Rank the files by the matches for this query, given by its filename, instead of the
+    /// matches for all of them.
+    #[clap(long = "query")]
+    query: Option<String>,
+}
+
+/// Prints the totals for the completed files, and which of them had the most matches.
+pub fn stats(args: &StatsArgs) -> Result<()> {
+    if !args.management_file.exists() {
+        bail!(
+            "Management file {} doesn't exist",
+            args.management_file.display()
+        );
+    }
+    // Read without taking the lock, so that a running search can be looked in on. Loading
+    // doesn't write anything, and picks up the run's journal as it stands.
+    let management = Management::load(&args.management_file, None)?;
+
+    let stats = &management.c_stats;
+    let size: u64 = stats.values().filter_map(|s| s.size).sum();
+    let secs: f64 = stats.values().map(|s| s.secs).sum();
+    println!(
+        "{} completed files, {} lines, {:.1} GB",
+        management.c_files.len(),
+        management.c_lines,
+        size as f64 / 1e9
+    );
+    if stats.len() < management.c_files.len() {
+        println!(
+            "  {} of them were completed by older versions, without recording their statistics",
+            management.c_files.len() - stats.len()
+        );
+    }
+    let lines: u64 = stats.values().map(|s| s.lines).sum();
+    if secs > 0.0 {
+        println!(
+            "{secs:.0} seconds spent searching them, {:.0} lines/sec on average",
+            lines as f64 / secs
+        );
+    }
+    println!(
+        "{} partially searched files, {} failed files",
+        management.partial.len(),
+        management.failed_files.len()
+    );
+
+    let mut totals: BTreeMap<&str, u64> = BTreeMap::new();
+    for file in stats.values() {
+        for (query, matches) in &file.matches {
+            *totals.entry(query).or_default() += matches;
+        }
+    }
+    println!("Matches per query:");
+    for (query, matches) in &totals {
+        println!("  {query}: {matches}");
+    }
+    if let Some(query) = &args.query {
+        if !totals.contains_key(query.as_str()) {
+            bail!("No matches were recorded for the query `{query}`");
+        }
+    }
+
+    let matches = |file: &&PathBuf| {
+        let matches = &stats[*file].matches;
+        match &args.query {
+            Some(query) => matches.get(query).copied().unwrap_or(0),
+            None => matches.values().sum(),
+        }
+    };
+    let mut ranked: Vec<_> = stats.keys().collect();
+    ranked.sort_by_key(|file| std::cmp::Reverse(matches(file)));
+    println!("Files with the most matches:");
+    for file in ranked.into_iter().take(args.top) {
+        let lines = stats[file].lines;
+        println!(
+            "  {}: {} matches in {lines} lines",
+            file.display(),
+            matches(&file)
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use aho_corasick::AhoCorasick;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::record::RecordView;
+
+/// How a query with `match_tags` matches its expressions against each of the record's
+/// `tags`, instead of anywhere in the line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagMatch {
+    /// A tag matches if it's one of the expressions, ignoring ASCII case.
+    Exact,
+    /// A tag matches if it contains one of the expressions, ignoring ASCII case.
+    Substring,
+}
+
+impl TagMatch {
+    /// Whether any of the record's tags match the query's expressions, which `searcher` was
+    /// built from.
+    pub fn matches(self, record: &RecordView, searcher: &AhoCorasick) -> bool {
+        let Some(Value::Array(tags)) = record.field("tags") else {
+            return false;
+        };
+        let mut tags = tags.iter().filter_map(Value::as_str);
+        match self {
+            TagMatch::Exact => tags.any(|tag| {
+                // Expressions can overlap, so the one covering the whole tag might not be the
+                // first one found.
+                searcher
+                    .find_overlapping_iter(tag)
+                    .any(|found| found.start() == 0 && found.end() == tag.len())
+            }),
+            TagMatch::Substring => tags.any(|tag| searcher.is_match(tag)),
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io::Write,
+    path::PathBuf,
+    sync::atomic::Ordering,
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+
+use crate::{
+    logging::STATUS_TO_STDERR,
+    output::{create_output, csv_escape, open_lines, read_record},
+    record::RecordView,
+    status,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct TopArgs {
+    /// The result files to count in, as written by the `raw` or `jsonl` output formats.
+    /// Files ending in `.zst` or `.gz` are decompressed.
+    #[clap(required = true)]
+    files: Vec<PathBuf>,
+    /// The field to count the values of, e.g. `uploader`, `channel_id` or `tags`. Each value
+    /// of a list is counted, once per record.
+    #[clap(long = "by", short = 'b')]
+    by: String,
+    /// How many of the most frequent values to list.
+    #[clap(long = "n", short = 'n', default_value_t = 10)]
+    n: usize,
+    /// How many distinct values to keep counts for. With more values than this, the least
+    /// frequent are dropped to make room, so the counts become estimates, each given with
+    /// how much it could be over by. Defaults to 100 times `--n`, and at least 10000.
+    #[clap(long = "capacity")]
+    capacity: Option<usize>,
+    /// Where to write the table, as CSV. Defaults to stdout.
+    #[clap(long = "output", short = 'o')]
+    output: Option<PathBuf>,
+}
+
+/// Counts the most frequent values in a bounded amount of memory, with the Space-Saving
+/// algorithm: once it's counting as many values as it has room for, a new value replaces
+/// the one with the lowest count, taking over its count as how much its own might be over.
+/// Any value more frequent than the lowest count is guaranteed to be kept.
+struct TopCounter {
+    capacity: usize,
+    /// The count for each value, and how much of it might belong to values it replaced.
+    counts: HashMap<String, (u64, u64)>,
+    /// The values ordered by their count, to find the lowest.
+    by_count: BTreeSet<(u64, String)>,
+    /// Whether any values have been dropped, making the counts estimates.
+    approximate: bool,
+}
+
+impl TopCounter {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            counts: HashMap::new(),
+            by_count: BTreeSet::new(),
+            approximate: false,
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        if let Some((count, _)) = self.counts.get_mut(value) {
+            self.by_count.remove(&(*count, value.to_owned()));
+            *count += 1;
+            self.by_count.insert((*count, value.to_owned()));
+            return;
+        }
+
+        let (count, error) = if self.counts.len() < self.capacity {
+            (1, 0)
+        } else {
+            let (lowest, replaced) = self.by_count.pop_first().expect("the counter is full");
+            self.counts.remove(&replaced);
+            self.approximate = true;
+            (lowest + 1, lowest)
+        };
+        self.counts.insert(value.to_owned(), (count, error));
+        self.by_count.insert((count, value.to_owned()));
+    }
+
+    /// The `n` values with the highest counts, with their counts and how much they might be
+    /// over by.
+    fn top(&self, n: usize) -> Vec<(&str, u64, u64)> {
+        let mut top: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(value, &(count, error))| (value.as_str(), count, error))
+            .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        top.truncate(n);
+        top
+    }
+}
+
+/// The values of the field to count for a record: each of a list's, once.
+fn values(record: &RecordView, field: &str) -> Vec<String> {
+    let text = |value: Value| match value {
+        Value::String(s) => s,
+        value => value.to_string(),
+    };
+    match record.field(field) {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Array(values)) => {
+            let mut seen = HashSet::new();
+            values
+                .into_iter()
+                .filter(|value| !value.is_null())
+                .map(text)
+                .filter(|value| seen.insert(value.clone()))
+                .collect()
+        }
+        Some(value) => vec![text(value)],
+    }
+}
+
+/// Lists the most frequent values of a field among the records in the result files.
+pub fn top(args: &TopArgs) -> Result<()> {
+    // The table can be written to stdout, so keep it clear of status messages.
+    STATUS_TO_STDERR.store(args.output.is_none(), Ordering::Relaxed);
+    let capacity = args
+        .capacity
+        .unwrap_or_else(|| args.n.saturating_mul(100).max(10_000))
+        .max(args.n);
+    let mut counter = TopCounter::new(capacity);
+    let mut records = 0;
+    let mut line = String::new();
+    for path in &args.files {
+        let mut reader =
+            open_lines(path).with_context(|| anyhow!("Error opening {}", path.display()))?;
+        while read_record(&mut reader, &mut line)
+            .with_context(|| anyhow!("Error reading {}", path.display()))?
+        {
+            let record = RecordView::new(line.trim_end());
+            let inner = record.inner_record();
+            for value in values(inner.as_ref().unwrap_or(&record), &args.by) {
+                counter.add(&value);
+            }
+            records += 1;
+        }
+    }
+
+    let top = counter.top(args.n);
+    if counter.approximate {
+        status!(
+            "There were more than {capacity} distinct values of `{}` among {records} records, \
+            so the counts are estimates",
+            args.by
+        );
+    }
+    let write = |out: &mut dyn Write| -> Result<()> {
+        writeln!(out, "{},count,max_overcount", csv_escape(&args.by))?;
+        for (value, count, error) in &top {
+            writeln!(out, "{},{count},{error}", csv_escape(value))?;
+        }
+        out.flush()?;
+        Ok(())
+    };
+    match &args.output {
+        Some(path) => write(&mut create_output(path)?)
+            .with_context(|| anyhow!("Error writing {}", path.display())),
+        None => write(&mut std::io::stdout().lock()),
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    io::{self, IsTerminal, Stderr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        cursor,
+        event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+        execute,
+        terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Cell, Gauge, List, Paragraph, Row, Sparkline, Table, TableState},
+    Frame, Terminal,
+};
+
+use crate::{
+    display::{self, Snapshot},
+    interrupt::{self, interrupted},
+    writer::Records,
+    Query,
+};
+
+/// How often the dashboard is redrawn, and checked for key presses.
+const TICK: Duration = Duration::from_millis(250);
+/// How many seconds of throughput are kept for the graph.
+const HISTORY: usize = 600;
+/// How many of the most recent matches and messages are kept.
+const RECENT: usize = 100;
+/// How much of each recent match is kept.
+const MATCH_WIDTH: usize = 400;
+
+type Backend = CrosstermBackend<Stderr>;
+
+/// The dashboard, once it's been started.
+static DASHBOARD: OnceLock<Dashboard> = OnceLock::new();
+
+/// A full screen dashboard on stderr, showing the files being searched, the matches for
+/// each query, the throughput over time and the most recent matches and messages. Files
+/// can be skipped and the whole search paused from it.
+///
+/// The progress itself is tracked by [`display`], with the bars hidden.
+pub struct Dashboard {
+    queries: Vec<String>,
+    /// The most recent matches, with the index of the query they matched.
+    recent: Mutex<VecDeque<(usize, String)>>,
+    messages: Mutex<VecDeque<String>>,
+    paused: AtomicBool,
+    /// The files which have been asked to stop, by name.
+    skips: Mutex<HashSet<String>>,
+    /// Set once the dashboard has been closed, so it's no longer shown.
+    closed: AtomicBool,
+}
+
+/// Keeps the dashboard up until it's dropped, which puts the terminal back how it was.
+pub struct Running {
+    thread: Option<JoinHandle<()>>,
+}
+
+/// What's shown, besides the progress, which only the drawing thread needs.
+struct View {
+    workers: TableState,
+    /// Lines searched in each second.
+    throughput: VecDeque<u64>,
+    last_lines: u64,
+    last_sample: Instant,
+}
+
+/// Starts showing the dashboard in place of the progress bars and status messages.
+pub fn start(queries: &[Query]) -> Result<Running> {
+    if !io::stderr().is_terminal() {
+        bail!("--tui needs stderr to be a terminal");
+    }
+    display::start_hidden();
+    let dashboard = DASHBOARD.get_or_init(|| Dashboard {
+        queries: queries.iter().map(|q| q.filename.clone()).collect(),
+        recent: Mutex::new(VecDeque::new()),
+        messages: Mutex::new(VecDeque::new()),
+        paused: AtomicBool::new(false),
+        skips: Mutex::new(HashSet::new()),
+        closed: AtomicBool::new(false),
+    });
+
+    terminal::enable_raw_mode()?;
+    let mut stderr = io::stderr();
+    if let Err(e) = execute!(stderr, EnterAlternateScreen, cursor::Hide) {
+        restore();
+        return Err(e.into());
+    }
+    let terminal = match Terminal::new(CrosstermBackend::new(stderr)) {
+        Ok(terminal) => terminal,
+        Err(e) => {
+            restore();
+            return Err(e.into());
+        }
+    };
+    let thread = std::thread::spawn(move || {
+        dashboard.run(terminal);
+        restore();
+    });
+    Ok(Running {
+        thread: Some(thread),
+    })
+}
+
+fn restore() {
+    let _ = execute!(io::stderr(), LeaveAlternateScreen, cursor::Show);
+    let _ = terminal::disable_raw_mode();
+}
+
+/// The dashboard, if it's being shown.
+pub fn get() -> Option<&'static Dashboard> {
+    DASHBOARD
+        .get()
+        .filter(|dashboard| !dashboard.closed.load(Ordering::Relaxed))
+}
+
+/// Holds up a searching thread while the search is paused.
+pub fn wait_while_paused() {
+    if let Some(dashboard) = get() {
+        while dashboard.paused.load(Ordering::Relaxed) && !interrupted() {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// Whether the file has been asked to stop from the dashboard.
+pub fn skip_requested(name: &str) -> bool {
+    get().is_some_and(|dashboard| dashboard.skips.lock().unwrap().contains(name))
+}
+
+/// Keeps the last of the matches about to be written for a query, to show.
+pub fn add_recent(query: usize, records: &Records) {
+    let Some(dashboard) = get() else {
+        return;
+    };
+    if let Some(record) = records.iter().last() {
+        let record = &record[..record.len().min(MATCH_WIDTH)];
+        let record = String::from_utf8_lossy(record).trim_end().to_string();
+        push_capped(
+            &mut dashboard.recent.lock().unwrap(),
+            (query, record),
+            RECENT,
+        );
+    }
+}
+
+/// Adds to the end of the queue, dropping from the front to keep it to `capacity`.
+fn push_capped<T>(items: &mut VecDeque<T>, item: T, capacity: usize) {
+    if items.len() == capacity {
+        items.pop_front();
+    }
+    items.push_back(item);
+}
+
+fn size(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1e6)
+}
+
+impl Dashboard {
+    /// Shows a status message.
+    pub fn message(&self, message: String) {
+        push_capped(&mut self.messages.lock().unwrap(), message, RECENT);
+    }
+
+    fn run(&self, mut terminal: Terminal<Backend>) {
+        let mut view = View {
+            workers: TableState::default().with_selected(Some(0)),
+            throughput: VecDeque::new(),
+            last_lines: 0,
+            last_sample: Instant::now(),
+        };
+        while !self.closed.load(Ordering::Relaxed) {
+            let snapshot = display::get().map(|display| display.snapshot());
+            if let Some(snapshot) = &snapshot {
+                view.sample(snapshot);
+                // Forget about files which have finished since they were skipped.
+                self.skips
+                    .lock()
+                    .unwrap()
+                    .retain(|name| snapshot.searching.iter().any(|file| file.name == *name));
+            }
+            if terminal
+                .draw(|frame| self.draw(frame, &mut view, snapshot.as_ref()))
+                .is_err()
+            {
+                return;
+            }
+
+            match event::poll(TICK) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(_) => return,
+            }
+            let Ok(Event::Key(key)) = event::read() else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            // Raw mode means Ctrl-C comes through as a key press rather than a signal.
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                self.stop();
+                continue;
+            }
+            let files = snapshot.as_ref().map_or(0, |s| s.searching.len());
+            match key.code {
+                KeyCode::Char('p') | KeyCode::Char(' ') => {
+                    let paused = !self.paused.load(Ordering::Relaxed);
+                    self.paused.store(paused, Ordering::Relaxed);
+                }
+                KeyCode::Char('s') => {
+                    let selected = view.workers.selected().unwrap_or(0);
+                    if let Some(file) = snapshot.and_then(|s| s.searching.into_iter().nth(selected))
+                    {
+                        self.message(format!(
+                            "Skipping {}, it will carry on from here next time",
+                            file.name
+                        ));
+                        self.skips.lock().unwrap().insert(file.name);
+                    }
+                }
+                KeyCode::Up | KeyCode::Char('k') => view.workers.select_previous(),
+                KeyCode::Down | KeyCode::Char('j') if files > 0 => {
+                    let selected = view.workers.selected().map_or(0, |i| i + 1);
+                    view.workers.select(Some(selected.min(files - 1)));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Stops the search as Ctrl-C would. Asking twice stops straight away.
+    fn stop(&self) {
+        if interrupted() {
+            restore();
+            std::process::exit(130);
+        }
+        interrupt::interrupt();
+        self.paused.store(false, Ordering::Relaxed);
+        self.message("Stopping once the files being searched have their progress recorded".into());
+    }
+
+    fn draw(&self, frame: &mut Frame, view: &mut View, snapshot: Option<&Snapshot>) {
+        let [header, middle, graph, recent, messages, footer] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(6),
+            Constraint::Length(6),
+            Constraint::Min(5),
+            Constraint::Length(7),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [workers, queries] =
+            Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)])
+                .areas(middle);
+
+        if let Some(snapshot) = snapshot {
+            self.draw_overall(frame, header, snapshot, view);
+            self.draw_workers(frame, workers, snapshot, view);
+            self.draw_queries(frame, queries, snapshot);
+        }
+        let width = graph.width.saturating_sub(2) as usize;
+        let skip = view.throughput.len().saturating_sub(width);
+        let data: Vec<u64> = view.throughput.iter().skip(skip).copied().collect();
+        frame.render_widget(
+            Sparkline::default()
+                .block(
+                    Block::bordered()
+                        .title(format!("Lines/sec: {}", data.last().copied().unwrap_or(0))),
+                )
+                .data(&data)
+                .style(Style::default().fg(Color::Cyan)),
+            graph,
+        );
+
+        let width = recent.width.saturating_sub(2) as usize;
+        let rows = recent.height.saturating_sub(2) as usize;
+        let matches = self.recent.lock().unwrap();
+        let items: Vec<Line> = matches
+            .iter()
+            .rev()
+            .take(rows)
+            .map(|(query, record)| {
+                let query = &self.queries[*query];
+                let record: String = record
+                    .chars()
+                    .map(|c| if c.is_control() { ' ' } else { c })
+                    .take(width.saturating_sub(query.len() + 2))
+                    .collect();
+                Line::from(vec![
+                    Span::from(query.as_str()).bold(),
+                    Span::from(": "),
+                    Span::from(record),
+                ])
+            })
+            .collect();
+        drop(matches);
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title("Recent matches")),
+            recent,
+        );
+
+        let rows = messages.height.saturating_sub(2) as usize;
+        let lines = self.messages.lock().unwrap();
+        let skip = lines.len().saturating_sub(rows);
+        let items: Vec<Line> = lines
+            .iter()
+            .skip(skip)
+            .map(|m| Line::from(m.as_str()))
+            .collect();
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title("Messages")),
+            messages,
+        );
+
+        frame.render_widget(
+            Paragraph::new(
+                "p pause/resume   s skip the selected file   ↑/↓ select a file   q stop",
+            )
+            .style(Style::default().add_modifier(Modifier::DIM)),
+            footer,
+        );
+    }
+
+    fn draw_overall(&self, frame: &mut Frame, area: Rect, snapshot: &Snapshot, view: &View) {
+        let ratio = match snapshot.total_bytes {
+            0 => 0.0,
+            total => (snapshot.done_bytes as f64 / total as f64).min(1.0),
+        };
+        let elapsed = snapshot.elapsed.as_secs_f64();
+        let eta = (ratio > 0.0).then(|| Duration::from_secs((elapsed / ratio - elapsed) as u64));
+        let mut title = format!(
+            "{}/{} files, {} of {}, elapsed {}, ETA {}",
+            snapshot.files_done,
+            snapshot.files,
+            size(snapshot.done_bytes),
+            size(snapshot.total_bytes),
+            humantime::format_duration(Duration::from_secs(elapsed as u64)),
+            eta.map_or("unknown".into(), |eta| humantime::format_duration(eta)
+                .to_string()),
+        );
+        if let Some(lines_per_sec) = view.throughput.back() {
+            title.push_str(&format!(", {lines_per_sec} lines/sec"));
+        }
+        let mut block = Block::bordered().title(title);
+        if interrupted() {
+            block = block.title(Line::from(" STOPPING ").red().bold().right_aligned());
+        } else if self.paused.load(Ordering::Relaxed) {
+            block = block.title(Line::from(" PAUSED ").yellow().bold().right_aligned());
+        }
+        frame.render_widget(
+            Gauge::default()
+                .block(block)
+                .gauge_style(Style::default().fg(Color::Green))
+                .ratio(ratio),
+            area,
+        );
+    }
+
+    fn draw_workers(&self, frame: &mut Frame, area: Rect, snapshot: &Snapshot, view: &mut View) {
+        let skips = self.skips.lock().unwrap();
+        let rows: Vec<Row> = snapshot
+            .searching
+            .iter()
+            .map(|file| {
+                let progress = match file.size {
+                    Some(size) if size > 0 => {
+                        format!("{:.0}%", file.read.min(size) as f64 * 100.0 / size as f64)
+                    }
+                    _ => String::new(),
+                };
+                let status = if skips.contains(&file.name) {
+                    "skipping"
+                } else {
+                    ""
+                };
+                Row::new(vec![
+                    Cell::from(file.name.as_str()),
+                    Cell::from(progress),
+                    Cell::from(size(file.read)),
+                    Cell::from(status),
+                ])
+            })
+            .collect();
+        drop(skips);
+        if let Some(selected) = view.workers.selected() {
+            if selected >= rows.len() {
+                view.workers.select(Some(rows.len().saturating_sub(1)));
+            }
+        }
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(1),
+                Constraint::Length(5),
+                Constraint::Length(11),
+                Constraint::Length(8),
+            ],
+        )
+        .header(Row::new(vec!["File", "Done", "Read", ""]).bold())
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered().title(format!("Searching {} files", snapshot.searching.len())));
+        frame.render_stateful_widget(table, area, &mut view.workers);
+    }
+
+    fn draw_queries(&self, frame: &mut Frame, area: Rect, snapshot: &Snapshot) {
+        let rows: Vec<Row> = self
+            .queries
+            .iter()
+            .enumerate()
+            .map(|(i, query)| {
+                let matches = snapshot.query_matches.get(i).copied().unwrap_or(0);
+                Row::new(vec![
+                    Cell::from(query.as_str()),
+                    Cell::from(matches.to_string()),
+                ])
+            })
+            .collect();
+        let table = Table::new(rows, [Constraint::Fill(1), Constraint::Length(12)])
+            .header(Row::new(vec!["Query", "Matches"]).bold())
+            .block(Block::bordered().title("Matches this run"));
+        frame.render_widget(table, area);
+    }
+}
+
+impl View {
+    /// Records the lines searched each second, for the graph.
+    fn sample(&mut self, snapshot: &Snapshot) {
+        if self.last_sample.elapsed() < Duration::from_secs(1) {
+            return;
+        }
+        let secs = self.last_sample.elapsed().as_secs_f64();
+        let lines = snapshot.lines.saturating_sub(self.last_lines);
+        push_capped(&mut self.throughput, (lines as f64 / secs) as u64, HISTORY);
+        self.last_lines = snapshot.lines;
+        self.last_sample = Instant::now();
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if let Some(dashboard) = DASHBOARD.get() {
+            dashboard.closed.store(true, Ordering::Relaxed);
+            dashboard.paused.store(false, Ordering::Relaxed);
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    io,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{bail, Result};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    decode::DecodeOptions, input::InputSelector, load_queries, source::Source, status,
+    tags::TagMatch, thread_pool, Query,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct ValidateArgs {
+    #[clap(long = "query-json", short = 'q')]
+    query_json: PathBuf,
+    /// Folder to look for input files in, as for searching.
+    #[clap(long = "input-folder", short = 'i', alias = "files-folder")]
+    files_folder: Option<String>,
+    /// Glob pattern, relative to the input folder, used to find input files.
+    #[clap(long = "glob", short = 'g', default_value = "**/*.zst")]
+    glob: String,
+    /// Glob pattern, relative to the input folder, of files to skip. Can be repeated.
+    #[clap(long = "exclude", short = 'x')]
+    exclude: Vec<String>,
+    /// Also decompress each input file all the way through, to check that none of them are
+    /// truncated or corrupt.
+    #[clap(long = "decode", requires = "files-folder")]
+    decode: bool,
+    /// How many files to decompress at once. Defaults to one for each CPU core.
+    #[clap(long = "threads", short = 'j')]
+    threads: Option<usize>,
+    /// The largest window the zstd decoder will accept, as for searching.
+    #[clap(long = "zstd-window-log", value_parser = clap::value_parser!(u32).range(10..=31))]
+    zstd_window_log: Option<u32>,
+}
+
+/// Describes the query, returning what's wrong with it, if anything.
+fn check_query(query: &Query) -> Option<&'static str> {
+    let mut description = format!(
+        "{}: {} expressions",
+        query.filename,
+        query.expressions.len()
+    );
+    if query.invert {
+        description.push_str(", inverted");
+    }
+    match query.match_tags {
+        Some(TagMatch::Exact) => description.push_str(", matching whole tags"),
+        Some(TagMatch::Substring) => description.push_str(", matching within tags"),
+        None => {}
+    }
+    if query.dedup {
+        description.push_str(", deduplicated by ID");
+    }
+    if let Some(channels) = &query.channels {
+        description.push_str(&format!(", limited to {} channels", channels.len()));
+    }
+    if let Some(categories) = &query.categories {
+        let mut names: Vec<_> = categories.names().collect();
+        names.sort_unstable();
+        description.push_str(&format!(", limited to categories {}", names.join(", ")));
+    }
+    status!("{description}");
+
+    if query.expressions.iter().any(String::is_empty) {
+        return Some("has an empty expression, which matches every line");
+    }
+    // Which is how every record from a channel list or category is written.
+    if query.expressions.is_empty() && query.invert && query.filters_records() {
+        return None;
+    }
+    if query.expressions.is_empty() {
+        return Some(match query.invert {
+            true => "has no expressions, so every line is written",
+            false => "has no expressions, so nothing can match",
+        });
+    }
+    None
+}
+
+/// Checks the query file and the input files, so that mistakes turn up before a long run
+/// rather than part way through it.
+pub fn validate(args: &ValidateArgs) -> Result<()> {
+    let (_, queries) = load_queries(&args.query_json)?;
+    let mut problems = 0;
+    for query in &queries {
+        if let Some(problem) = check_query(query) {
+            eprintln!("Query `{}` {problem}", query.filename);
+            problems += 1;
+        }
+    }
+
+    if let Some(files_folder) = &args.files_folder {
+        let selection = InputSelector::new(files_folder, &args.glob, &args.exclude)?.find()?;
+        let size: u64 = selection
+            .inputs
+            .iter()
+            .filter_map(|i| i.source_size())
+            .sum();
+        status!(
+            "Found {} input files, {:.1} GB, and excluded {}",
+            selection.inputs.len(),
+            size as f64 / 1e9,
+            selection.excluded.len()
+        );
+        if selection.inputs.is_empty() {
+            eprintln!(
+                "No files matching `{}` found in `{files_folder}`",
+                args.glob
+            );
+            problems += 1;
+        }
+
+        if args.decode {
+            let options = DecodeOptions {
+                window_log_max: args.zstd_window_log,
+                ..DecodeOptions::default()
+            };
+            let failed = AtomicUsize::new(0);
+            thread_pool(args.threads)?.install(|| {
+                selection.inputs.par_iter().for_each(|input| {
+                    let decoded = input
+                        .lines(&options)
+                        .and_then(|mut reader| Ok(io::copy(&mut reader, &mut io::sink())?));
+                    match decoded {
+                        Ok(bytes) => status!("{input}: {bytes} bytes decompressed"),
+                        Err(e) => {
+                            eprintln!("Error reading {input}: {e:#}");
+                            failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                })
+            });
+            problems += failed.into_inner();
+        }
+    }
+
+    match problems {
+        0 => {
+            status!("No problems found");
+            Ok(())
+        }
+        problems => bail!("Found {problems} problems"),
+    }
+}
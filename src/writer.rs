@@ -0,0 +1,328 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Condvar, Mutex, OnceLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{output::OutputFile, pool::BufferPool};
+
+/// A batch of records, stored end to end in one buffer rather than each in its own.
+#[derive(Debug, Default)]
+pub struct Records {
+    data: Vec<u8>,
+    /// Where each record ends in `data`.
+    ends: Vec<usize>,
+}
+
+impl Records {
+    pub fn push(&mut self, record: &[u8]) {
+        self.data.extend_from_slice(record);
+        self.ends.push(self.data.len());
+    }
+
+    /// Adds every record in `other` to the end of these.
+    pub fn extend(&mut self, other: &Records) {
+        let offset = self.data.len();
+        self.data.extend_from_slice(&other.data);
+        self.ends.extend(other.ends.iter().map(|end| offset + end));
+    }
+
+    /// Adds a record by having `write` append it to the buffer. If it returns `false`,
+    /// whatever it wrote is discarded and no record is added.
+    pub fn push_with(&mut self, write: impl FnOnce(&mut Vec<u8>) -> bool) -> bool {
+        let start = self.data.len();
+        if write(&mut self.data) {
+            self.ends.push(self.data.len());
+            true
+        } else {
+            self.data.truncate(start);
+            false
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    /// Roughly how much memory the records take up.
+    pub fn size(&self) -> u64 {
+        (self.data.len() + self.ends.len() * std::mem::size_of::<usize>()) as u64
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        let starts = std::iter::once(0).chain(self.ends.iter().copied());
+        starts
+            .zip(&self.ends)
+            .map(|(start, &end)| &self.data[start..end])
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.ends.clear();
+    }
+}
+
+/// Limits the memory taken up by matches waiting to be written, so that a query matching
+/// a large part of the input can't run us out of memory.
+///
+/// Matches are either buffered by the searching threads, or queued for the writers. When
+/// the total goes over the budget the searching threads write out what they have early,
+/// and they wait for the writers to catch up while the queued matches alone are over it.
+/// Only waiting on the writers means a searching thread can never be left waiting on
+/// another one's matches.
+pub struct MatchMemory {
+    budget: u64,
+    buffered: AtomicU64,
+    queued: Mutex<u64>,
+    written: Condvar,
+}
+
+impl MatchMemory {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            buffered: AtomicU64::new(0),
+            queued: Mutex::new(0),
+            written: Condvar::new(),
+        }
+    }
+
+    /// Starts counting matches buffered by a searching thread, until it's dropped.
+    pub fn buffered(&self) -> Buffered<'_> {
+        Buffered {
+            memory: self,
+            bytes: 0,
+        }
+    }
+
+    pub fn over_budget(&self) -> bool {
+        let queued = *self.queued.lock().unwrap();
+        self.buffered.load(Ordering::Relaxed) + queued > self.budget
+    }
+
+    /// Waits until the matches queued for the writers fit in the budget again.
+    pub fn wait_for_room(&self) {
+        let queued = self.queued.lock().unwrap();
+        let _queued = self
+            .written
+            .wait_while(queued, |queued| *queued > self.budget)
+            .unwrap();
+    }
+
+    fn queue(&self, bytes: u64) {
+        *self.queued.lock().unwrap() += bytes;
+    }
+
+    fn dequeue(&self, bytes: u64) {
+        *self.queued.lock().unwrap() -= bytes;
+        self.written.notify_all();
+    }
+}
+
+/// Matches buffered by a searching thread, counted against the budget until they're
+/// written or dropped.
+pub struct Buffered<'a> {
+    memory: &'a MatchMemory,
+    bytes: u64,
+}
+
+impl Buffered<'_> {
+    /// Updates how much memory the thread's buffered matches take up.
+    pub fn set(&mut self, bytes: u64) {
+        if bytes > self.bytes {
+            self.memory
+                .buffered
+                .fetch_add(bytes - self.bytes, Ordering::Relaxed);
+        } else {
+            self.memory
+                .buffered
+                .fetch_sub(self.bytes - bytes, Ordering::Relaxed);
+        }
+        self.bytes = bytes;
+    }
+}
+
+impl Drop for Buffered<'_> {
+    fn drop(&mut self) {
+        self.set(0);
+    }
+}
+
+enum Message {
+    Records(Records),
+    /// Flush everything written so far, replying once it's done.
+    Flush(mpsc::Sender<Result<(), String>>),
+}
+
+/// What's shared between the writers and the searching threads handing them matches.
+pub struct WriterShared {
+    /// The number of searching threads, each of which can have a batch of records waiting
+    /// for each writer before they have to wait for it.
+    pub threads: usize,
+    pub memory: MatchMemory,
+    /// Buffers of records, handed back by the writers once they've been written.
+    pub buffers: BufferPool<Records>,
+    /// How often to flush what's been written to the files.
+    pub flush_interval: Option<Duration>,
+    /// How often to sync the files to disk.
+    pub sync_interval: Option<Duration>,
+}
+
+/// Writes an output file on its own thread, so that the searching threads can hand over
+/// their matches and get on with searching, rather than queueing up to write them.
+pub struct MatchWriter {
+    sender: mpsc::SyncSender<Message>,
+    /// The first error writing the file. Nothing more is written after this.
+    error: Arc<OnceLock<String>>,
+    shared: Arc<WriterShared>,
+    thread: JoinHandle<OutputFile>,
+}
+
+/// Writes out the records as they arrive, flushing and syncing the file as often as the
+/// intervals say, until the sender is dropped.
+fn run_writer(
+    file: &mut OutputFile,
+    receiver: mpsc::Receiver<Message>,
+    shared: &WriterShared,
+    error: &OnceLock<String>,
+) {
+    let fail = |file: &OutputFile, e: io::Error| {
+        let _ = error.set(format!("Error writing to {}: {e}", file.path().display()));
+    };
+    let mut last_flush = Instant::now();
+    let mut last_sync = Instant::now();
+    // Whether anything's been written since the last flush or sync.
+    let mut unflushed = false;
+    let mut unsynced = false;
+
+    loop {
+        let next = |interval: Option<Duration>, last: Instant| {
+            interval.map(|interval| interval.saturating_sub(last.elapsed()))
+        };
+        let wait = match (
+            next(shared.flush_interval.filter(|_| unflushed), last_flush),
+            next(shared.sync_interval.filter(|_| unsynced), last_sync),
+        ) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let message = match wait {
+            Some(wait) => match receiver.recv_timeout(wait) {
+                Ok(message) => Some(message),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            },
+            None => match receiver.recv() {
+                Ok(message) => Some(message),
+                Err(_) => break,
+            },
+        };
+
+        match message {
+            Some(Message::Records(mut records)) => {
+                if error.get().is_none() {
+                    for record in records.iter() {
+                        if let Err(e) = file.write_record(record) {
+                            fail(file, e);
+                            break;
+                        }
+                    }
+                    unflushed = true;
+                    unsynced = true;
+                }
+                shared.memory.dequeue(records.size());
+                records.clear();
+                shared.buffers.give(records);
+            }
+            Some(Message::Flush(reply)) => {
+                if error.get().is_none() {
+                    if let Err(e) = file.flush() {
+                        fail(file, e);
+                    }
+                    unflushed = false;
+                }
+                let _ = reply.send(error.get().cloned().map_or(Ok(()), Err));
+            }
+            None => {}
+        }
+
+        if error.get().is_some() {
+            continue;
+        }
+        if unsynced && next(shared.sync_interval, last_sync).is_some_and(|d| d.is_zero()) {
+            if let Err(e) = file.sync() {
+                fail(file, e);
+            }
+            (unflushed, unsynced) = (false, false);
+            last_sync = Instant::now();
+            last_flush = last_sync;
+        } else if unflushed && next(shared.flush_interval, last_flush).is_some_and(|d| d.is_zero())
+        {
+            if let Err(e) = file.flush() {
+                fail(file, e);
+            }
+            unflushed = false;
+            last_flush = Instant::now();
+        }
+    }
+}
+
+impl MatchWriter {
+    pub fn spawn(mut file: OutputFile, shared: Arc<WriterShared>) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(shared.threads);
+        let error = Arc::new(OnceLock::new());
+
+        let thread_error = Arc::clone(&error);
+        let thread_shared = Arc::clone(&shared);
+        let thread = thread::spawn(move || {
+            run_writer(&mut file, receiver, &thread_shared, &thread_error);
+            file
+        });
+
+        Self {
+            sender,
+            error,
+            shared,
+            thread,
+        }
+    }
+
+    /// Queues the records to be written, failing if writing has already failed.
+    pub fn write(&self, records: Records) -> Result<(), String> {
+        if let Some(e) = self.error.get() {
+            return Err(e.clone());
+        }
+        self.shared.memory.queue(records.size());
+        // The thread only stops once we're dropped.
+        let _ = self.sender.send(Message::Records(records));
+        Ok(())
+    }
+
+    /// Waits for everything queued so far to be written out and flushed.
+    pub fn flush(&self) -> Result<(), String> {
+        let (reply, receiver) = mpsc::channel();
+        let _ = self.sender.send(Message::Flush(reply));
+        receiver.recv().unwrap_or(Ok(()))
+    }
+
+    /// Waits for everything queued to be written, and hands back the file to be finished.
+    pub fn close(self) -> Result<OutputFile, String> {
+        drop(self.sender);
+        let file = self
+            .thread
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e));
+        match self.error.get() {
+            Some(e) => Err(e.clone()),
+            None => Ok(file),
+        }
+    }
+}